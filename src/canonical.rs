@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::common::BencodeValue;
+use crate::encoder::encode_to_bytes;
+use crate::fields::FieldError;
+use crate::path::Path;
+
+/// A construct the lenient parser accepted that BEP 3 does not allow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueKind {
+    /// `i03e`
+    IntegerLeadingZero,
+    /// `i-0e`
+    NegativeZero,
+    /// `03:abc`
+    LengthLeadingZero,
+    /// A dictionary key that sorts before the key preceding it
+    UnsortedKey,
+    /// A dictionary key seen before; the last occurrence is kept
+    DuplicateKey,
+    /// Bytes after the root value, which are dropped
+    TrailingData,
+}
+
+impl fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IssueKind::IntegerLeadingZero => "integer has leading zeros",
+            IssueKind::NegativeZero => "negative zero",
+            IssueKind::LengthLeadingZero => "string length has leading zeros",
+            IssueKind::UnsortedKey => "key is not in sorted order",
+            IssueKind::DuplicateKey => "duplicate key",
+            IssueKind::TrailingData => "trailing data after the root value",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    /// Offset of the token the issue was found in
+    pub offset: usize,
+    pub path: Path,
+    pub kind: IssueKind,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_root() {
+            "<root>".to_string()
+        } else {
+            self.path.to_string()
+        };
+        write!(f, "{}: {} at offset {}", path, self.kind, self.offset)
+    }
+}
+
+/// The value read by [`parse_lenient`] and every irregularity found on the way
+#[derive(Debug, PartialEq)]
+pub struct Lenient<'a> {
+    pub value: BencodeValue<'a>,
+    pub issues: Vec<Issue>,
+}
+
+/// Parse bencode that may not be canonical
+///
+/// Input that is broken rather than merely irregular, such as a truncated
+/// string or a non-digit in an integer, is still an error.
+pub fn parse_lenient(input: &[u8]) -> Result<Lenient<'_>, FieldError> {
+    let mut parser = LenientParser {
+        input,
+        pos: 0,
+        issues: Vec::new(),
+    };
+    let value = parser.value(Path::root())?;
+    if parser.pos < input.len() {
+        parser.issue(parser.pos, Path::root(), IssueKind::TrailingData);
+    }
+    Ok(Lenient {
+        value,
+        issues: parser.issues,
+    })
+}
+
+/// Re-encode possibly non-canonical input canonically
+///
+/// Canonicalizing a torrent whose info dictionary had issues changes its
+/// info-hash.
+pub fn canonicalize(input: &[u8]) -> Result<(Vec<u8>, Vec<Issue>), FieldError> {
+    let lenient = parse_lenient(input)?;
+    let output = encode_to_bytes(&lenient.value)
+        .map_err(|e| FieldError::invalid(Path::root(), e.to_string()))?;
+    Ok((output, lenient.issues))
+}
+
+struct LenientParser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    issues: Vec<Issue>,
+}
+
+impl<'a> LenientParser<'a> {
+    fn issue(&mut self, offset: usize, path: Path, kind: IssueKind) {
+        self.issues.push(Issue { offset, path, kind });
+    }
+
+    fn error(&self, path: Path, message: &str) -> FieldError {
+        FieldError::invalid(path, format!("{} at offset {}", message, self.pos))
+    }
+
+    fn value(&mut self, path: Path) -> Result<BencodeValue<'a>, FieldError> {
+        match self.input.get(self.pos) {
+            Some(b'i') => self.integer(path).map(BencodeValue::Integer),
+            Some(b'0'..=b'9') => self.byte_string(path).map(BencodeValue::ByteString),
+            Some(b'l') => {
+                self.pos += 1;
+                let mut list = Vec::new();
+                while self.input.get(self.pos) != Some(&b'e') {
+                    list.push(self.value(path.index(list.len()))?);
+                }
+                self.pos += 1;
+                Ok(BencodeValue::List(list))
+            }
+            Some(b'd') => self.dictionary(path),
+            Some(_) => Err(self.error(path, "expected a value")),
+            None => Err(self.error(path, "unexpected end of input")),
+        }
+    }
+
+    fn dictionary(&mut self, path: Path) -> Result<BencodeValue<'a>, FieldError> {
+        self.pos += 1;
+        let mut dict = BTreeMap::new();
+        let mut previous: Option<&[u8]> = None;
+        while self.input.get(self.pos) != Some(&b'e') {
+            let offset = self.pos;
+            if !matches!(self.input.get(self.pos), Some(b'0'..=b'9')) {
+                return Err(self.error(path, "expected a dictionary key"));
+            }
+            let key = self.byte_string(path.clone())?;
+            if dict.contains_key(key) {
+                self.issue(offset, path.key(key), IssueKind::DuplicateKey);
+            } else if previous.is_some_and(|previous| key < previous) {
+                self.issue(offset, path.key(key), IssueKind::UnsortedKey);
+            }
+            previous = Some(key);
+            let value = self.value(path.key(key))?;
+            dict.insert(key, value);
+        }
+        self.pos += 1;
+        Ok(BencodeValue::Dictionary(dict))
+    }
+
+    fn digits(&mut self) -> &'a [u8] {
+        let start = self.pos;
+        while matches!(self.input.get(self.pos), Some(b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        &self.input[start..self.pos]
+    }
+
+    fn integer(&mut self, path: Path) -> Result<isize, FieldError> {
+        let offset = self.pos;
+        self.pos += 1;
+        let negative = self.input.get(self.pos) == Some(&b'-');
+        if negative {
+            self.pos += 1;
+        }
+        let digits = self.digits();
+        if digits.is_empty() || self.input.get(self.pos) != Some(&b'e') {
+            return Err(self.error(path, "malformed integer"));
+        }
+        self.pos += 1;
+
+        let significant = match digits.iter().position(|&d| d != b'0') {
+            Some(first) => &digits[first..],
+            None => b"0",
+        };
+        if significant.len() < digits.len() {
+            self.issue(offset, path.clone(), IssueKind::IntegerLeadingZero);
+        }
+        if negative && significant == b"0" {
+            self.issue(offset, path, IssueKind::NegativeZero);
+            return Ok(0);
+        }
+
+        // Parsed with the sign so that isize::MIN is accepted
+        let mut text = String::from(if negative { "-" } else { "" });
+        text.push_str(std::str::from_utf8(significant).expect("ASCII digits"));
+        text.parse().map_err(|_| {
+            FieldError::invalid(path, format!("integer out of range at offset {}", offset))
+        })
+    }
+
+    fn byte_string(&mut self, path: Path) -> Result<&'a [u8], FieldError> {
+        let offset = self.pos;
+        let digits = self.digits();
+        if self.input.get(self.pos) != Some(&b':') {
+            return Err(self.error(path, "expected ':' after string length"));
+        }
+        self.pos += 1;
+        if digits.len() > 1 && digits[0] == b'0' {
+            self.issue(offset, path.clone(), IssueKind::LengthLeadingZero);
+        }
+        let length: usize = std::str::from_utf8(digits)
+            .expect("ASCII digits")
+            .parse()
+            .map_err(|_| self.error(path.clone(), "string length out of range"))?;
+        let end = self
+            .pos
+            .checked_add(length)
+            .filter(|&end| end <= self.input.len())
+            .ok_or_else(|| self.error(path, "string runs past the end of input"))?;
+        let bytes = &self.input[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(issues: &[Issue]) -> Vec<(usize, String, IssueKind)> {
+        issues
+            .iter()
+            .map(|i| (i.offset, i.path.to_string(), i.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_canonical_input_is_unchanged() {
+        let input = b"d4:infod6:lengthi-5e4:name1:ae4:listli0e0:ee";
+        let (output, issues) = canonicalize(input).unwrap();
+        assert_eq!(output, input.to_vec());
+        assert!(issues.is_empty());
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let input = b"d1:bi007e1:ai-0e1:b02:xye";
+        let (output, issues) = canonicalize(input).unwrap();
+        assert_eq!(output, b"d1:ai0e1:b2:xye".to_vec());
+        assert_eq!(
+            kinds(&issues),
+            [
+                (4, "b".to_string(), IssueKind::IntegerLeadingZero),
+                (9, "a".to_string(), IssueKind::UnsortedKey),
+                (12, "a".to_string(), IssueKind::NegativeZero),
+                (16, "b".to_string(), IssueKind::DuplicateKey),
+                (19, "b".to_string(), IssueKind::LengthLeadingZero),
+            ]
+        );
+        assert_eq!(
+            issues[1].to_string(),
+            "a: key is not in sorted order at offset 9"
+        );
+
+        let (output, issues) = canonicalize(b"i-00ejunk").unwrap();
+        assert_eq!(output, b"i0e".to_vec());
+        assert_eq!(
+            issues.iter().map(|i| i.kind).collect::<Vec<_>>(),
+            [
+                IssueKind::IntegerLeadingZero,
+                IssueKind::NegativeZero,
+                IssueKind::TrailingData
+            ]
+        );
+    }
+
+    #[test]
+    fn test_broken_input_is_rejected() {
+        let error = |input: &[u8]| canonicalize(input).unwrap_err().to_string();
+        assert_eq!(error(b"d1:ai1x"), "a: malformed integer at offset 6");
+        assert_eq!(
+            error(b"l5:abc"),
+            "[0]: string runs past the end of input at offset 3"
+        );
+        assert_eq!(
+            error(b"di1ei2ee"),
+            "<root>: expected a dictionary key at offset 1"
+        );
+        assert_eq!(error(b"li1e"), "[1]: unexpected end of input at offset 4");
+        assert_eq!(
+            error(b"i99999999999999999999e"),
+            "<root>: integer out of range at offset 0"
+        );
+    }
+}
@@ -17,7 +17,7 @@ pub enum BencodeValue<'a> {
 type Res<T, U> = IResult<T, U, VerboseError<T>>;
 
 // Parse a single bencode value (integer, byte string, list, or dictionary)
-pub fn bencode_value(input: &[u8]) -> Res<&[u8], BencodeValue> {
+pub fn bencode_value(input: &[u8]) -> Res<&[u8], BencodeValue<'_>> {
     alt((
         map(integer, BencodeValue::Integer),
         map(byte_string, BencodeValue::ByteString),
@@ -0,0 +1,150 @@
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// Length of a DHT node ID or info-hash
+pub const NODE_ID_LEN: usize = 20;
+/// IPv4 address and port
+pub const PEER_V4_LEN: usize = 6;
+/// IPv6 address and port
+pub const PEER_V6_LEN: usize = 18;
+/// Node ID followed by a compact IPv4 peer (BEP 5)
+pub const NODE_V4_LEN: usize = NODE_ID_LEN + PEER_V4_LEN;
+/// Node ID followed by a compact IPv6 peer (BEP 32)
+pub const NODE_V6_LEN: usize = NODE_ID_LEN + PEER_V6_LEN;
+
+pub type NodeId = [u8; NODE_ID_LEN];
+
+/// A DHT node as found in `nodes` and `nodes6`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CompactNode {
+    pub id: NodeId,
+    pub addr: SocketAddr,
+}
+
+/// Decode one compact peer, 6 bytes for IPv4 or 18 for IPv6
+pub fn decode_peer(bytes: &[u8]) -> Option<SocketAddr> {
+    let (ip, port): (IpAddr, _) = match bytes.len() {
+        PEER_V4_LEN => {
+            let octets: [u8; 4] = bytes[..4].try_into().ok()?;
+            (Ipv4Addr::from(octets).into(), &bytes[4..])
+        }
+        PEER_V6_LEN => {
+            let octets: [u8; 16] = bytes[..16].try_into().ok()?;
+            (Ipv6Addr::from(octets).into(), &bytes[16..])
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, u16::from_be_bytes([port[0], port[1]])))
+}
+
+/// Compact form of a peer address: big-endian IP followed by the port
+pub fn encode_peer(addr: &SocketAddr) -> Vec<u8> {
+    let mut out = match addr.ip() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    out.extend_from_slice(&addr.port().to_be_bytes());
+    out
+}
+
+/// Decode a string of concatenated 6-byte IPv4 peers
+pub fn decode_peers_v4(bytes: &[u8]) -> Option<Vec<SocketAddr>> {
+    decode_peers(bytes, PEER_V4_LEN)
+}
+
+/// Decode a string of concatenated 18-byte IPv6 peers
+pub fn decode_peers_v6(bytes: &[u8]) -> Option<Vec<SocketAddr>> {
+    decode_peers(bytes, PEER_V6_LEN)
+}
+
+fn decode_peers(bytes: &[u8], len: usize) -> Option<Vec<SocketAddr>> {
+    if bytes.len() % len != 0 {
+        return None;
+    }
+    bytes.chunks_exact(len).map(decode_peer).collect()
+}
+
+/// Concatenate compact peers; mixing address families gives an unusable string
+pub fn encode_peers(addrs: &[SocketAddr]) -> Vec<u8> {
+    addrs.iter().flat_map(encode_peer).collect()
+}
+
+/// Decode a BEP 5 `nodes` string of 26-byte entries
+pub fn decode_nodes_v4(bytes: &[u8]) -> Option<Vec<CompactNode>> {
+    decode_nodes(bytes, NODE_V4_LEN)
+}
+
+/// Decode a BEP 32 `nodes6` string of 38-byte entries
+pub fn decode_nodes_v6(bytes: &[u8]) -> Option<Vec<CompactNode>> {
+    decode_nodes(bytes, NODE_V6_LEN)
+}
+
+fn decode_nodes(bytes: &[u8], len: usize) -> Option<Vec<CompactNode>> {
+    if bytes.len() % len != 0 {
+        return None;
+    }
+    bytes
+        .chunks_exact(len)
+        .map(|chunk| {
+            Some(CompactNode {
+                id: chunk[..NODE_ID_LEN].try_into().ok()?,
+                addr: decode_peer(&chunk[NODE_ID_LEN..])?,
+            })
+        })
+        .collect()
+}
+
+pub fn encode_nodes(nodes: &[CompactNode]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for node in nodes {
+        out.extend_from_slice(&node.id);
+        out.extend_from_slice(&encode_peer(&node.addr));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peers() {
+        let v4: SocketAddr = "10.0.0.1:6881".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:51413".parse().unwrap();
+
+        assert_eq!(encode_peer(&v4), vec![10, 0, 0, 1, 0x1a, 0xe1]);
+        assert_eq!(decode_peer(&encode_peer(&v6)), Some(v6));
+
+        let both = encode_peers(&[v4, v4]);
+        assert_eq!(decode_peers_v4(&both), Some(vec![v4, v4]));
+        assert_eq!(decode_peers_v4(&both[..7]), None);
+        assert_eq!(decode_peers_v6(&encode_peers(&[v6])), Some(vec![v6]));
+        assert_eq!(decode_peers_v4(b""), Some(vec![]));
+    }
+
+    #[test]
+    fn test_nodes() {
+        let nodes = vec![
+            CompactNode {
+                id: [1; 20],
+                addr: "192.168.1.2:1234".parse().unwrap(),
+            },
+            CompactNode {
+                id: [2; 20],
+                addr: "127.0.0.1:80".parse().unwrap(),
+            },
+        ];
+        let encoded = encode_nodes(&nodes);
+        assert_eq!(encoded.len(), 52);
+        assert_eq!(decode_nodes_v4(&encoded), Some(nodes));
+        assert_eq!(decode_nodes_v4(&encoded[..30]), None);
+
+        let node6 = CompactNode {
+            id: [3; 20],
+            addr: "[::1]:9".parse().unwrap(),
+        };
+        let encoded = encode_nodes(&[node6]);
+        assert_eq!(encoded.len(), NODE_V6_LEN);
+        assert_eq!(decode_nodes_v6(&encoded), Some(vec![node6]));
+    }
+}
@@ -0,0 +1,794 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::fields::Extra;
+use crate::file_tree::{FileNode, FileTree};
+use crate::infohash::sha1;
+use crate::merkle::{file_root, piece_layer, BlockHasher, Hash, BLOCK_SIZE};
+use crate::torrent::{FileEntry, FileLayout, Info, Metainfo, MIN_V2_PIECE_LENGTH};
+
+/// Largest piece length picked automatically
+pub const MAX_AUTO_PIECE_LENGTH: u64 = 16 * 1024 * 1024;
+
+/// Number of pieces the automatic piece length aims to stay under
+const TARGET_PIECE_COUNT: u64 = 2000;
+
+/// Which hashes the created torrent carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TorrentVersion {
+    V1,
+    V2,
+    /// Both v1 pieces (with BEP 47 padding files) and a v2 file tree
+    Hybrid,
+}
+
+/// What to do with symbolic links met while walking a directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    Skip,
+    /// Follow links to their targets, skipping links that lead back into a parent
+    Follow,
+}
+
+/// Error type for torrent creation
+#[derive(Debug)]
+pub enum CreateError {
+    IoError(io::Error),
+    NoFiles,
+    InvalidPieceLength(u64),
+    InvalidPath(PathBuf),
+}
+
+impl From<io::Error> for CreateError {
+    fn from(error: io::Error) -> Self {
+        CreateError::IoError(error)
+    }
+}
+
+impl std::fmt::Display for CreateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CreateError::IoError(e) => write!(f, "IO error: {}", e),
+            CreateError::NoFiles => write!(f, "No files to add"),
+            CreateError::InvalidPieceLength(l) => write!(
+                f,
+                "Invalid piece length {}: must be a power of two of at least 16 KiB",
+                l
+            ),
+            CreateError::InvalidPath(p) => write!(f, "Path is not valid UTF-8: {}", p.display()),
+        }
+    }
+}
+
+impl std::error::Error for CreateError {}
+
+/// Builds a `Metainfo` from a file or directory on disk
+#[derive(Debug, Clone)]
+pub struct TorrentBuilder {
+    root: PathBuf,
+    name: Option<String>,
+    piece_length: Option<u64>,
+    version: TorrentVersion,
+    exclude: Vec<String>,
+    symlinks: SymlinkPolicy,
+    threads: usize,
+    announce: Option<String>,
+    announce_list: Vec<Vec<String>>,
+    comment: Option<String>,
+    created_by: Option<String>,
+    creation_date: Option<i64>,
+    private: Option<bool>,
+    source: Option<String>,
+}
+
+// A file found on disk, with its path inside the torrent
+struct SourceFile {
+    path: Vec<String>,
+    disk_path: PathBuf,
+    length: u64,
+}
+
+// A run of the v1 piece stream: a file on disk, or BEP 47 padding
+enum Segment {
+    File(usize),
+    Padding(u64),
+}
+
+impl TorrentBuilder {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        TorrentBuilder {
+            root: root.into(),
+            name: None,
+            piece_length: None,
+            version: TorrentVersion::V1,
+            exclude: Vec::new(),
+            symlinks: SymlinkPolicy::Skip,
+            threads: 1,
+            announce: None,
+            announce_list: Vec::new(),
+            comment: None,
+            created_by: None,
+            creation_date: None,
+            private: None,
+            source: None,
+        }
+    }
+
+    /// Torrent name, defaulting to the file or directory name
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Piece length, which must be a power of two of at least 16 KiB
+    ///
+    /// When not set, the smallest power of two keeping the torrent under about
+    /// 2000 pieces is picked, capped at 16 MiB.
+    pub fn piece_length(mut self, piece_length: u64) -> Self {
+        self.piece_length = Some(piece_length);
+        self
+    }
+
+    pub fn version(mut self, version: TorrentVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Skip files and directories matching a glob pattern (`*` and `?`)
+    ///
+    /// Patterns containing `/` are matched against the path relative to the
+    /// root, other patterns against each file or directory name.
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    /// Number of threads used to hash pieces
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    pub fn announce(mut self, url: impl Into<String>) -> Self {
+        self.announce = Some(url.into());
+        self
+    }
+
+    pub fn announce_list(mut self, tiers: Vec<Vec<String>>) -> Self {
+        self.announce_list = tiers;
+        self
+    }
+
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    pub fn created_by(mut self, created_by: impl Into<String>) -> Self {
+        self.created_by = Some(created_by.into());
+        self
+    }
+
+    pub fn creation_date(mut self, timestamp: i64) -> Self {
+        self.creation_date = Some(timestamp);
+        self
+    }
+
+    pub fn private(mut self, private: bool) -> Self {
+        self.private = Some(private);
+        self
+    }
+
+    pub fn source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// Walk the files, hash them and assemble the metainfo
+    pub fn build(&self) -> Result<Metainfo, CreateError> {
+        let name = match &self.name {
+            Some(name) => name.clone(),
+            None => file_name(&self.root)?,
+        };
+
+        let root_is_file = fs::metadata(&self.root)?.is_file();
+        let files = if root_is_file {
+            vec![SourceFile {
+                path: vec![name.clone()],
+                disk_path: self.root.clone(),
+                length: fs::metadata(&self.root)?.len(),
+            }]
+        } else {
+            let mut files = Vec::new();
+            let mut visited = HashSet::new();
+            visited.insert(fs::canonicalize(&self.root)?);
+            self.walk(&self.root, &mut Vec::new(), &mut visited, &mut files)?;
+            files
+        };
+        if files.is_empty() {
+            return Err(CreateError::NoFiles);
+        }
+
+        let total: u64 = files.iter().map(|f| f.length).sum();
+        let piece_length = match self.piece_length {
+            Some(l) if l.is_power_of_two() && l >= MIN_V2_PIECE_LENGTH => l,
+            Some(l) => return Err(CreateError::InvalidPieceLength(l)),
+            None => auto_piece_length(total),
+        };
+
+        let with_v1 = self.version != TorrentVersion::V2;
+        let with_v2 = self.version != TorrentVersion::V1;
+
+        let mut info = Info {
+            name: name.into_bytes(),
+            piece_length,
+            pieces: None,
+            layout: None,
+            meta_version: None,
+            file_tree: None,
+            private: self.private,
+            source: self.source.clone(),
+            extra: Extra::new(),
+        };
+        let mut piece_layers = BTreeMap::new();
+
+        if with_v1 {
+            let segments =
+                v1_segments(&files, piece_length, self.version == TorrentVersion::Hybrid);
+            info.pieces = Some(hash_v1_pieces(
+                &files,
+                &segments,
+                piece_length,
+                self.threads,
+            )?);
+            info.layout = Some(if root_is_file {
+                FileLayout::Single {
+                    length: files[0].length,
+                }
+            } else {
+                v1_layout(&files, &segments)
+            });
+        }
+
+        if with_v2 {
+            let mut tree = FileTree::default();
+            let hashed = run_jobs(files.len(), self.threads, |i| {
+                hash_v2_file(&files[i], piece_length)
+            })?;
+            for (file, (root, layer)) in files.iter().zip(hashed) {
+                if let (Some(root), Some(layer)) = (root, layer) {
+                    piece_layers.insert(root.to_vec(), layer);
+                }
+                let node = FileNode {
+                    length: file.length,
+                    pieces_root: root,
+                    extra: Extra::new(),
+                };
+                if !tree.insert(&file.path, node) {
+                    return Err(CreateError::InvalidPath(file.disk_path.clone()));
+                }
+            }
+            info.meta_version = Some(2);
+            info.file_tree = Some(tree);
+        }
+
+        Ok(Metainfo {
+            announce: self.announce.clone(),
+            announce_list: self.announce_list.clone(),
+            comment: self.comment.clone().map(String::into_bytes),
+            created_by: self.created_by.clone().map(String::into_bytes),
+            creation_date: self.creation_date,
+            encoding: None,
+            info,
+            piece_layers,
+            extra: Extra::new(),
+        })
+    }
+
+    fn is_excluded(&self, relative: &[String]) -> bool {
+        let joined = relative.join("/");
+        let name = relative.last().map(String::as_str).unwrap_or("");
+        self.exclude.iter().any(|pattern| {
+            if pattern.contains('/') {
+                glob_match(pattern.as_bytes(), joined.as_bytes())
+            } else {
+                glob_match(pattern.as_bytes(), name.as_bytes())
+            }
+        })
+    }
+
+    fn walk(
+        &self,
+        dir: &FsPath,
+        prefix: &mut Vec<String>,
+        visited: &mut HashSet<PathBuf>,
+        out: &mut Vec<SourceFile>,
+    ) -> Result<(), CreateError> {
+        let mut entries = fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+
+        for disk_path in entries {
+            prefix.push(file_name(&disk_path)?);
+
+            if !self.is_excluded(prefix) {
+                let mut metadata = fs::symlink_metadata(&disk_path)?;
+                let mut follow = true;
+                if metadata.file_type().is_symlink() {
+                    follow = self.symlinks == SymlinkPolicy::Follow;
+                    if follow {
+                        metadata = fs::metadata(&disk_path)?;
+                    }
+                }
+
+                if follow && metadata.is_dir() {
+                    let canonical = fs::canonicalize(&disk_path)?;
+                    if visited.insert(canonical.clone()) {
+                        self.walk(&disk_path, prefix, visited, out)?;
+                        visited.remove(&canonical);
+                    }
+                } else if follow && metadata.is_file() {
+                    out.push(SourceFile {
+                        path: prefix.clone(),
+                        disk_path,
+                        length: metadata.len(),
+                    });
+                }
+            }
+
+            prefix.pop();
+        }
+        Ok(())
+    }
+}
+
+fn file_name(path: &FsPath) -> Result<String, CreateError> {
+    // Paths like `.` have no name of their own, so fall back to the resolved one
+    let resolved;
+    let name = match path.file_name() {
+        Some(name) => name,
+        None => {
+            resolved = fs::canonicalize(path)?;
+            resolved.file_name().unwrap_or_default()
+        }
+    };
+    match name.to_str() {
+        Some(name) if !name.is_empty() => Ok(name.to_string()),
+        _ => Err(CreateError::InvalidPath(path.to_path_buf())),
+    }
+}
+
+/// The smallest power of two of at least 16 KiB giving at most 2000 pieces
+pub fn auto_piece_length(total_length: u64) -> u64 {
+    let mut piece_length = MIN_V2_PIECE_LENGTH;
+    while piece_length < MAX_AUTO_PIECE_LENGTH
+        && total_length.div_ceil(piece_length) > TARGET_PIECE_COUNT
+    {
+        piece_length *= 2;
+    }
+    piece_length
+}
+
+// Match a glob with `*` and `?`, where neither crosses a `/`
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            glob_match(&pattern[1..], text)
+                || (text.first().is_some_and(|&c| c != b'/') && glob_match(pattern, &text[1..]))
+        }
+        (Some(b'?'), Some(&c)) if c != b'/' => glob_match(&pattern[1..], &text[1..]),
+        (Some(p), Some(c)) if p == c => glob_match(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+// Lay out the v1 stream, aligning each file to a piece boundary for hybrids
+fn v1_segments(files: &[SourceFile], piece_length: u64, pad: bool) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    for (i, file) in files.iter().enumerate() {
+        segments.push(Segment::File(i));
+        let remainder = file.length % piece_length;
+        if pad && i + 1 < files.len() && remainder != 0 {
+            segments.push(Segment::Padding(piece_length - remainder));
+        }
+    }
+    segments
+}
+
+fn v1_layout(files: &[SourceFile], segments: &[Segment]) -> FileLayout {
+    let entries = segments
+        .iter()
+        .map(|segment| match segment {
+            Segment::File(i) => FileEntry {
+                length: files[*i].length,
+                path: files[*i].path.clone(),
+                attr: None,
+                extra: Extra::new(),
+            },
+            Segment::Padding(length) => FileEntry {
+                length: *length,
+                path: vec![".pad".to_string(), length.to_string()],
+                attr: Some("p".to_string()),
+                extra: Extra::new(),
+            },
+        })
+        .collect();
+    FileLayout::Multiple { files: entries }
+}
+
+// Read `buf.len()` bytes of the v1 stream starting at `offset`
+fn read_stream(
+    files: &[SourceFile],
+    segments: &[Segment],
+    mut offset: u64,
+    buf: &mut [u8],
+) -> io::Result<()> {
+    let mut filled = 0;
+    for segment in segments {
+        if filled == buf.len() {
+            break;
+        }
+        let length = match segment {
+            Segment::File(i) => files[*i].length,
+            Segment::Padding(length) => *length,
+        };
+        if offset >= length {
+            offset -= length;
+            continue;
+        }
+
+        let take = ((length - offset) as usize).min(buf.len() - filled);
+        let target = &mut buf[filled..filled + take];
+        match segment {
+            Segment::File(i) => {
+                let mut file = File::open(&files[*i].disk_path)?;
+                file.seek(SeekFrom::Start(offset))?;
+                file.read_exact(target)?;
+            }
+            Segment::Padding(_) => target.fill(0),
+        }
+        filled += take;
+        offset = 0;
+    }
+
+    if filled < buf.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "file shrank while hashing",
+        ));
+    }
+    Ok(())
+}
+
+fn hash_v1_pieces(
+    files: &[SourceFile],
+    segments: &[Segment],
+    piece_length: u64,
+    threads: usize,
+) -> io::Result<Vec<u8>> {
+    let total: u64 = segments
+        .iter()
+        .map(|segment| match segment {
+            Segment::File(i) => files[*i].length,
+            Segment::Padding(length) => *length,
+        })
+        .sum();
+    let count = total.div_ceil(piece_length) as usize;
+
+    let hashes = run_jobs(count, threads, |index| {
+        let start = index as u64 * piece_length;
+        let mut buf = vec![0; piece_length.min(total - start) as usize];
+        read_stream(files, segments, start, &mut buf)?;
+        Ok(sha1(&buf))
+    })?;
+    Ok(hashes.concat())
+}
+
+// A file's pieces root and, for files longer than a piece, its piece layer
+type V2Hashes = (Option<Hash>, Option<Vec<u8>>);
+
+fn hash_v2_file(file: &SourceFile, piece_length: u64) -> io::Result<V2Hashes> {
+    if file.length == 0 {
+        return Ok((None, None));
+    }
+
+    let mut hasher = BlockHasher::new();
+    let mut reader = File::open(&file.disk_path)?;
+    let mut buf = vec![0; BLOCK_SIZE as usize * 16];
+    let mut remaining = file.length;
+    while remaining > 0 {
+        let take = (buf.len() as u64).min(remaining) as usize;
+        reader.read_exact(&mut buf[..take])?;
+        hasher.update(&buf[..take]);
+        remaining -= take as u64;
+    }
+
+    let blocks = hasher.finish();
+    let layer = if file.length > piece_length {
+        Some(piece_layer(&blocks, piece_length).concat())
+    } else {
+        None
+    };
+    Ok((Some(file_root(&blocks)), layer))
+}
+
+// Run `count` independent jobs on up to `threads` threads, keeping results in order
+fn run_jobs<T, F>(count: usize, threads: usize, job: F) -> io::Result<Vec<T>>
+where
+    T: Send,
+    F: Fn(usize) -> io::Result<T> + Sync,
+{
+    if threads <= 1 || count <= 1 {
+        return (0..count).map(job).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<io::Result<T>>>> = Mutex::new((0..count).map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..threads.min(count) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= count {
+                    break;
+                }
+                let result = job(index);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every job runs"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::ToBencode;
+    use crate::infohash::InfoHash;
+    use crate::merkle::hash_block;
+
+    const PIECE: u64 = MIN_V2_PIECE_LENGTH;
+
+    fn write(dir: &FsPath, relative: &str, data: &[u8]) {
+        let path = dir.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+
+    fn data(length: usize, seed: u8) -> Vec<u8> {
+        (0..length)
+            .map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed))
+            .collect()
+    }
+
+    fn sample_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "content/b.bin", &data(PIECE as usize + 100, 1));
+        write(dir.path(), "content/a/x.txt", &data(10, 2));
+        write(dir.path(), "content/a/empty", b"");
+        write(dir.path(), "content/skip.tmp", b"ignored");
+        dir
+    }
+
+    fn round_trip(metainfo: &Metainfo) -> Metainfo {
+        let encoded = metainfo.to_bencode().unwrap();
+        let parsed = Metainfo::from_bytes(&encoded).unwrap();
+        assert_eq!(&parsed, metainfo);
+        parsed
+    }
+
+    #[test]
+    fn test_single_file_v1() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = data(PIECE as usize * 2 + 5, 0);
+        write(dir.path(), "file.bin", &content);
+
+        let metainfo = TorrentBuilder::new(dir.path().join("file.bin"))
+            .announce("http://tracker.example/announce")
+            .build()
+            .unwrap();
+        round_trip(&metainfo);
+
+        let info = &metainfo.info;
+        assert_eq!(info.name, b"file.bin");
+        assert_eq!(info.piece_length, PIECE);
+        assert_eq!(
+            info.layout,
+            Some(FileLayout::Single {
+                length: content.len() as u64
+            })
+        );
+        assert_eq!(info.piece_count(), 3);
+        for (i, chunk) in content.chunks(PIECE as usize).enumerate() {
+            assert_eq!(info.piece_hash(i), Some(&sha1(chunk)[..]));
+        }
+        assert!(!info.has_v2());
+    }
+
+    #[test]
+    fn test_directory_v1_with_exclusions() {
+        let dir = sample_dir();
+        let metainfo = TorrentBuilder::new(dir.path().join("content"))
+            .exclude("*.tmp")
+            .build()
+            .unwrap();
+        round_trip(&metainfo);
+
+        let files = match &metainfo.info.layout {
+            Some(FileLayout::Multiple { files }) => files,
+            _ => panic!("Expected multiple files"),
+        };
+        let paths: Vec<String> = files.iter().map(|f| f.path.join("/")).collect();
+        assert_eq!(paths, vec!["a/empty", "a/x.txt", "b.bin"]);
+        assert_eq!(metainfo.info.name, b"content");
+
+        let mut stream = data(10, 2);
+        stream.extend(data(PIECE as usize + 100, 1));
+        assert_eq!(
+            metainfo.info.piece_hash(0),
+            Some(&sha1(&stream[..PIECE as usize])[..])
+        );
+        assert_eq!(
+            metainfo.info.piece_hash(1),
+            Some(&sha1(&stream[PIECE as usize..])[..])
+        );
+
+        let excluded = TorrentBuilder::new(dir.path().join("content"))
+            .exclude("a/*")
+            .exclude("*.tmp")
+            .build()
+            .unwrap();
+        assert_eq!(excluded.info.total_length(), PIECE + 100);
+    }
+
+    #[test]
+    fn test_v2() {
+        let dir = sample_dir();
+        let metainfo = TorrentBuilder::new(dir.path().join("content"))
+            .exclude("*.tmp")
+            .version(TorrentVersion::V2)
+            .build()
+            .unwrap();
+        round_trip(&metainfo);
+
+        let info = &metainfo.info;
+        assert!(info.has_v2() && !info.has_v1());
+        let tree = info.file_tree.as_ref().unwrap();
+        let files = tree.files();
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0].1.pieces_root, None);
+        assert_eq!(files[1].1.pieces_root, Some(hash_block(&data(10, 2))));
+
+        // Only the file longer than a piece gets a piece layer
+        let big_root = files[2].1.pieces_root.unwrap();
+        assert_eq!(metainfo.piece_layers.len(), 1);
+        assert_eq!(metainfo.piece_layers[&big_root.to_vec()].len(), 64);
+
+        let hash = InfoHash::from_torrent(&metainfo.to_bencode().unwrap()).unwrap();
+        assert!(hash.v2.is_some() && hash.v1.is_none());
+    }
+
+    #[test]
+    fn test_hybrid_pads_files() {
+        let dir = sample_dir();
+        let metainfo = TorrentBuilder::new(dir.path().join("content"))
+            .exclude("*.tmp")
+            .version(TorrentVersion::Hybrid)
+            .build()
+            .unwrap();
+        round_trip(&metainfo);
+
+        let files = match &metainfo.info.layout {
+            Some(FileLayout::Multiple { files }) => files,
+            _ => panic!("Expected multiple files"),
+        };
+        let paths: Vec<String> = files.iter().map(|f| f.path.join("/")).collect();
+        assert_eq!(paths, vec!["a/empty", "a/x.txt", ".pad/16374", "b.bin"]);
+        assert!(files[2].is_padding());
+
+        let mut first_piece = data(10, 2);
+        first_piece.resize(PIECE as usize, 0);
+        assert_eq!(metainfo.info.piece_hash(0), Some(&sha1(&first_piece)[..]));
+        assert_eq!(metainfo.info.piece_count(), 3);
+
+        let hash = InfoHash::from_torrent(&metainfo.to_bencode().unwrap()).unwrap();
+        assert!(hash.is_hybrid());
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..5u8 {
+            write(
+                dir.path(),
+                &format!("f{}", i),
+                &data(PIECE as usize * i as usize + 7, i),
+            );
+        }
+
+        for version in [
+            TorrentVersion::V1,
+            TorrentVersion::V2,
+            TorrentVersion::Hybrid,
+        ] {
+            let builder = TorrentBuilder::new(dir.path())
+                .name("files")
+                .version(version);
+            let sequential = builder.clone().build().unwrap();
+            let parallel = builder.threads(4).build().unwrap();
+            assert_eq!(sequential, parallel);
+        }
+    }
+
+    #[test]
+    fn test_piece_length_choice() {
+        assert_eq!(auto_piece_length(0), 16 * 1024);
+        assert_eq!(auto_piece_length(2000 * 16 * 1024), 16 * 1024);
+        assert_eq!(auto_piece_length(2000 * 16 * 1024 + 1), 32 * 1024);
+        assert_eq!(auto_piece_length(u64::MAX / 2), MAX_AUTO_PIECE_LENGTH);
+
+        let dir = sample_dir();
+        let error = TorrentBuilder::new(dir.path())
+            .piece_length(20000)
+            .build()
+            .unwrap_err();
+        assert!(matches!(error, CreateError::InvalidPieceLength(20000)));
+    }
+
+    #[test]
+    fn test_empty_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let error = TorrentBuilder::new(dir.path()).build().unwrap_err();
+        assert!(matches!(error, CreateError::NoFiles));
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*.tmp", b"a.tmp"));
+        assert!(glob_match(b"?.tmp", b"a.tmp"));
+        assert!(!glob_match(b"*.tmp", b"a.tmpx"));
+        assert!(glob_match(b"a/*", b"a/x"));
+        assert!(!glob_match(b"a/*", b"a/x/y"));
+        assert!(glob_match(b"*", b""));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_policy() {
+        let dir = sample_dir();
+        let content = dir.path().join("content");
+        std::os::unix::fs::symlink(content.join("a/x.txt"), content.join("link")).unwrap();
+        std::os::unix::fs::symlink(&content, content.join("loop")).unwrap();
+
+        let skipped = TorrentBuilder::new(&content)
+            .exclude("*.tmp")
+            .build()
+            .unwrap();
+        assert_eq!(skipped.info.total_length(), PIECE + 110);
+
+        let followed = TorrentBuilder::new(&content)
+            .exclude("*.tmp")
+            .symlinks(SymlinkPolicy::Follow)
+            .build()
+            .unwrap();
+        assert_eq!(followed.info.total_length(), PIECE + 120);
+    }
+}
@@ -0,0 +1,357 @@
+use std::fmt;
+
+use crate::common::BencodeValue;
+use crate::encoder::{encode_to_bytes, EncodingError};
+use crate::fields::parse_document;
+#[cfg(feature = "hash")]
+use crate::infohash::sha1;
+
+/// Largest encoded `v` a node will store (BEP 44)
+pub const MAX_VALUE_LEN: usize = 1000;
+/// Largest `salt` of a mutable item
+pub const MAX_SALT_LEN: usize = 64;
+pub const PUBLIC_KEY_LEN: usize = 32;
+pub const SIGNATURE_LEN: usize = 64;
+
+/// Error type for BEP 44 items
+///
+/// Each variant maps onto the KRPC error code a storing node replies with.
+#[derive(Debug)]
+pub enum ItemError {
+    ValueTooLarge(usize),
+    SaltTooLarge(usize),
+    InvalidSignature,
+    /// `seq` is not newer than the `current` one a node already holds
+    StaleSequence {
+        current: i64,
+        seq: i64,
+    },
+    InvalidValue(String),
+    EncodingError(EncodingError),
+}
+
+impl ItemError {
+    /// KRPC error code for this error, as listed in BEP 44
+    pub fn krpc_code(&self) -> i64 {
+        match self {
+            ItemError::ValueTooLarge(_) => 205,
+            ItemError::InvalidSignature => 206,
+            ItemError::SaltTooLarge(_) => 207,
+            ItemError::StaleSequence { .. } => 302,
+            ItemError::InvalidValue(_) | ItemError::EncodingError(_) => 203,
+        }
+    }
+}
+
+impl From<EncodingError> for ItemError {
+    fn from(error: EncodingError) -> Self {
+        ItemError::EncodingError(error)
+    }
+}
+
+impl fmt::Display for ItemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ItemError::ValueTooLarge(len) => write!(
+                f,
+                "Value is {} bytes encoded, more than {}",
+                len, MAX_VALUE_LEN
+            ),
+            ItemError::SaltTooLarge(len) => {
+                write!(f, "Salt is {} bytes, more than {}", len, MAX_SALT_LEN)
+            }
+            ItemError::InvalidSignature => write!(f, "Invalid signature"),
+            ItemError::StaleSequence { current, seq } => {
+                write!(f, "Sequence number {} is not newer than {}", seq, current)
+            }
+            ItemError::InvalidValue(e) => write!(f, "Invalid value: {}", e),
+            ItemError::EncodingError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ItemError {}
+
+fn check_value(encoded: &[u8]) -> Result<(), ItemError> {
+    if encoded.len() > MAX_VALUE_LEN {
+        return Err(ItemError::ValueTooLarge(encoded.len()));
+    }
+    parse_document(encoded)
+        .map(|_| ())
+        .map_err(|e| ItemError::InvalidValue(e.to_string()))
+}
+
+fn check_salt(salt: &[u8]) -> Result<(), ItemError> {
+    if salt.len() > MAX_SALT_LEN {
+        return Err(ItemError::SaltTooLarge(salt.len()));
+    }
+    Ok(())
+}
+
+/// An immutable item, addressed by the SHA-1 of its encoded value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImmutableItem {
+    encoded: Vec<u8>,
+}
+
+impl ImmutableItem {
+    pub fn new(value: &BencodeValue) -> Result<Self, ItemError> {
+        ImmutableItem::from_encoded(encode_to_bytes(value)?)
+    }
+
+    /// Wrap an already encoded value, checking that it parses and fits
+    pub fn from_encoded(encoded: Vec<u8>) -> Result<Self, ItemError> {
+        check_value(&encoded)?;
+        Ok(ImmutableItem { encoded })
+    }
+
+    /// The bencoded `v`
+    pub fn encoded(&self) -> &[u8] {
+        &self.encoded
+    }
+
+    pub fn value(&self) -> BencodeValue<'_> {
+        parse_document(&self.encoded).expect("checked on construction")
+    }
+
+    #[cfg(feature = "hash")]
+    pub fn target(&self) -> [u8; 20] {
+        sha1(&self.encoded)
+    }
+}
+
+/// A mutable item, signed by the owner of `public_key`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MutableItem {
+    pub public_key: [u8; PUBLIC_KEY_LEN],
+    /// Empty when the item has no salt
+    pub salt: Vec<u8>,
+    pub seq: i64,
+    encoded: Vec<u8>,
+    pub signature: [u8; SIGNATURE_LEN],
+}
+
+/// The exact bytes a mutable item's signature covers
+///
+/// This is the bencoding of `salt` (if any), `seq` and `v` as if they were
+/// dictionary entries, without the surrounding `d` and `e`.
+pub fn signing_buffer(salt: &[u8], seq: i64, encoded_value: &[u8]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    if !salt.is_empty() {
+        buffer.extend_from_slice(format!("4:salt{}:", salt.len()).as_bytes());
+        buffer.extend_from_slice(salt);
+    }
+    buffer.extend_from_slice(format!("3:seqi{}e1:v", seq).as_bytes());
+    buffer.extend_from_slice(encoded_value);
+    buffer
+}
+
+/// DHT target of a mutable item: SHA-1 of the public key followed by the salt
+#[cfg(feature = "hash")]
+pub fn mutable_target(public_key: &[u8; PUBLIC_KEY_LEN], salt: &[u8]) -> [u8; 20] {
+    let mut input = public_key.to_vec();
+    input.extend_from_slice(salt);
+    sha1(&input)
+}
+
+impl MutableItem {
+    /// Assemble an item received from the network, without checking the signature
+    pub fn from_parts(
+        public_key: [u8; PUBLIC_KEY_LEN],
+        salt: Vec<u8>,
+        seq: i64,
+        encoded: Vec<u8>,
+        signature: [u8; SIGNATURE_LEN],
+    ) -> Result<Self, ItemError> {
+        check_value(&encoded)?;
+        check_salt(&salt)?;
+        Ok(MutableItem {
+            public_key,
+            salt,
+            seq,
+            encoded,
+            signature,
+        })
+    }
+
+    /// Create and sign an item
+    #[cfg(feature = "sign")]
+    pub fn sign(
+        key: &ed25519_dalek::SigningKey,
+        salt: &[u8],
+        seq: i64,
+        value: &BencodeValue,
+    ) -> Result<Self, ItemError> {
+        use ed25519_dalek::Signer;
+
+        let encoded = encode_to_bytes(value)?;
+        check_value(&encoded)?;
+        check_salt(salt)?;
+        let signature = key.sign(&signing_buffer(salt, seq, &encoded));
+        Ok(MutableItem {
+            public_key: key.verifying_key().to_bytes(),
+            salt: salt.to_vec(),
+            seq,
+            encoded,
+            signature: signature.to_bytes(),
+        })
+    }
+
+    /// Check the signature against the public key
+    #[cfg(feature = "sign")]
+    pub fn verify(&self) -> Result<(), ItemError> {
+        let key = ed25519_dalek::VerifyingKey::from_bytes(&self.public_key)
+            .map_err(|_| ItemError::InvalidSignature)?;
+        let signature = ed25519_dalek::Signature::from_bytes(&self.signature);
+        key.verify_strict(&self.signing_buffer(), &signature)
+            .map_err(|_| ItemError::InvalidSignature)
+    }
+
+    pub fn signing_buffer(&self) -> Vec<u8> {
+        signing_buffer(&self.salt, self.seq, &self.encoded)
+    }
+
+    /// The bencoded `v`
+    pub fn encoded(&self) -> &[u8] {
+        &self.encoded
+    }
+
+    pub fn value(&self) -> BencodeValue<'_> {
+        parse_document(&self.encoded).expect("checked on construction")
+    }
+
+    #[cfg(feature = "hash")]
+    pub fn target(&self) -> [u8; 20] {
+        mutable_target(&self.public_key, &self.salt)
+    }
+}
+
+/// A BEP 44 item as carried by `put` queries and `get` responses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    Immutable(ImmutableItem),
+    Mutable(MutableItem),
+}
+
+impl Item {
+    pub fn encoded(&self) -> &[u8] {
+        match self {
+            Item::Immutable(item) => item.encoded(),
+            Item::Mutable(item) => item.encoded(),
+        }
+    }
+
+    pub fn value(&self) -> BencodeValue<'_> {
+        match self {
+            Item::Immutable(item) => item.value(),
+            Item::Mutable(item) => item.value(),
+        }
+    }
+
+    #[cfg(feature = "hash")]
+    pub fn target(&self) -> [u8; 20] {
+        match self {
+            Item::Immutable(item) => item.target(),
+            Item::Mutable(item) => item.target(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "hash"))]
+mod tests {
+    use super::*;
+    use crate::encoding::to_hex;
+
+    #[test]
+    fn test_immutable_item() {
+        // Example from BEP 44
+        let item = ImmutableItem::new(&BencodeValue::ByteString(b"Hello World!")).unwrap();
+        assert_eq!(item.encoded(), b"12:Hello World!");
+        assert_eq!(
+            to_hex(&item.target()),
+            "e5f96f6f38320f0f33959cb4d3d656452117aadb"
+        );
+        assert_eq!(item.value(), BencodeValue::ByteString(b"Hello World!"));
+    }
+
+    #[test]
+    fn test_value_limit() {
+        let data = [b'x'; 997];
+        let item = ImmutableItem::new(&BencodeValue::ByteString(&data[..996])).unwrap();
+        assert_eq!(item.encoded().len(), MAX_VALUE_LEN);
+
+        let error = ImmutableItem::new(&BencodeValue::ByteString(&data)).unwrap_err();
+        assert!(matches!(error, ItemError::ValueTooLarge(1001)));
+        assert_eq!(error.krpc_code(), 205);
+
+        let error = ImmutableItem::from_encoded(b"i1".to_vec()).unwrap_err();
+        assert!(matches!(error, ItemError::InvalidValue(_)));
+    }
+
+    #[test]
+    fn test_signing_buffer() {
+        // Examples from BEP 44
+        assert_eq!(
+            signing_buffer(b"", 1, b"12:Hello World!"),
+            b"3:seqi1e1:v12:Hello World!"
+        );
+        assert_eq!(
+            signing_buffer(b"foobar", 1, b"12:Hello World!"),
+            b"4:salt6:foobar3:seqi1e1:v12:Hello World!"
+        );
+    }
+
+    #[cfg(feature = "sign")]
+    #[test]
+    fn test_bep44_test_vectors() {
+        use crate::encoding::decode_hex;
+        use std::convert::TryInto;
+
+        let secret = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+        let value = BencodeValue::ByteString(b"Hello World!");
+
+        let item = MutableItem::sign(&secret, b"", 1, &value).unwrap();
+        item.verify().unwrap();
+        assert_eq!(item.target(), sha1(&secret.verifying_key().to_bytes()));
+
+        let salted = MutableItem::sign(&secret, b"foobar", 1, &value).unwrap();
+        salted.verify().unwrap();
+        assert_ne!(salted.target(), item.target());
+
+        // The public key and signature from the BEP 44 mutable example
+        let public_key =
+            decode_hex("77ff84905a91936367c01360803104f92432fcd904a43511876df5cdf3e7e548").unwrap();
+        let signature = decode_hex("305ac8aeb6c9c151fa120f120ea2cfb923564e11552d06a5d856091e5e853cff1260d3f39e4999684aa92eb73ffd136e6f4f3ecbfda0ce53a1608ecd7ae21f01").unwrap();
+        let example = MutableItem::from_parts(
+            public_key.try_into().unwrap(),
+            Vec::new(),
+            1,
+            b"12:Hello World!".to_vec(),
+            signature.try_into().unwrap(),
+        )
+        .unwrap();
+        example.verify().unwrap();
+        assert_eq!(
+            to_hex(&example.target()),
+            "4a533d47ec9c7d95b1ad75f576cffc641853b750"
+        );
+    }
+
+    #[cfg(feature = "sign")]
+    #[test]
+    fn test_tampering_fails() {
+        let secret = ed25519_dalek::SigningKey::from_bytes(&[9; 32]);
+        let item = MutableItem::sign(&secret, b"s", 5, &BencodeValue::Integer(1)).unwrap();
+
+        let mut bumped = item.clone();
+        bumped.seq = 6;
+        assert!(matches!(bumped.verify(), Err(ItemError::InvalidSignature)));
+
+        let mut resalted = item.clone();
+        resalted.salt = b"t".to_vec();
+        assert_eq!(resalted.verify().unwrap_err().krpc_code(), 206);
+
+        let error = MutableItem::sign(&secret, &[0; 65], 1, &BencodeValue::Integer(1)).unwrap_err();
+        assert!(matches!(error, ItemError::SaltTooLarge(65)));
+    }
+}
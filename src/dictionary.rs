@@ -15,12 +15,12 @@ use crate::common::BencodeValue;
 type Res<T, U> = IResult<T, U, VerboseError<T>>;
 
 // Parse a key-value pair (key must be a byte string)
-fn dict_pair(input: &[u8]) -> Res<&[u8], (&[u8], BencodeValue)> {
+fn dict_pair(input: &[u8]) -> Res<&[u8], (&[u8], BencodeValue<'_>)> {
     tuple((byte_string, bencode_value))(input)
 }
 
 // Parse a bencode dictionary
-pub fn dictionary(input: &[u8]) -> Res<&[u8], BTreeMap<&[u8], BencodeValue>> {
+pub fn dictionary(input: &[u8]) -> Res<&[u8], BTreeMap<&[u8], BencodeValue<'_>>> {
     map(delimited(char('d'), many0(dict_pair), char('e')), |pairs| {
         pairs.into_iter().collect()
     })(input)
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+use crate::common::BencodeValue;
+use crate::fields::FieldError;
+use crate::path::Path;
+use crate::span::value_spans;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    /// Different scalars, or values of different types
+    Changed,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Changed => "changed",
+        })
+    }
+}
+
+/// One difference between two documents
+///
+/// Containers present on both sides are compared member by member, so a
+/// change is only ever reported at the deepest path that differs.
+#[derive(Debug, PartialEq)]
+pub struct Change<'v> {
+    pub path: Path,
+    pub kind: ChangeKind,
+    pub old: Option<&'v BencodeValue<'v>>,
+    pub new: Option<&'v BencodeValue<'v>>,
+    /// Byte range of `old` in the old input, filled in by [`attach_spans`]
+    pub old_span: Option<Range<usize>>,
+    pub new_span: Option<Range<usize>>,
+}
+
+impl<'v> Change<'v> {
+    fn new(
+        path: Path,
+        kind: ChangeKind,
+        old: Option<&'v BencodeValue<'v>>,
+        new: Option<&'v BencodeValue<'v>>,
+    ) -> Self {
+        Change {
+            path,
+            kind,
+            old,
+            new,
+            old_span: None,
+            new_span: None,
+        }
+    }
+}
+
+/// Compare two values, returning changes in path order
+///
+/// List elements are compared by index, so an element inserted at the front
+/// shows up as every later element changing plus one added at the end.
+pub fn diff<'v>(old: &'v BencodeValue<'v>, new: &'v BencodeValue<'v>) -> Vec<Change<'v>> {
+    let mut changes = Vec::new();
+    diff_at(Path::root(), old, new, &mut changes);
+    changes
+}
+
+fn diff_at<'v>(
+    path: Path,
+    old: &'v BencodeValue<'v>,
+    new: &'v BencodeValue<'v>,
+    changes: &mut Vec<Change<'v>>,
+) {
+    match (old, new) {
+        (BencodeValue::Dictionary(a), BencodeValue::Dictionary(b)) => {
+            let mut keys: Vec<&[u8]> = a.keys().chain(b.keys()).copied().collect();
+            keys.sort_unstable();
+            keys.dedup();
+            for key in keys {
+                let child = path.key(key);
+                match (a.get(key), b.get(key)) {
+                    (Some(x), Some(y)) => diff_at(child, x, y, changes),
+                    (Some(x), None) => {
+                        changes.push(Change::new(child, ChangeKind::Removed, Some(x), None))
+                    }
+                    (None, Some(y)) => {
+                        changes.push(Change::new(child, ChangeKind::Added, None, Some(y)))
+                    }
+                    (None, None) => unreachable!("key came from one of the dictionaries"),
+                }
+            }
+        }
+        (BencodeValue::List(a), BencodeValue::List(b)) => {
+            for index in 0..a.len().max(b.len()) {
+                let child = path.index(index);
+                match (a.get(index), b.get(index)) {
+                    (Some(x), Some(y)) => diff_at(child, x, y, changes),
+                    (x, y) => {
+                        let kind = if x.is_some() {
+                            ChangeKind::Removed
+                        } else {
+                            ChangeKind::Added
+                        };
+                        changes.push(Change::new(child, kind, x, y));
+                    }
+                }
+            }
+        }
+        _ if old == new => {}
+        _ => changes.push(Change::new(path, ChangeKind::Changed, Some(old), Some(new))),
+    }
+}
+
+/// Fill in the byte ranges of each change from the inputs the values were
+/// parsed from
+pub fn attach_spans(
+    changes: &mut [Change],
+    old_input: &[u8],
+    new_input: &[u8],
+) -> Result<(), FieldError> {
+    let spans = |input| -> Result<HashMap<Path, Range<usize>>, FieldError> {
+        Ok(value_spans(input)?
+            .into_iter()
+            .map(|span| (span.path, span.range))
+            .collect())
+    };
+    let old_spans = spans(old_input)?;
+    let new_spans = spans(new_input)?;
+    for change in changes {
+        if change.old.is_some() {
+            change.old_span = old_spans.get(&change.path).cloned();
+        }
+        if change.new.is_some() {
+            change.new_span = new_spans.get(&change.path).cloned();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::parse_document;
+
+    fn summary(changes: &[Change]) -> Vec<String> {
+        changes
+            .iter()
+            .map(|c| format!("{} {}", c.kind, c.path))
+            .collect()
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = parse_document(b"d1:ai1e1:bli1ei2ee1:cd1:xi0eee").unwrap();
+        let new = parse_document(b"d1:bli1ei3ei4ee1:cd1:x1:01:yi0ee1:di0ee").unwrap();
+        let changes = diff(&old, &new);
+        assert_eq!(
+            summary(&changes),
+            [
+                "removed a",
+                "changed b[1]",
+                "added b[2]",
+                "changed c.x",
+                "added c.y",
+                "added d"
+            ]
+        );
+        assert_eq!(changes[0].old, Some(&BencodeValue::Integer(1)));
+        assert_eq!(changes[0].new, None);
+        assert_eq!(changes[3].new, Some(&BencodeValue::ByteString(b"0")));
+
+        assert!(diff(&old, &old).is_empty());
+        let root = diff(&old, &BencodeValue::Integer(0));
+        assert_eq!(summary(&root), ["changed "]);
+    }
+
+    #[test]
+    fn test_attach_spans() {
+        let old_input = b"d4:infod4:name1:a6:lengthi1eee";
+        let new_input = b"d4:infod4:name2:ab6:lengthi1eee";
+        let old = parse_document(old_input).unwrap();
+        let new = parse_document(new_input).unwrap();
+        let mut changes = diff(&old, &new);
+        attach_spans(&mut changes, old_input, new_input).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].old_span, Some(14..17));
+        assert_eq!(changes[0].new_span, Some(14..18));
+    }
+}
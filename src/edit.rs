@@ -0,0 +1,293 @@
+use std::ops::Range;
+
+use crate::explain::{explain, TokenKind};
+use crate::fields::{parse_document, FieldError};
+use crate::path::{Path, PathSegment};
+use crate::span::value_spans;
+
+/// A dictionary entry or list element of the container being edited
+struct Child {
+    segment: PathSegment,
+    /// From the start of the key (for dictionaries) to the end of the value
+    range: Range<usize>,
+}
+
+/// The container at `path` with its byte range and children in input order
+fn container(input: &[u8], path: &Path) -> Result<(Range<usize>, Vec<Child>), FieldError> {
+    let spans = value_spans(input)?;
+    let range = spans
+        .iter()
+        .find(|span| &span.path == path)
+        .map(|span| span.range.clone())
+        .ok_or_else(|| FieldError::missing(path.clone()))?;
+
+    match input[range.start] {
+        b'd' => {
+            let mut children = Vec::new();
+            for annotation in explain(input).annotations {
+                if let TokenKind::Key(key) = annotation.kind {
+                    let inside = range.contains(&annotation.offset);
+                    if inside && annotation.path.parent().as_ref() == Some(path) {
+                        let value = spans.iter().find(|span| span.path == annotation.path);
+                        let end = value.map_or(annotation.offset, |span| span.range.end);
+                        children.push(Child {
+                            segment: PathSegment::Key(key.to_vec()),
+                            range: annotation.offset..end,
+                        });
+                    }
+                }
+            }
+            Ok((range, children))
+        }
+        b'l' => {
+            let mut children: Vec<Child> = spans
+                .into_iter()
+                .filter(|span| span.path.parent().as_ref() == Some(path))
+                .map(|span| Child {
+                    segment: span.path.last().cloned().expect("child paths are not root"),
+                    range: span.range,
+                })
+                .collect();
+            children.sort_by_key(|child| child.range.start);
+            Ok((range, children))
+        }
+        _ => Err(FieldError::wrong_type(path.clone(), "list or dictionary")),
+    }
+}
+
+// The entry for the key at the end of `path`, refusing to pick one of several
+fn only_child<'c>(children: &'c [Child], path: &Path) -> Result<Option<&'c Child>, FieldError> {
+    let segment = path.last().expect("checked not root");
+    let mut matching = children.iter().filter(|child| &child.segment == segment);
+    let child = matching.next();
+    if matching.next().is_some() {
+        return Err(FieldError::invalid(path.clone(), "duplicate key"));
+    }
+    Ok(child)
+}
+
+fn splice(input: &[u8], range: Range<usize>, replacement: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() + replacement.len());
+    output.extend_from_slice(&input[..range.start]);
+    output.extend_from_slice(replacement);
+    output.extend_from_slice(&input[range.end..]);
+    output
+}
+
+fn encode_key(key: &[u8]) -> Vec<u8> {
+    let mut out = format!("{}:", key.len()).into_bytes();
+    out.extend_from_slice(key);
+    out
+}
+
+/// Set the value at `path` to the already bencoded `value`, leaving every
+/// other byte of `input` untouched
+///
+/// An existing value is replaced in place. A missing dictionary key is
+/// inserted before the first greater key, and a list index one past the end
+/// appends. The container itself must already exist.
+pub fn set_value(input: &[u8], path: &Path, value: &[u8]) -> Result<Vec<u8>, FieldError> {
+    put(input, path, value, false)
+}
+
+/// Like [`set_value`], except that a list index inserts before the element
+/// already there instead of replacing it
+pub fn insert_value(input: &[u8], path: &Path, value: &[u8]) -> Result<Vec<u8>, FieldError> {
+    put(input, path, value, true)
+}
+
+fn put(input: &[u8], path: &Path, value: &[u8], insert: bool) -> Result<Vec<u8>, FieldError> {
+    parse_document(value)?;
+    let parent = match path.parent() {
+        Some(parent) => parent,
+        None => {
+            parse_document(input)?;
+            return Ok(value.to_vec());
+        }
+    };
+
+    let (range, children) = container(input, &parent)?;
+    let segment = path.last().expect("checked not root");
+    match (segment, input[range.start]) {
+        (PathSegment::Key(key), b'd') => match only_child(&children, path)? {
+            Some(child) => {
+                let value_start = child.range.start + encode_key(key).len();
+                Ok(splice(input, value_start..child.range.end, value))
+            }
+            None => {
+                let at = children
+                    .iter()
+                    .find(|child| matches!(&child.segment, PathSegment::Key(k) if k > key))
+                    .map_or(range.end - 1, |child| child.range.start);
+                let mut entry = encode_key(key);
+                entry.extend_from_slice(value);
+                Ok(splice(input, at..at, &entry))
+            }
+        },
+        (PathSegment::Index(index), b'l') => match children.get(*index) {
+            Some(child) if insert => Ok(splice(input, child.range.start..child.range.start, value)),
+            Some(child) => Ok(splice(input, child.range.clone(), value)),
+            None if *index == children.len() => {
+                Ok(splice(input, range.end - 1..range.end - 1, value))
+            }
+            None => Err(FieldError::invalid(
+                path.clone(),
+                format!("index out of range for a list of {}", children.len()),
+            )),
+        },
+        (PathSegment::Key(_), _) => Err(FieldError::wrong_type(parent, "dictionary")),
+        (PathSegment::Index(_), _) => Err(FieldError::wrong_type(parent, "list")),
+    }
+}
+
+/// Remove the dictionary entry or list element at `path`
+pub fn delete_value(input: &[u8], path: &Path) -> Result<Vec<u8>, FieldError> {
+    let parent = path
+        .parent()
+        .ok_or_else(|| FieldError::invalid(Path::root(), "cannot delete the root value"))?;
+    let (_, children) = container(input, &parent)?;
+    let segment = path.last().expect("checked not root");
+
+    let child = match segment {
+        PathSegment::Key(_) => only_child(&children, path)?,
+        PathSegment::Index(index) => children.get(*index),
+    };
+    match child {
+        Some(child) => Ok(splice(input, child.range.clone(), b"")),
+        None => Err(FieldError::missing(path.clone())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(s: &str) -> Path {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_set_existing() {
+        let input = b"d8:announce5:http:4:infod4:name1:aee";
+        assert_eq!(
+            set_value(input, &path("info.name"), b"3:new").unwrap(),
+            b"d8:announce5:http:4:infod4:name3:newee"
+        );
+        assert_eq!(
+            set_value(input, &path("announce"), b"i1e").unwrap(),
+            b"d8:announcei1e4:infod4:name1:aee"
+        );
+        assert_eq!(set_value(input, &Path::root(), b"de").unwrap(), b"de");
+    }
+
+    #[test]
+    fn test_set_inserts_in_order() {
+        let input = b"d1:bi2e1:di4ee";
+        assert_eq!(
+            set_value(input, &path("a"), b"i1e").unwrap(),
+            b"d1:ai1e1:bi2e1:di4ee"
+        );
+        assert_eq!(
+            set_value(input, &path("c"), b"i3e").unwrap(),
+            b"d1:bi2e1:ci3e1:di4ee"
+        );
+        assert_eq!(
+            set_value(input, &path("e"), b"i5e").unwrap(),
+            b"d1:bi2e1:di4e1:ei5ee"
+        );
+    }
+
+    #[test]
+    fn test_untouched_bytes_survive() {
+        // The unsorted keys of `x` would be reordered by a parse and re-encode
+        let input = b"d1:ai1e1:xd1:zi0e1:yi0eee";
+        assert_eq!(
+            set_value(input, &path("a"), b"i2e").unwrap(),
+            b"d1:ai2e1:xd1:zi0e1:yi0eee"
+        );
+    }
+
+    #[test]
+    fn test_lists() {
+        let input = b"d4:listli1ei2eee";
+        assert_eq!(
+            set_value(input, &path("list[1]"), b"1:x").unwrap(),
+            b"d4:listli1e1:xee"
+        );
+        assert_eq!(
+            set_value(input, &path("list[2]"), b"i3e").unwrap(),
+            b"d4:listli1ei2ei3eee"
+        );
+        assert_eq!(
+            set_value(input, &path("list[5]"), b"i3e")
+                .unwrap_err()
+                .to_string(),
+            "list[5]: index out of range for a list of 2"
+        );
+        assert_eq!(
+            insert_value(input, &path("list[1]"), b"1:x").unwrap(),
+            b"d4:listli1e1:xi2eee"
+        );
+        assert_eq!(
+            insert_value(input, &path("list[2]"), b"i3e").unwrap(),
+            b"d4:listli1ei2ei3eee"
+        );
+        assert_eq!(
+            delete_value(input, &path("list[0]")).unwrap(),
+            b"d4:listli2eee"
+        );
+    }
+
+    #[test]
+    fn test_delete() {
+        let input = b"d8:announce5:http:13:announce-listll1:aee4:infod4:name1:aee";
+        assert_eq!(
+            delete_value(input, &path("announce-list")).unwrap(),
+            b"d8:announce5:http:4:infod4:name1:aee"
+        );
+        assert_eq!(
+            delete_value(input, &path("info.name")).unwrap(),
+            b"d8:announce5:http:13:announce-listll1:aee4:infodee"
+        );
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        // The parser keeps the second `a`, so edits must go there
+        let input = b"d1:ad1:bi1ee1:ad1:ci2eee";
+        assert_eq!(
+            set_value(input, &path("a.c"), b"i3e").unwrap(),
+            b"d1:ad1:bi1ee1:ad1:ci3eee"
+        );
+        assert_eq!(
+            set_value(input, &path("a.b"), b"i3e").unwrap(),
+            b"d1:ad1:bi1ee1:ad1:bi3e1:ci2eee"
+        );
+        check_error(set_value(input, &path("a"), b"i3e"), "a: duplicate key");
+        check_error(delete_value(input, &path("a")), "a: duplicate key");
+    }
+
+    fn check_error(result: Result<Vec<u8>, FieldError>, expected: &str) {
+        assert_eq!(result.unwrap_err().to_string(), expected);
+    }
+
+    #[test]
+    fn test_errors() {
+        let input = b"d1:ai1ee";
+        check_error(delete_value(input, &path("b")), "b: missing");
+        check_error(set_value(input, &path("b.c"), b"i1e"), "b: missing");
+        check_error(
+            set_value(input, &path("a.c"), b"i1e"),
+            "a: expected list or dictionary",
+        );
+        check_error(
+            set_value(input, &path("[0]"), b"i1e"),
+            "<root>: expected list",
+        );
+        check_error(
+            delete_value(input, &Path::root()),
+            "<root>: cannot delete the root value",
+        );
+        assert!(set_value(input, &path("a"), b"i1").is_err());
+    }
+}
@@ -0,0 +1,527 @@
+use std::fmt;
+use std::str;
+
+use crate::byte_string::byte_string;
+use crate::integer::integer;
+use crate::path::Path;
+
+/// What a run of input bytes was interpreted as
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind<'a> {
+    Integer(isize),
+    ByteString(&'a [u8]),
+    Key(&'a [u8]),
+    ListStart,
+    DictionaryStart,
+    End,
+    /// Bytes after a complete root value, which `parse_bencode` hands back unparsed
+    Trailing,
+    /// The bytes could not be parsed; `position` is the offset of the offending byte
+    Error {
+        position: usize,
+        message: String,
+    },
+}
+
+/// A single row of an explanation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation<'a> {
+    pub offset: usize,
+    pub raw: &'a [u8],
+    pub kind: TokenKind<'a>,
+    pub depth: usize,
+    pub path: Path,
+}
+
+/// Annotated listing of how each byte of an input was interpreted
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation<'a> {
+    pub annotations: Vec<Annotation<'a>>,
+}
+
+impl Explanation<'_> {
+    /// Offset at which `parse_bencode` stops with an error, if it does
+    pub fn error_offset(&self) -> Option<usize> {
+        self.annotations.iter().find_map(|a| match a.kind {
+            TokenKind::Error { position, .. } => Some(position),
+            _ => None,
+        })
+    }
+}
+
+enum Frame {
+    List { path: Path, index: usize },
+    Dictionary { path: Path, key: Option<Vec<u8>> },
+}
+
+struct Explainer<'a> {
+    input: &'a [u8],
+    pos: usize,
+    stack: Vec<Frame>,
+    annotations: Vec<Annotation<'a>>,
+}
+
+/// Walk the input and describe every token, continuing past errors
+///
+/// Errors are recovered from by skipping the offending bytes, so rows after
+/// the first error show a best-effort reading of the rest of the input.
+pub fn explain(input: &[u8]) -> Explanation<'_> {
+    let mut explainer = Explainer {
+        input,
+        pos: 0,
+        stack: Vec::new(),
+        annotations: Vec::new(),
+    };
+    explainer.run();
+    Explanation {
+        annotations: explainer.annotations,
+    }
+}
+
+impl<'a> Explainer<'a> {
+    fn push(&mut self, start: usize, end: usize, kind: TokenKind<'a>, path: Path) {
+        self.annotations.push(Annotation {
+            offset: start,
+            raw: &self.input[start..end],
+            kind,
+            depth: self.stack.len(),
+            path,
+        });
+        self.pos = end;
+    }
+
+    fn error(&mut self, start: usize, position: usize, end: usize, message: String, path: Path) {
+        let end = end.min(self.input.len());
+        self.push(start, end, TokenKind::Error { position, message }, path);
+    }
+
+    // Called once a value is complete, so the enclosing container moves on
+    fn complete_value(&mut self) {
+        match self.stack.last_mut() {
+            Some(Frame::List { index, .. }) => *index += 1,
+            Some(Frame::Dictionary { key, .. }) => *key = None,
+            None => {}
+        }
+    }
+
+    fn run(&mut self) {
+        let mut root_done = false;
+
+        loop {
+            let at_end = self.pos >= self.input.len();
+            let next = self.input.get(self.pos).copied();
+
+            let path = match self.stack.last() {
+                None if root_done => {
+                    if !at_end {
+                        self.push(
+                            self.pos,
+                            self.input.len(),
+                            TokenKind::Trailing,
+                            Path::root(),
+                        );
+                    }
+                    return;
+                }
+                None => Path::root(),
+                Some(Frame::List { path, index }) => path.index(*index),
+                Some(Frame::Dictionary { path, key }) => match key {
+                    Some(key) => path.key(key),
+                    None => path.clone(),
+                },
+            };
+
+            if at_end {
+                let message = match self.stack.last() {
+                    None => "unexpected end of input, expected a value",
+                    Some(Frame::Dictionary { key: Some(_), .. }) => {
+                        "unexpected end of input, expected a dictionary value"
+                    }
+                    Some(_) => "unexpected end of input, expected 'e'",
+                };
+                self.error(self.pos, self.pos, self.pos, message.to_string(), path);
+                return;
+            }
+
+            match self.stack.last() {
+                Some(Frame::List { .. }) | Some(Frame::Dictionary { key: None, .. })
+                    if next == Some(b'e') =>
+                {
+                    let frame = self.stack.pop();
+                    let path = match frame {
+                        Some(Frame::List { path, .. }) | Some(Frame::Dictionary { path, .. }) => {
+                            path
+                        }
+                        None => unreachable!(),
+                    };
+                    self.push(self.pos, self.pos + 1, TokenKind::End, path);
+                    self.complete_value();
+                    if self.stack.is_empty() {
+                        root_done = true;
+                    }
+                    continue;
+                }
+                Some(Frame::Dictionary { key: None, .. }) => {
+                    if let Some(key) = self.byte_string(path.clone(), true) {
+                        if let Some(Frame::Dictionary { key: slot, .. }) = self.stack.last_mut() {
+                            *slot = Some(key.to_vec());
+                        }
+                    }
+                    continue;
+                }
+                Some(Frame::Dictionary { key: Some(_), .. }) if next == Some(b'e') => {
+                    let message = "expected a dictionary value, found 'e'".to_string();
+                    self.error(self.pos, self.pos, self.pos + 1, message, path);
+                    self.complete_value();
+                    continue;
+                }
+                _ => {}
+            }
+
+            let complete = match next {
+                Some(b'i') => self.integer(path),
+                Some(b'0'..=b'9') => self.byte_string(path, false).is_some(),
+                Some(b'l') => {
+                    self.push(self.pos, self.pos + 1, TokenKind::ListStart, path.clone());
+                    self.stack.push(Frame::List { path, index: 0 });
+                    false
+                }
+                Some(b'd') => {
+                    self.push(
+                        self.pos,
+                        self.pos + 1,
+                        TokenKind::DictionaryStart,
+                        path.clone(),
+                    );
+                    self.stack.push(Frame::Dictionary { path, key: None });
+                    false
+                }
+                Some(b) => {
+                    let message = format!("unexpected byte 0x{:02x}, expected a value", b);
+                    self.error(self.pos, self.pos, self.pos + 1, message, path);
+                    false
+                }
+                None => unreachable!(),
+            };
+
+            if complete {
+                self.complete_value();
+                if self.stack.is_empty() {
+                    root_done = true;
+                }
+            }
+        }
+    }
+
+    // Returns whether a value was produced (errors that skip a whole token count)
+    fn integer(&mut self, path: Path) -> bool {
+        let start = self.pos;
+        let rest = &self.input[start..];
+
+        if let Ok((remaining, value)) = integer(rest) {
+            let end = self.input.len() - remaining.len();
+            self.push(start, end, TokenKind::Integer(value), path);
+            return true;
+        }
+
+        let mut p = start + 1;
+        if self.input.get(p) == Some(&b'-') {
+            p += 1;
+        }
+        let digits_start = p;
+        while self.input.get(p).is_some_and(u8::is_ascii_digit) {
+            p += 1;
+        }
+
+        if p == digits_start {
+            let message = describe_unexpected(self.input.get(p), "a digit");
+            self.error(start, p, p + 1, message, path);
+            return false;
+        }
+        if self.input.get(p) != Some(&b'e') {
+            let message = describe_unexpected(self.input.get(p), "'e'");
+            self.error(start, p, p + 1, message, path);
+            return false;
+        }
+
+        let digits = &self.input[digits_start..p];
+        let message = if digits.len() > 1 && digits[0] == b'0' {
+            "leading zeros are not allowed in integers"
+        } else if digits == b"0" {
+            "negative zero is not allowed"
+        } else {
+            "integer out of range"
+        };
+        self.error(start, digits_start, p + 1, message.to_string(), path);
+        true
+    }
+
+    // Returns the string contents on success
+    fn byte_string(&mut self, path: Path, is_key: bool) -> Option<&'a [u8]> {
+        let start = self.pos;
+        let input = self.input;
+
+        if let Ok((remaining, value)) = byte_string(&input[start..]) {
+            let end = input.len() - remaining.len();
+            let kind = if is_key {
+                TokenKind::Key(value)
+            } else {
+                TokenKind::ByteString(value)
+            };
+            let path = if is_key { path.key(value) } else { path };
+            self.push(start, end, kind, path);
+            return Some(value);
+        }
+
+        let mut p = start;
+        while input.get(p).is_some_and(u8::is_ascii_digit) {
+            p += 1;
+        }
+
+        if p == start {
+            let message = describe_unexpected(input.get(p), "a byte string key");
+            self.error(start, p, p + 1, message, path);
+            return None;
+        }
+        if input.get(p) != Some(&b':') {
+            let message = describe_unexpected(input.get(p), "':'");
+            self.error(start, p, p + 1, message, path);
+            return None;
+        }
+
+        let digits = &input[start..p];
+        let data_start = p + 1;
+        let length = str::from_utf8(digits)
+            .ok()
+            .and_then(|s| s.parse::<usize>().ok());
+        let end = length.and_then(|length| data_start.checked_add(length));
+        match (length, end) {
+            (Some(_), Some(end)) if end <= input.len() => {
+                let message = "leading zeros are not allowed in lengths".to_string();
+                self.error(start, start, end, message, path);
+            }
+            (Some(length), _) => {
+                let message = format!(
+                    "byte string of length {} runs past end of input ({} bytes left)",
+                    length,
+                    input.len() - data_start
+                );
+                self.error(start, data_start, input.len(), message, path);
+            }
+            _ => {
+                let message = "byte string length out of range".to_string();
+                self.error(start, start, data_start, message, path);
+            }
+        }
+        None
+    }
+}
+
+fn describe_unexpected(found: Option<&u8>, expected: &str) -> String {
+    match found {
+        Some(b) => format!("unexpected byte 0x{:02x}, expected {}", b, expected),
+        None => format!("unexpected end of input, expected {}", expected),
+    }
+}
+
+// Render bytes as a short quoted preview for the listing
+fn preview(bytes: &[u8]) -> String {
+    const LIMIT: usize = 32;
+    let shown = &bytes[..bytes.len().min(LIMIT)];
+    let mut out: String = shown.escape_ascii().to_string();
+    if bytes.len() > LIMIT {
+        out.push_str("...");
+    }
+    format!("\"{}\"", out)
+}
+
+impl fmt::Display for TokenKind<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Integer(i) => write!(f, "integer {}", i),
+            TokenKind::ByteString(s) => write!(f, "string {}", preview(s)),
+            TokenKind::Key(k) => write!(f, "key {}", preview(k)),
+            TokenKind::ListStart => write!(f, "list start"),
+            TokenKind::DictionaryStart => write!(f, "dict start"),
+            TokenKind::End => write!(f, "end"),
+            TokenKind::Trailing => write!(f, "trailing data"),
+            TokenKind::Error { position, message } => {
+                write!(f, "ERROR at {}: {}", position, message)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Explanation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const RAW_BYTES: usize = 8;
+
+        writeln!(
+            f,
+            "{:>8}  {:<26}  {:>5}  {:<40}  kind",
+            "offset", "raw", "depth", "path"
+        )?;
+        for a in &self.annotations {
+            let mut raw: Vec<String> = a
+                .raw
+                .iter()
+                .take(RAW_BYTES)
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            if a.raw.len() > RAW_BYTES {
+                raw.push("..".to_string());
+            }
+            writeln!(
+                f,
+                "{:>8}  {:<26}  {:>5}  {:<40}  {}",
+                a.offset,
+                raw.join(" "),
+                a.depth,
+                a.path.to_string(),
+                a.kind
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_bencode;
+
+    fn kinds<'a>(explanation: &Explanation<'a>) -> Vec<TokenKind<'a>> {
+        explanation
+            .annotations
+            .iter()
+            .map(|a| a.kind.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_explain_valid_input() {
+        let input = b"d4:infod4:name4:spame4:listli1ei2eee";
+        let explanation = explain(input);
+
+        assert_eq!(
+            kinds(&explanation),
+            vec![
+                TokenKind::DictionaryStart,
+                TokenKind::Key(b"info"),
+                TokenKind::DictionaryStart,
+                TokenKind::Key(b"name"),
+                TokenKind::ByteString(b"spam"),
+                TokenKind::End,
+                TokenKind::Key(b"list"),
+                TokenKind::ListStart,
+                TokenKind::Integer(1),
+                TokenKind::Integer(2),
+                TokenKind::End,
+                TokenKind::End,
+            ]
+        );
+
+        let a = &explanation.annotations;
+        assert_eq!((a[4].offset, a[4].raw, a[4].depth), (14, &b"4:spam"[..], 2));
+        assert_eq!(a[4].path.to_string(), "info.name");
+        assert_eq!(a[9].path.to_string(), "list[1]");
+        assert_eq!(a[11].depth, 0);
+        assert_eq!(explanation.error_offset(), None);
+
+        // Every byte is covered exactly once
+        let covered: usize = a.iter().map(|a| a.raw.len()).sum();
+        assert_eq!(covered, input.len());
+    }
+
+    #[test]
+    fn test_explain_trailing_data() {
+        let explanation = explain(b"i42eextra");
+        assert_eq!(
+            kinds(&explanation),
+            vec![TokenKind::Integer(42), TokenKind::Trailing]
+        );
+        assert_eq!(explanation.annotations[1].raw, b"extra");
+        assert_eq!(explanation.error_offset(), None);
+    }
+
+    #[test]
+    fn test_explain_continues_past_errors() {
+        let explanation = explain(b"li01e4:spami-0ee");
+        assert_eq!(explanation.error_offset(), Some(2));
+        assert_eq!(explanation.annotations[1].raw, b"i01e");
+        assert_eq!(
+            explanation.annotations[2].kind,
+            TokenKind::ByteString(b"spam")
+        );
+        assert_eq!(explanation.annotations[2].path.to_string(), "[1]");
+        assert!(matches!(
+            explanation.annotations[3].kind,
+            TokenKind::Error { position: 13, .. }
+        ));
+        assert_eq!(explanation.annotations[4].kind, TokenKind::End);
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_error_positions() {
+        assert_eq!(explain(b"").error_offset(),             Some(0));
+        assert_eq!(explain(b"x").error_offset(),            Some(0));
+        assert_eq!(explain(b"i42").error_offset(),          Some(3));
+        assert_eq!(explain(b"i4x2e").error_offset(),        Some(2));
+        assert_eq!(explain(b"ie").error_offset(),           Some(1));
+        assert_eq!(explain(b"10:hello").error_offset(),     Some(3));
+        assert_eq!(explain(b"04:spam").error_offset(),      Some(0));
+        assert_eq!(explain(b"4spam").error_offset(),        Some(1));
+        assert_eq!(explain(b"di1ei2ee").error_offset(),     Some(1));
+        assert_eq!(explain(b"d3:fooe").error_offset(),      Some(6));
+        assert_eq!(explain(b"d3:fooi1e").error_offset(),    Some(9));
+        assert_eq!(explain(b"li1eXi2ee").error_offset(),    Some(4));
+    }
+
+    #[test]
+    fn test_huge_length() {
+        let input = format!("{}:x", usize::MAX);
+        let explanation = explain(input.as_bytes());
+        assert_eq!(explanation.error_offset(), Some(input.len() - 1));
+        assert!(parse_bencode(input.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_agrees_with_parser() {
+        let inputs: &[&[u8]] = &[
+            b"i42e",
+            b"i42eextra",
+            b"d3:fooi42e3:bar4:spame",
+            b"d3:zzzi1e3:aaai2ee",
+            b"li1eli2eee",
+            b"li1ei2e",
+            b"d3:fooXe",
+            b"i-0e",
+            b"i99999999999999999999999e",
+            b"l4:spam",
+            b"d4:infod",
+        ];
+
+        for input in inputs {
+            assert_eq!(
+                explain(input).error_offset().is_some(),
+                parse_bencode(input).is_err(),
+                "{:?}",
+                String::from_utf8_lossy(input)
+            );
+        }
+    }
+
+    #[test]
+    fn test_display_listing() {
+        let listing = explain(b"d3:fooi42ee").to_string();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[1].contains("dict start"));
+        assert!(lines[2].contains("33 3a 66 6f 6f") && lines[2].contains("key \"foo\""));
+        assert!(lines[3].contains("foo") && lines[3].contains("integer 42"));
+
+        let listing = explain(b"i01e").to_string();
+        assert!(listing.contains("ERROR at 1: leading zeros"));
+    }
+}
@@ -0,0 +1,364 @@
+use std::collections::BTreeMap;
+use std::convert::{TryFrom, TryInto};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str;
+
+use crate::common::BencodeValue;
+use crate::encoder::{encode_to_bytes, EncodingError, ToBencode};
+use crate::fields::{
+    as_unsigned, insert_extra, integer_value, parse_dictionary_prefix, parse_document,
+    string_value, Extra, FieldError, Fields,
+};
+use crate::path::Path;
+
+/// Name of the metadata exchange extension in the handshake `m` dictionary
+pub const UT_METADATA: &str = "ut_metadata";
+/// Size of every metadata piece but the last (BEP 9)
+pub const METADATA_PIECE_LEN: usize = 16 * 1024;
+
+/// The BEP 10 extended handshake, sent as extended message 0
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedHandshake {
+    /// Extension names mapped to the message IDs the sender wants to receive;
+    /// an ID of 0 disables an extension announced earlier. `None` when the
+    /// handshake has no `m`, as re-handshakes that only update `reqq` may not
+    pub messages: Option<BTreeMap<String, u8>>,
+    /// Client name and version (`v`)
+    pub client: Option<String>,
+    /// Listen port of the sender (`p`)
+    pub port: Option<u16>,
+    /// Our address as seen by the sender (`yourip`)
+    pub your_ip: Option<IpAddr>,
+    /// Size of the info dictionary, from BEP 9
+    pub metadata_size: Option<u64>,
+    /// Number of outstanding requests the sender accepts
+    pub reqq: Option<u64>,
+    pub extra: Extra,
+}
+
+const HANDSHAKE_KEYS: &[&[u8]] = &[b"m", b"metadata_size", b"p", b"reqq", b"v", b"yourip"];
+
+impl Default for ExtendedHandshake {
+    fn default() -> Self {
+        ExtendedHandshake::new()
+    }
+}
+
+impl ExtendedHandshake {
+    pub fn new() -> Self {
+        ExtendedHandshake {
+            messages: None,
+            client: None,
+            port: None,
+            your_ip: None,
+            metadata_size: None,
+            reqq: None,
+            extra: Extra::new(),
+        }
+    }
+
+    /// Message ID the sender uses for an extension, if it supports it
+    pub fn message_id(&self, name: &str) -> Option<u8> {
+        self.messages
+            .as_ref()?
+            .get(name)
+            .copied()
+            .filter(|&id| id != 0)
+    }
+
+    /// Number of metadata pieces, when `metadata_size` is known
+    pub fn metadata_pieces(&self) -> Option<u64> {
+        self.metadata_size
+            .map(|size| size.div_ceil(METADATA_PIECE_LEN as u64))
+    }
+
+    pub fn from_bytes(input: &[u8]) -> Result<ExtendedHandshake, FieldError> {
+        ExtendedHandshake::from_bencode(&parse_document(input)?)
+    }
+
+    pub fn from_bencode(value: &BencodeValue) -> Result<ExtendedHandshake, FieldError> {
+        let fields = Fields::new(value, Path::root())?;
+
+        let messages = match fields.dictionary(b"m")? {
+            Some(m) => {
+                let mut messages = BTreeMap::new();
+                for (name, id) in m.iter() {
+                    let path = m.path_of(name);
+                    let name = str::from_utf8(name)
+                        .map_err(|_| FieldError::invalid(path.clone(), "name is not UTF-8"))?;
+                    let id = as_unsigned(id, &path)?
+                        .try_into()
+                        .map_err(|_| FieldError::invalid(path, "must be a message ID below 256"))?;
+                    messages.insert(name.to_string(), id);
+                }
+                Some(messages)
+            }
+            None => None,
+        };
+
+        let port =
+            match fields.unsigned(b"p")? {
+                Some(port) => Some(port.try_into().map_err(|_| {
+                    FieldError::invalid(fields.path_of(b"p"), "must be a valid port")
+                })?),
+                None => None,
+            };
+        let your_ip = match fields.bytes(b"yourip")? {
+            Some(bytes) => Some(decode_ip(bytes).ok_or_else(|| {
+                FieldError::invalid(fields.path_of(b"yourip"), "must be 4 or 16 bytes long")
+            })?),
+            None => None,
+        };
+
+        Ok(ExtendedHandshake {
+            messages,
+            client: fields.string(b"v")?,
+            port,
+            your_ip,
+            metadata_size: fields.unsigned(b"metadata_size")?,
+            reqq: fields.unsigned(b"reqq")?,
+            extra: fields.extra(HANDSHAKE_KEYS),
+        })
+    }
+}
+
+fn decode_ip(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => Some(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?).into()),
+        16 => Some(Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?).into()),
+        _ => None,
+    }
+}
+
+fn encode_ip(ip: &IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+impl ToBencode for ExtendedHandshake {
+    fn to_bencode(&self) -> Result<Vec<u8>, EncodingError> {
+        let your_ip = self.your_ip.as_ref().map(encode_ip);
+
+        let mut dict = BTreeMap::new();
+        insert_extra(&mut dict, &self.extra)?;
+        if let Some(messages) = &self.messages {
+            let mut m = BTreeMap::new();
+            for (name, id) in messages {
+                m.insert(name.as_bytes(), integer_value(*id)?);
+            }
+            dict.insert(&b"m"[..], BencodeValue::Dictionary(m));
+        }
+        if let Some(client) = &self.client {
+            dict.insert(&b"v"[..], string_value(client));
+        }
+        if let Some(port) = self.port {
+            dict.insert(&b"p"[..], integer_value(port)?);
+        }
+        if let Some(ip) = &your_ip {
+            dict.insert(&b"yourip"[..], BencodeValue::ByteString(ip));
+        }
+        if let Some(size) = self.metadata_size {
+            dict.insert(&b"metadata_size"[..], integer_value(size)?);
+        }
+        if let Some(reqq) = self.reqq {
+            dict.insert(&b"reqq"[..], integer_value(reqq)?);
+        }
+        encode_to_bytes(&BencodeValue::Dictionary(dict))
+    }
+}
+
+/// A BEP 9 ut_metadata message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataMessage {
+    Request {
+        piece: u64,
+    },
+    /// A piece of the info dictionary, carried after the bencoded header
+    Data {
+        piece: u64,
+        total_size: u64,
+        payload: Vec<u8>,
+    },
+    Reject {
+        piece: u64,
+    },
+}
+
+impl MetadataMessage {
+    pub fn piece(&self) -> u64 {
+        match self {
+            MetadataMessage::Request { piece }
+            | MetadataMessage::Data { piece, .. }
+            | MetadataMessage::Reject { piece } => *piece,
+        }
+    }
+
+    /// Decode a message body; only `data` messages may have bytes after the dictionary
+    pub fn from_bytes(input: &[u8]) -> Result<MetadataMessage, FieldError> {
+        let (value, payload) = parse_dictionary_prefix(input)?;
+        let fields = Fields::new(&value, Path::root())?;
+        let piece = fields.require_unsigned(b"piece")?;
+
+        let message = match fields.require_unsigned(b"msg_type")? {
+            0 => MetadataMessage::Request { piece },
+            1 => {
+                if payload.len() > METADATA_PIECE_LEN {
+                    return Err(FieldError::invalid(
+                        Path::root(),
+                        format!("payload is larger than {} bytes", METADATA_PIECE_LEN),
+                    ));
+                }
+                MetadataMessage::Data {
+                    piece,
+                    total_size: fields.require_unsigned(b"total_size")?,
+                    payload: payload.to_vec(),
+                }
+            }
+            2 => MetadataMessage::Reject { piece },
+            other => {
+                return Err(FieldError::invalid(
+                    fields.path_of(b"msg_type"),
+                    format!("unknown message type {}", other),
+                ))
+            }
+        };
+        if !payload.is_empty() && !matches!(message, MetadataMessage::Data { .. }) {
+            return Err(FieldError::invalid(
+                Path::root(),
+                format!("{} unexpected bytes after dictionary", payload.len()),
+            ));
+        }
+        Ok(message)
+    }
+}
+
+impl ToBencode for MetadataMessage {
+    /// The bencoded header, followed by the payload for `data` messages
+    fn to_bencode(&self) -> Result<Vec<u8>, EncodingError> {
+        let msg_type: u8 = match self {
+            MetadataMessage::Request { .. } => 0,
+            MetadataMessage::Data { .. } => 1,
+            MetadataMessage::Reject { .. } => 2,
+        };
+
+        let mut dict = BTreeMap::new();
+        dict.insert(&b"msg_type"[..], integer_value(msg_type)?);
+        dict.insert(&b"piece"[..], integer_value(self.piece())?);
+        if let MetadataMessage::Data { total_size, .. } = self {
+            dict.insert(&b"total_size"[..], integer_value(*total_size)?);
+        }
+
+        let mut out = encode_to_bytes(&BencodeValue::Dictionary(dict))?;
+        if let MetadataMessage::Data { payload, .. } = self {
+            out.extend_from_slice(payload);
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handshake_round_trip() {
+        let input = b"d1:ei0e1:md11:ut_metadatai3e6:ut_pexi0ee13:metadata_sizei31235e1:pi6881e4:reqqi250e1:v13:Example 1.0.06:yourip4:\x7f\x00\x00\x01e";
+        let handshake = ExtendedHandshake::from_bytes(input).unwrap();
+
+        assert_eq!(handshake.message_id(UT_METADATA), Some(3));
+        assert_eq!(handshake.message_id("ut_pex"), None);
+        assert_eq!(handshake.client.as_deref(), Some("Example 1.0.0"));
+        assert_eq!(handshake.port, Some(6881));
+        assert_eq!(handshake.your_ip, Some(Ipv4Addr::LOCALHOST.into()));
+        assert_eq!(handshake.reqq, Some(250));
+        assert_eq!(handshake.metadata_pieces(), Some(2));
+        assert_eq!(handshake.extra[&b"e".to_vec()], b"i0e");
+        assert_eq!(handshake.to_bencode().unwrap(), input.to_vec());
+
+        let mut built = ExtendedHandshake::new();
+        built
+            .messages
+            .get_or_insert_with(BTreeMap::new)
+            .insert(UT_METADATA.to_string(), 1);
+        assert_eq!(built.to_bencode().unwrap(), b"d1:md11:ut_metadatai1eee");
+    }
+
+    #[test]
+    fn test_handshake_without_m_round_trips() {
+        let input = b"d4:reqqi500ee";
+        let handshake = ExtendedHandshake::from_bytes(input).unwrap();
+        assert_eq!(handshake.messages, None);
+        assert_eq!(handshake.message_id(UT_METADATA), None);
+        assert_eq!(handshake.to_bencode().unwrap(), input.to_vec());
+
+        let empty = b"d1:mdee";
+        let handshake = ExtendedHandshake::from_bytes(empty).unwrap();
+        assert_eq!(handshake.messages, Some(BTreeMap::new()));
+        assert_eq!(handshake.to_bencode().unwrap(), empty.to_vec());
+    }
+
+    #[test]
+    fn test_invalid_handshake() {
+        let check = |input: &[u8], expected: &str| {
+            assert_eq!(
+                ExtendedHandshake::from_bytes(input)
+                    .unwrap_err()
+                    .to_string(),
+                expected
+            );
+        };
+        check(b"d1:md1:xi256eee", "m.x: must be a message ID below 256");
+        check(b"d1:pi65536ee", "p: must be a valid port");
+        check(b"d6:yourip3:abce", "yourip: must be 4 or 16 bytes long");
+    }
+
+    #[test]
+    fn test_metadata_messages() {
+        let request = MetadataMessage::from_bytes(b"d8:msg_typei0e5:piecei0ee").unwrap();
+        assert_eq!(request, MetadataMessage::Request { piece: 0 });
+
+        let input = b"d8:msg_typei1e5:piecei1e10:total_sizei20000eexxxx";
+        let data = MetadataMessage::from_bytes(input).unwrap();
+        assert_eq!(
+            data,
+            MetadataMessage::Data {
+                piece: 1,
+                total_size: 20000,
+                payload: b"xxxx".to_vec(),
+            }
+        );
+        assert_eq!(data.to_bencode().unwrap(), input.to_vec());
+
+        let reject = MetadataMessage::Reject { piece: 7 };
+        assert_eq!(
+            MetadataMessage::from_bytes(&reject.to_bencode().unwrap()).unwrap(),
+            reject
+        );
+    }
+
+    #[test]
+    fn test_invalid_metadata_messages() {
+        let check = |input: &[u8], expected: &str| {
+            assert_eq!(
+                MetadataMessage::from_bytes(input).unwrap_err().to_string(),
+                expected
+            );
+        };
+        check(
+            b"d8:msg_typei3e5:piecei0ee",
+            "msg_type: unknown message type 3",
+        );
+        check(b"d8:msg_typei1e5:piecei0ee", "total_size: missing");
+        check(
+            b"d8:msg_typei0e5:piecei0eejunk",
+            "<root>: 4 unexpected bytes after dictionary",
+        );
+        check(b"d8:msg_typei0ee", "piece: missing");
+
+        let mut oversized = b"d8:msg_typei1e5:piecei0e10:total_sizei1eee".to_vec();
+        oversized.resize(oversized.len() + METADATA_PIECE_LEN + 1, 0);
+        check(&oversized, "<root>: payload is larger than 16384 bytes");
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+use crate::common::BencodeValue;
+use crate::compact::{decode_peers_v4, decode_peers_v6, encode_peers, PEER_V4_LEN, PEER_V6_LEN};
+use crate::encoder::{encode_to_bytes, EncodingError, ToBencode};
+use crate::fields::{
+    as_list, as_string, bytes_list_value, insert_extra, integer_value, parse_document,
+    string_list_value, string_value, Extra, FieldError, Fields,
+};
+use crate::path::Path;
+
+/// Value of `file-format` in files written by libtorrent
+pub const FILE_FORMAT: &str = "libtorrent resume file";
+
+/// A libtorrent `.fastresume` file, including the `qBt-` keys qBittorrent adds
+///
+/// Only commonly edited keys are typed; everything else is kept verbatim in
+/// `extra`. Every typed field is optional so that a key which was absent stays
+/// absent, and a file written by libtorrent is re-encoded byte for byte.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FastResume {
+    pub file_format: Option<String>,
+    pub file_version: Option<i64>,
+    pub libtorrent_version: Option<String>,
+    pub info_hash: Option<[u8; 20]>,
+    /// SHA-256 info-hash of v2 and hybrid torrents
+    pub info_hash2: Option<[u8; 32]>,
+    /// Raw bytes, since Linux paths need not be UTF-8
+    pub name: Option<Vec<u8>>,
+    pub save_path: Option<Vec<u8>>,
+    /// One byte per piece, with bit 0 set when the piece is downloaded
+    pub pieces: Option<Vec<u8>>,
+    pub paused: Option<bool>,
+    pub auto_managed: Option<bool>,
+    pub seed_mode: Option<bool>,
+    pub total_uploaded: Option<i64>,
+    pub total_downloaded: Option<i64>,
+    pub added_time: Option<i64>,
+    pub completed_time: Option<i64>,
+    pub file_priority: Option<Vec<i64>>,
+    pub peers: Option<Vec<SocketAddr>>,
+    pub peers6: Option<Vec<SocketAddr>>,
+    /// Tracker tiers, as in `announce-list`
+    pub trackers: Option<Vec<Vec<String>>>,
+    pub url_list: Option<Vec<String>>,
+    /// New paths of renamed files, indexed like the torrent's files
+    pub mapped_files: Option<Vec<Vec<u8>>>,
+    pub qbt_save_path: Option<String>,
+    pub qbt_category: Option<String>,
+    pub qbt_tags: Option<Vec<String>>,
+    pub qbt_name: Option<String>,
+    /// Ratio limit times 1000, or a negative value for the global setting
+    pub qbt_ratio_limit: Option<i64>,
+    /// Minutes, or a negative value for the global setting
+    pub qbt_seeding_time_limit: Option<i64>,
+    pub extra: Extra,
+}
+
+const FASTRESUME_KEYS: &[&[u8]] = &[
+    b"added_time",
+    b"auto_managed",
+    b"completed_time",
+    b"file-format",
+    b"file-version",
+    b"file_priority",
+    b"info-hash",
+    b"info-hash2",
+    b"libtorrent-version",
+    b"mapped_files",
+    b"name",
+    b"paused",
+    b"peers",
+    b"peers6",
+    b"pieces",
+    b"qBt-category",
+    b"qBt-name",
+    b"qBt-ratioLimit",
+    b"qBt-savePath",
+    b"qBt-seedingTimeLimit",
+    b"qBt-tags",
+    b"save_path",
+    b"seed_mode",
+    b"total_downloaded",
+    b"total_uploaded",
+    b"trackers",
+    b"url-list",
+];
+
+impl FastResume {
+    pub fn new() -> Self {
+        FastResume::default()
+    }
+
+    pub fn from_bytes(input: &[u8]) -> Result<FastResume, FieldError> {
+        FastResume::from_bencode(&parse_document(input)?)
+    }
+
+    pub fn from_bencode(value: &BencodeValue) -> Result<FastResume, FieldError> {
+        let fields = Fields::new(value, Path::root())?;
+
+        let trackers = match fields.list(b"trackers")? {
+            Some(tiers) => {
+                let mut trackers = Vec::new();
+                for (i, tier) in tiers.iter().enumerate() {
+                    let tier_path = fields.path_of(b"trackers").index(i);
+                    let urls = as_list(tier, &tier_path)?
+                        .iter()
+                        .enumerate()
+                        .map(|(j, url)| as_string(url, &tier_path.index(j)))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    trackers.push(urls);
+                }
+                Some(trackers)
+            }
+            None => None,
+        };
+        Ok(FastResume {
+            file_format: fields.string(b"file-format")?,
+            file_version: fields.integer(b"file-version")?,
+            libtorrent_version: fields.string(b"libtorrent-version")?,
+            info_hash: fields.fixed_bytes(b"info-hash")?,
+            info_hash2: fields.fixed_bytes(b"info-hash2")?,
+            name: fields.bytes(b"name")?.map(<[u8]>::to_vec),
+            save_path: fields.bytes(b"save_path")?.map(<[u8]>::to_vec),
+            pieces: fields.bytes(b"pieces")?.map(<[u8]>::to_vec),
+            paused: fields.flag(b"paused")?,
+            auto_managed: fields.flag(b"auto_managed")?,
+            seed_mode: fields.flag(b"seed_mode")?,
+            total_uploaded: fields.integer(b"total_uploaded")?,
+            total_downloaded: fields.integer(b"total_downloaded")?,
+            added_time: fields.integer(b"added_time")?,
+            completed_time: fields.integer(b"completed_time")?,
+            file_priority: fields.integer_list(b"file_priority")?,
+            peers: fields.compact(b"peers", PEER_V4_LEN, decode_peers_v4)?,
+            peers6: fields.compact(b"peers6", PEER_V6_LEN, decode_peers_v6)?,
+            trackers,
+            url_list: fields.string_list(b"url-list")?,
+            mapped_files: fields.bytes_list(b"mapped_files")?,
+            qbt_save_path: fields.string(b"qBt-savePath")?,
+            qbt_category: fields.string(b"qBt-category")?,
+            qbt_tags: fields.string_list(b"qBt-tags")?,
+            qbt_name: fields.string(b"qBt-name")?,
+            qbt_ratio_limit: fields.integer(b"qBt-ratioLimit")?,
+            qbt_seeding_time_limit: fields.integer(b"qBt-seedingTimeLimit")?,
+            extra: fields.extra(FASTRESUME_KEYS),
+        })
+    }
+
+    /// Whether piece `index` is marked as downloaded
+    pub fn has_piece(&self, index: usize) -> bool {
+        self.pieces
+            .as_ref()
+            .and_then(|pieces| pieces.get(index))
+            .is_some_and(|piece| piece & 1 == 1)
+    }
+
+    /// The directory data is stored in, preferring qBittorrent's own setting
+    pub fn effective_save_path(&self) -> Option<&[u8]> {
+        self.qbt_save_path
+            .as_deref()
+            .filter(|path| !path.is_empty())
+            .map(str::as_bytes)
+            .or(self.save_path.as_deref())
+    }
+}
+
+impl ToBencode for FastResume {
+    fn to_bencode(&self) -> Result<Vec<u8>, EncodingError> {
+        let peers = self.peers.as_deref().map(encode_peers);
+        let peers6 = self.peers6.as_deref().map(encode_peers);
+
+        let mut dict = BTreeMap::new();
+        insert_extra(&mut dict, &self.extra)?;
+
+        let strings = [
+            (&b"file-format"[..], &self.file_format),
+            (b"libtorrent-version", &self.libtorrent_version),
+            (b"qBt-savePath", &self.qbt_save_path),
+            (b"qBt-category", &self.qbt_category),
+            (b"qBt-name", &self.qbt_name),
+        ];
+        for (key, value) in strings {
+            if let Some(value) = value {
+                dict.insert(key, string_value(value));
+            }
+        }
+
+        let integers = [
+            (&b"file-version"[..], self.file_version),
+            (b"total_uploaded", self.total_uploaded),
+            (b"total_downloaded", self.total_downloaded),
+            (b"added_time", self.added_time),
+            (b"completed_time", self.completed_time),
+            (b"qBt-ratioLimit", self.qbt_ratio_limit),
+            (b"qBt-seedingTimeLimit", self.qbt_seeding_time_limit),
+        ];
+        for (key, value) in integers {
+            if let Some(value) = value {
+                dict.insert(key, integer_value(value)?);
+            }
+        }
+
+        let flags = [
+            (&b"paused"[..], self.paused),
+            (b"auto_managed", self.auto_managed),
+            (b"seed_mode", self.seed_mode),
+        ];
+        for (key, value) in flags {
+            if let Some(value) = value {
+                dict.insert(key, integer_value(u8::from(value))?);
+            }
+        }
+
+        let bytes = [
+            (&b"info-hash"[..], self.info_hash.as_ref().map(|h| &h[..])),
+            (b"info-hash2", self.info_hash2.as_ref().map(|h| &h[..])),
+            (b"name", self.name.as_deref()),
+            (b"save_path", self.save_path.as_deref()),
+            (b"pieces", self.pieces.as_deref()),
+            (b"peers", peers.as_deref()),
+            (b"peers6", peers6.as_deref()),
+        ];
+        for (key, value) in bytes {
+            if let Some(value) = value {
+                dict.insert(key, BencodeValue::ByteString(value));
+            }
+        }
+
+        let lists = [
+            (&b"url-list"[..], &self.url_list),
+            (b"qBt-tags", &self.qbt_tags),
+        ];
+        for (key, value) in lists {
+            if let Some(value) = value {
+                dict.insert(key, string_list_value(value));
+            }
+        }
+
+        if let Some(mapped_files) = &self.mapped_files {
+            dict.insert(&b"mapped_files"[..], bytes_list_value(mapped_files));
+        }
+        if let Some(trackers) = &self.trackers {
+            let tiers = trackers
+                .iter()
+                .map(|tier| string_list_value(tier))
+                .collect();
+            dict.insert(&b"trackers"[..], BencodeValue::List(tiers));
+        }
+        if let Some(priorities) = &self.file_priority {
+            let priorities = priorities
+                .iter()
+                .map(|&p| integer_value(p))
+                .collect::<Result<_, _>>()?;
+            dict.insert(&b"file_priority"[..], BencodeValue::List(priorities));
+        }
+
+        encode_to_bytes(&BencodeValue::Dictionary(dict))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Shaped like a qBittorrent 4.x file, with keys the model does not type
+    fn sample() -> Vec<u8> {
+        let mut input = b"d10:added_timei1700000000e12:auto_managedi1e".to_vec();
+        input.extend_from_slice(b"14:completed_timei1700003600e");
+        input.extend_from_slice(b"11:file-format22:libtorrent resume file12:file-versioni1e");
+        input.extend_from_slice(b"13:file_priorityli4ei0ee");
+        input.extend_from_slice(b"9:info-hash20:");
+        input.extend_from_slice(&[0xab; 20]);
+        input.extend_from_slice(b"18:libtorrent-version7:2.0.9.0");
+        input.extend_from_slice(b"12:mapped_filesl0:9:renamed.ce");
+        input.extend_from_slice(b"4:name6:sample6:pausedi0e5:peers6:");
+        input.extend_from_slice(&[10, 0, 0, 1, 0x1a, 0xe1]);
+        input.extend_from_slice(b"6:pieces3:\x01\x00\x01");
+        input.extend_from_slice(b"12:qBt-category5:linux17:qBt-contentLayout8:Original");
+        input.extend_from_slice(b"14:qBt-ratioLimiti-2000e12:qBt-savePath0:");
+        input.extend_from_slice(b"20:qBt-seedingTimeLimiti-2e8:qBt-tagsl3:iso2:ose");
+        input.extend_from_slice(b"9:save_path13:/srv/download9:seed_modei0e");
+        input.extend_from_slice(b"16:total_downloadedi3145728e14:total_uploadedi0e");
+        input.extend_from_slice(
+            b"8:trackersll26:udp://tracker.example:1337el23:https://backup.example/ee",
+        );
+        input.extend_from_slice(b"10:unfinishedle8:url-listlee");
+        input
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let input = sample();
+        let resume = FastResume::from_bytes(&input).unwrap();
+
+        assert_eq!(resume.file_format.as_deref(), Some(FILE_FORMAT));
+        assert_eq!(resume.info_hash, Some([0xab; 20]));
+        assert_eq!(resume.name.as_deref(), Some(&b"sample"[..]));
+        assert_eq!(resume.paused, Some(false));
+        assert_eq!(resume.file_priority, Some(vec![4, 0]));
+        assert_eq!(resume.peers, Some(vec!["10.0.0.1:6881".parse().unwrap()]));
+        assert_eq!(resume.peers6, None);
+        assert_eq!(resume.trackers.as_ref().unwrap().len(), 2);
+        assert_eq!(resume.url_list, Some(vec![]));
+        assert_eq!(resume.qbt_tags, Some(vec!["iso".into(), "os".into()]));
+        assert_eq!(resume.qbt_ratio_limit, Some(-2000));
+        assert!(resume.has_piece(0) && !resume.has_piece(1) && !resume.has_piece(9));
+        assert_eq!(resume.effective_save_path(), Some(&b"/srv/download"[..]));
+        assert!(resume.extra.contains_key(b"qBt-contentLayout".as_slice()));
+        assert!(resume.extra.contains_key(b"unfinished".as_slice()));
+
+        assert_eq!(resume.to_bencode().unwrap(), input);
+    }
+
+    #[test]
+    fn test_edit_leaves_other_bytes_alone() {
+        let input = sample();
+        let mut resume = FastResume::from_bytes(&input).unwrap();
+        resume.save_path = Some(b"/mnt/data".to_vec());
+        resume.qbt_category = None;
+
+        let expected = String::from_utf8_lossy(&input)
+            .replace("13:/srv/download", "9:/mnt/data")
+            .replace("12:qBt-category5:linux", "");
+        assert_eq!(
+            String::from_utf8_lossy(&resume.to_bencode().unwrap()),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_paths_need_not_be_utf8() {
+        let input = b"d12:mapped_filesl2:\xff\xfee4:name1:\xff9:save_path4:/\xe9t\xe9e";
+        let resume = FastResume::from_bytes(input).unwrap();
+        assert_eq!(resume.name.as_deref(), Some(&b"\xff"[..]));
+        assert_eq!(resume.effective_save_path(), Some(&b"/\xe9t\xe9"[..]));
+        assert_eq!(resume.mapped_files, Some(vec![b"\xff\xfe".to_vec()]));
+        assert_eq!(resume.to_bencode().unwrap(), input);
+    }
+
+    #[test]
+    fn test_invalid_fields() {
+        let check = |input: &[u8], expected: &str| {
+            assert_eq!(
+                FastResume::from_bytes(input).unwrap_err().to_string(),
+                expected
+            );
+        };
+        check(b"d6:pausedi2ee", "paused: must be 0 or 1");
+        check(b"d9:info-hash3:abce", "info-hash: must be 20 bytes long");
+        check(b"d5:peers4:abcde", "peers: length must be a multiple of 6");
+        check(
+            b"d8:trackersll1:ai1eeee",
+            "trackers[0][1]: expected byte string",
+        );
+        check(b"d8:qBt-tagsli1eee", "qBt-tags[0]: expected byte string");
+    }
+}
@@ -0,0 +1,528 @@
+use std::collections::BTreeMap;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::str;
+
+use crate::common::BencodeValue;
+use crate::encoder::{encode_to_bytes, EncodingError};
+use crate::explain::{explain, TokenKind};
+use crate::parser::parse_bencode;
+use crate::path::Path;
+
+/// Raw bencoded values of dictionary keys a typed model does not know about
+///
+/// Keeping them lets a model be read and written back without losing data.
+pub type Extra = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// What went wrong while reading a typed model from bencode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldErrorKind {
+    Parse(String),
+    Missing,
+    WrongType { expected: &'static str },
+    Invalid(String),
+}
+
+/// Error pointing at the field of a bencode document that could not be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    pub path: Path,
+    pub kind: FieldErrorKind,
+}
+
+impl FieldError {
+    pub fn missing(path: Path) -> Self {
+        FieldError {
+            path,
+            kind: FieldErrorKind::Missing,
+        }
+    }
+
+    pub fn wrong_type(path: Path, expected: &'static str) -> Self {
+        FieldError {
+            path,
+            kind: FieldErrorKind::WrongType { expected },
+        }
+    }
+
+    pub fn invalid(path: Path, reason: impl Into<String>) -> Self {
+        FieldError {
+            path,
+            kind: FieldErrorKind::Invalid(reason.into()),
+        }
+    }
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_root() {
+            "<root>".to_string()
+        } else {
+            self.path.to_string()
+        };
+        match &self.kind {
+            FieldErrorKind::Parse(e) => write!(f, "Parse error: {}", e),
+            FieldErrorKind::Missing => write!(f, "{}: missing", path),
+            FieldErrorKind::WrongType { expected } => write!(f, "{}: expected {}", path, expected),
+            FieldErrorKind::Invalid(reason) => write!(f, "{}: {}", path, reason),
+        }
+    }
+}
+
+impl std::error::Error for FieldError {}
+
+fn parse_error(message: String) -> FieldError {
+    FieldError {
+        path: Path::root(),
+        kind: FieldErrorKind::Parse(message),
+    }
+}
+
+// Describe why `input` failed to parse, with the offset reported by `explain`
+fn explain_error(input: &[u8]) -> FieldError {
+    let message = explain(input)
+        .annotations
+        .into_iter()
+        .find_map(|a| match a.kind {
+            TokenKind::Error { position, message } => {
+                Some(format!("offset {}: {}", position, message))
+            }
+            _ => None,
+        })
+        .unwrap_or_else(|| "invalid bencode".to_string());
+    parse_error(message)
+}
+
+/// Parse a complete document, rejecting trailing bytes
+///
+/// Parse errors are described with the offset reported by `explain`.
+pub fn parse_document(input: &[u8]) -> Result<BencodeValue<'_>, FieldError> {
+    match parse_bencode(input) {
+        Ok((b"", value)) => Ok(value),
+        Ok((remaining, _)) => Err(parse_error(format!(
+            "{} trailing bytes at offset {}",
+            remaining.len(),
+            input.len() - remaining.len()
+        ))),
+        Err(_) => Err(explain_error(input)),
+    }
+}
+
+/// Parse a complete document, also rejecting dictionaries whose keys are
+/// unsorted or repeated
+///
+/// `parse_document` accepts such input and keeps the last value of a repeated
+/// key, so a document that passes here re-encodes to exactly the same bytes.
+pub fn parse_strict(input: &[u8]) -> Result<BencodeValue<'_>, FieldError> {
+    let value = parse_document(input)?;
+
+    // The previous key of each open container; lists never set one
+    let mut previous: Vec<Option<&[u8]>> = Vec::new();
+    for annotation in explain(input).annotations {
+        match annotation.kind {
+            TokenKind::ListStart | TokenKind::DictionaryStart => previous.push(None),
+            TokenKind::End => {
+                previous.pop();
+            }
+            TokenKind::Key(key) => {
+                if let Some(last) = previous.last_mut() {
+                    match last {
+                        Some(last) if key == *last => {
+                            return Err(FieldError::invalid(annotation.path, "duplicate key"));
+                        }
+                        Some(last) if key < *last => {
+                            return Err(FieldError::invalid(
+                                annotation.path,
+                                "key is not in sorted order",
+                            ));
+                        }
+                        _ => {}
+                    }
+                    *last = Some(key);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(value)
+}
+
+/// Parse a dictionary at the start of `input` and return it with the bytes after it
+///
+/// Peer wire messages such as ut_metadata `data` append a raw payload to the
+/// dictionary, which `parse_document` would reject as trailing bytes.
+pub fn parse_dictionary_prefix(input: &[u8]) -> Result<(BencodeValue<'_>, &[u8]), FieldError> {
+    match parse_bencode(input) {
+        Ok((remaining, value @ BencodeValue::Dictionary(_))) => Ok((value, remaining)),
+        Ok(_) => Err(FieldError::wrong_type(Path::root(), "dictionary")),
+        Err(_) => Err(explain_error(input)),
+    }
+}
+
+pub fn as_integer(value: &BencodeValue, path: &Path) -> Result<i64, FieldError> {
+    match value {
+        BencodeValue::Integer(i) => Ok(*i as i64),
+        _ => Err(FieldError::wrong_type(path.clone(), "integer")),
+    }
+}
+
+pub fn as_unsigned(value: &BencodeValue, path: &Path) -> Result<u64, FieldError> {
+    let i = as_integer(value, path)?;
+    u64::try_from(i).map_err(|_| FieldError::invalid(path.clone(), "must not be negative"))
+}
+
+pub fn as_bytes<'a>(value: &BencodeValue<'a>, path: &Path) -> Result<&'a [u8], FieldError> {
+    match value {
+        BencodeValue::ByteString(bytes) => Ok(bytes),
+        _ => Err(FieldError::wrong_type(path.clone(), "byte string")),
+    }
+}
+
+pub fn as_string(value: &BencodeValue, path: &Path) -> Result<String, FieldError> {
+    let bytes = as_bytes(value, path)?;
+    str::from_utf8(bytes)
+        .map(str::to_string)
+        .map_err(|_| FieldError::invalid(path.clone(), "not valid UTF-8"))
+}
+
+pub fn as_list<'v, 'a>(
+    value: &'v BencodeValue<'a>,
+    path: &Path,
+) -> Result<&'v [BencodeValue<'a>], FieldError> {
+    match value {
+        BencodeValue::List(list) => Ok(list),
+        _ => Err(FieldError::wrong_type(path.clone(), "list")),
+    }
+}
+
+/// Typed access to the entries of a dictionary, tracking the path for errors
+pub struct Fields<'v, 'a> {
+    dict: &'v BTreeMap<&'a [u8], BencodeValue<'a>>,
+    path: Path,
+}
+
+impl<'v, 'a> Fields<'v, 'a> {
+    pub fn new(value: &'v BencodeValue<'a>, path: Path) -> Result<Self, FieldError> {
+        match value {
+            BencodeValue::Dictionary(dict) => Ok(Fields { dict, path }),
+            _ => Err(FieldError::wrong_type(path, "dictionary")),
+        }
+    }
+
+    /// Path of the dictionary itself
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path of one of the dictionary's entries
+    pub fn path_of(&self, key: &[u8]) -> Path {
+        self.path.key(key)
+    }
+
+    /// Every entry of the dictionary in key order
+    pub fn iter(&self) -> impl Iterator<Item = (&'a [u8], &'v BencodeValue<'a>)> + 'v {
+        self.dict.iter().map(|(k, v)| (*k, v))
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&'v BencodeValue<'a>> {
+        self.dict.get(key)
+    }
+
+    pub fn require(&self, key: &[u8]) -> Result<&'v BencodeValue<'a>, FieldError> {
+        self.get(key)
+            .ok_or_else(|| FieldError::missing(self.path_of(key)))
+    }
+
+    pub fn integer(&self, key: &[u8]) -> Result<Option<i64>, FieldError> {
+        self.get(key)
+            .map(|v| as_integer(v, &self.path_of(key)))
+            .transpose()
+    }
+
+    pub fn unsigned(&self, key: &[u8]) -> Result<Option<u64>, FieldError> {
+        self.get(key)
+            .map(|v| as_unsigned(v, &self.path_of(key)))
+            .transpose()
+    }
+
+    pub fn bytes(&self, key: &[u8]) -> Result<Option<&'a [u8]>, FieldError> {
+        self.get(key)
+            .map(|v| as_bytes(v, &self.path_of(key)))
+            .transpose()
+    }
+
+    pub fn string(&self, key: &[u8]) -> Result<Option<String>, FieldError> {
+        self.get(key)
+            .map(|v| as_string(v, &self.path_of(key)))
+            .transpose()
+    }
+
+    pub fn list(&self, key: &[u8]) -> Result<Option<&'v [BencodeValue<'a>]>, FieldError> {
+        self.get(key)
+            .map(|v| as_list(v, &self.path_of(key)))
+            .transpose()
+    }
+
+    pub fn dictionary(&self, key: &[u8]) -> Result<Option<Fields<'v, 'a>>, FieldError> {
+        self.get(key)
+            .map(|v| Fields::new(v, self.path_of(key)))
+            .transpose()
+    }
+
+    /// A byte string of exactly `N` bytes, such as a hash or node ID
+    pub fn fixed_bytes<const N: usize>(&self, key: &[u8]) -> Result<Option<[u8; N]>, FieldError> {
+        match self.bytes(key)? {
+            Some(bytes) => bytes.try_into().map(Some).map_err(|_| {
+                FieldError::invalid(self.path_of(key), format!("must be {} bytes long", N))
+            }),
+            None => Ok(None),
+        }
+    }
+
+    /// A compact string of `len`-byte entries such as peers or nodes, split up
+    /// by `decode`
+    pub fn compact<T>(
+        &self,
+        key: &[u8],
+        len: usize,
+        decode: fn(&[u8]) -> Option<Vec<T>>,
+    ) -> Result<Option<Vec<T>>, FieldError> {
+        match self.bytes(key)? {
+            Some(bytes) => decode(bytes).map(Some).ok_or_else(|| {
+                FieldError::invalid(
+                    self.path_of(key),
+                    format!("length must be a multiple of {}", len),
+                )
+            }),
+            None => Ok(None),
+        }
+    }
+
+    pub fn require_fixed_bytes<const N: usize>(&self, key: &[u8]) -> Result<[u8; N], FieldError> {
+        self.fixed_bytes(key)?
+            .ok_or_else(|| FieldError::missing(self.path_of(key)))
+    }
+
+    pub fn require_unsigned(&self, key: &[u8]) -> Result<u64, FieldError> {
+        as_unsigned(self.require(key)?, &self.path_of(key))
+    }
+
+    pub fn require_bytes(&self, key: &[u8]) -> Result<&'a [u8], FieldError> {
+        as_bytes(self.require(key)?, &self.path_of(key))
+    }
+
+    pub fn require_string(&self, key: &[u8]) -> Result<String, FieldError> {
+        as_string(self.require(key)?, &self.path_of(key))
+    }
+
+    pub fn require_dictionary(&self, key: &[u8]) -> Result<Fields<'v, 'a>, FieldError> {
+        Fields::new(self.require(key)?, self.path_of(key))
+    }
+
+    /// An integer used as a boolean, which must be 0 or 1
+    pub fn flag(&self, key: &[u8]) -> Result<Option<bool>, FieldError> {
+        match self.integer(key)? {
+            Some(0) => Ok(Some(false)),
+            Some(1) => Ok(Some(true)),
+            Some(_) => Err(FieldError::invalid(self.path_of(key), "must be 0 or 1")),
+            None => Ok(None),
+        }
+    }
+
+    pub fn string_list(&self, key: &[u8]) -> Result<Option<Vec<String>>, FieldError> {
+        let list = match self.list(key)? {
+            Some(list) => list,
+            None => return Ok(None),
+        };
+        let path = self.path_of(key);
+        list.iter()
+            .enumerate()
+            .map(|(i, s)| as_string(s, &path.index(i)))
+            .collect::<Result<_, _>>()
+            .map(Some)
+    }
+
+    /// A list of byte strings such as paths, which need not be UTF-8
+    pub fn bytes_list(&self, key: &[u8]) -> Result<Option<Vec<Vec<u8>>>, FieldError> {
+        let list = match self.list(key)? {
+            Some(list) => list,
+            None => return Ok(None),
+        };
+        let path = self.path_of(key);
+        list.iter()
+            .enumerate()
+            .map(|(i, s)| as_bytes(s, &path.index(i)).map(<[u8]>::to_vec))
+            .collect::<Result<_, _>>()
+            .map(Some)
+    }
+
+    pub fn integer_list(&self, key: &[u8]) -> Result<Option<Vec<i64>>, FieldError> {
+        let list = match self.list(key)? {
+            Some(list) => list,
+            None => return Ok(None),
+        };
+        let path = self.path_of(key);
+        list.iter()
+            .enumerate()
+            .map(|(i, n)| as_integer(n, &path.index(i)))
+            .collect::<Result<_, _>>()
+            .map(Some)
+    }
+
+    /// Collect every entry whose key is not in `known`, re-encoded as raw bencode
+    pub fn extra(&self, known: &[&[u8]]) -> Extra {
+        self.dict
+            .iter()
+            .filter(|(k, _)| !known.contains(k))
+            .filter_map(|(k, v)| encode_to_bytes(v).ok().map(|raw| (k.to_vec(), raw)))
+            .collect()
+    }
+}
+
+/// Add the entries of an `Extra` map back into a dictionary being written
+pub fn insert_extra<'a>(
+    dict: &mut BTreeMap<&'a [u8], BencodeValue<'a>>,
+    extra: &'a Extra,
+) -> Result<(), EncodingError> {
+    for (key, raw) in extra {
+        match parse_bencode(raw) {
+            Ok((b"", value)) => {
+                dict.insert(key, value);
+            }
+            _ => {
+                return Err(EncodingError::CustomError(format!(
+                    "Invalid raw value for key {}",
+                    String::from_utf8_lossy(key)
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A byte string value borrowing a Rust string
+pub fn string_value(s: &str) -> BencodeValue<'_> {
+    BencodeValue::ByteString(s.as_bytes())
+}
+
+/// A list of byte strings borrowing Rust strings
+pub fn string_list_value(list: &[String]) -> BencodeValue<'_> {
+    BencodeValue::List(list.iter().map(|s| string_value(s)).collect())
+}
+
+/// A list of byte strings borrowing raw bytes
+pub fn bytes_list_value(list: &[Vec<u8>]) -> BencodeValue<'_> {
+    BencodeValue::List(list.iter().map(|b| BencodeValue::ByteString(b)).collect())
+}
+
+/// An integer value, failing if it does not fit the platform integer
+pub fn integer_value<'a, T: TryInto<isize> + Copy + fmt::Display>(
+    i: T,
+) -> Result<BencodeValue<'a>, EncodingError> {
+    i.try_into().map(BencodeValue::Integer).map_err(|_| {
+        EncodingError::CustomError(format!("Integer {} too large for bencode encoding", i))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typed_access() {
+        let value = parse_document(b"d3:agei-3e4:name4:spam4:sizei42e4:tagsl1:a1:bee").unwrap();
+        let fields = Fields::new(&value, Path::root()).unwrap();
+
+        assert_eq!(fields.integer(b"age"), Ok(Some(-3)));
+        assert_eq!(fields.require_unsigned(b"size"), Ok(42));
+        assert_eq!(fields.string(b"name"), Ok(Some("spam".to_string())));
+        assert_eq!(fields.bytes(b"missing"), Ok(None));
+        assert_eq!(fields.list(b"tags").unwrap().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_errors_carry_paths() {
+        let value = parse_document(b"d4:infod4:name2:\xc0\x7f4:sizei-1eee").unwrap();
+        let info = Fields::new(&value, Path::root())
+            .unwrap()
+            .require_dictionary(b"info")
+            .unwrap();
+
+        let error = info.require_string(b"name").unwrap_err();
+        assert_eq!(error.to_string(), "info.name: not valid UTF-8");
+
+        let error = info.require_unsigned(b"size").unwrap_err();
+        assert_eq!(error.to_string(), "info.size: must not be negative");
+
+        let error = info.require_bytes(b"pieces").unwrap_err();
+        assert_eq!(error.to_string(), "info.pieces: missing");
+
+        let error = info.dictionary(b"name").err().unwrap();
+        assert_eq!(error.to_string(), "info.name: expected dictionary");
+    }
+
+    #[test]
+    fn test_parse_document_errors() {
+        let error = parse_document(b"i42eextra").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Parse error: 5 trailing bytes at offset 4"
+        );
+
+        let error = parse_document(b"li01ee").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Parse error: offset 2: leading zeros are not allowed in integers"
+        );
+    }
+
+    #[test]
+    fn test_parse_strict() {
+        assert!(parse_strict(b"d1:ad1:xi1e1:yi2ee1:bli1ei0eee").is_ok());
+
+        let error = parse_strict(b"d1:ad1:yi1e1:xi2eee").unwrap_err();
+        assert_eq!(error.to_string(), "a.x: key is not in sorted order");
+        let error = parse_strict(b"d1:ai1e1:ai2ee").unwrap_err();
+        assert_eq!(error.to_string(), "a: duplicate key");
+        assert!(parse_document(b"d1:ai1e1:ai2ee").is_ok());
+
+        let error = parse_strict(b"i1ei2e").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Parse error: 3 trailing bytes at offset 3"
+        );
+    }
+
+    #[test]
+    fn test_parse_dictionary_prefix() {
+        let (value, payload) = parse_dictionary_prefix(b"d1:ai1ee\x00raw").unwrap();
+        assert_eq!(
+            Fields::new(&value, Path::root()).unwrap().integer(b"a"),
+            Ok(Some(1))
+        );
+        assert_eq!(payload, b"\x00raw");
+
+        let error = parse_dictionary_prefix(b"i1eraw").unwrap_err();
+        assert_eq!(error.to_string(), "<root>: expected dictionary");
+        assert!(parse_dictionary_prefix(b"d1:ai1e").is_err());
+    }
+
+    #[test]
+    fn test_extra_round_trip() {
+        let input = b"d5:known1:x7:unknownld1:ai1eeee";
+        let value = parse_document(input).unwrap();
+        let fields = Fields::new(&value, Path::root()).unwrap();
+        let extra = fields.extra(&[b"known"]);
+
+        assert_eq!(extra.len(), 1);
+        assert_eq!(extra[&b"unknown".to_vec()], b"ld1:ai1eee".to_vec());
+
+        let mut dict = BTreeMap::new();
+        dict.insert(&b"known"[..], string_value("x"));
+        insert_extra(&mut dict, &extra).unwrap();
+        assert_eq!(
+            encode_to_bytes(&BencodeValue::Dictionary(dict)).unwrap(),
+            input.to_vec()
+        );
+    }
+}
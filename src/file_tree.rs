@@ -0,0 +1,475 @@
+use std::collections::BTreeMap;
+
+use crate::common::BencodeValue;
+use crate::encoder::EncodingError;
+use crate::fields::{insert_extra, integer_value, Extra, FieldError, Fields};
+use crate::path::Path;
+
+/// Length of a SHA-256 merkle root
+pub const PIECES_ROOT_LEN: usize = 32;
+
+/// A single file described by a multi-file torrent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+    pub length: u64,
+    pub path: Vec<String>,
+    /// BEP 47 attributes, such as `p` for padding files
+    pub attr: Option<String>,
+    pub extra: Extra,
+}
+
+/// Whether the torrent holds one file (`length`) or several (`files`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileLayout {
+    Single { length: u64 },
+    Multiple { files: Vec<FileEntry> },
+}
+
+impl FileEntry {
+    /// Whether BEP 47 marks this entry as a padding file
+    pub fn is_padding(&self) -> bool {
+        self.attr.as_deref().is_some_and(|attr| attr.contains('p'))
+    }
+}
+
+impl FileLayout {
+    /// Sum of the file lengths, or `None` if it does not fit in a u64
+    pub fn checked_total_length(&self) -> Option<u64> {
+        match self {
+            FileLayout::Single { length } => Some(*length),
+            FileLayout::Multiple { files } => files
+                .iter()
+                .try_fold(0u64, |total, f| total.checked_add(f.length)),
+        }
+    }
+
+    /// Sum of the file lengths, which parsing checks does not overflow
+    pub fn total_length(&self) -> u64 {
+        self.checked_total_length().unwrap_or(u64::MAX)
+    }
+}
+
+/// Whether a file or directory name stays inside the directory it is joined to
+pub(crate) fn is_safe_component(name: &[u8]) -> bool {
+    !name.is_empty() && name != b"." && name != b".." && !name.contains(&b'/')
+}
+
+/// A file in a v2 file tree, stored under the empty-string key of its node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileNode {
+    pub length: u64,
+    /// Merkle root of the file's 16 KiB blocks, absent for empty files
+    pub pieces_root: Option<[u8; PIECES_ROOT_LEN]>,
+    pub extra: Extra,
+}
+
+/// An entry of a v2 file tree: either a file or a directory of further entries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileTreeNode {
+    File(FileNode),
+    Directory(BTreeMap<String, FileTreeNode>),
+}
+
+/// The BEP 52 `file tree` dictionary of a v2 info dictionary
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileTree(pub BTreeMap<String, FileTreeNode>);
+
+fn parse_file_node(value: &BencodeValue, path: Path) -> Result<FileNode, FieldError> {
+    let fields = Fields::new(value, path)?;
+    let length = fields.require_unsigned(b"length")?;
+
+    let pieces_root = match fields.bytes(b"pieces root")? {
+        Some(root) if root.len() == PIECES_ROOT_LEN => {
+            let mut array = [0; PIECES_ROOT_LEN];
+            array.copy_from_slice(root);
+            Some(array)
+        }
+        Some(_) => {
+            return Err(FieldError::invalid(
+                fields.path_of(b"pieces root"),
+                "must be 32 bytes long",
+            ))
+        }
+        None if length > 0 => return Err(FieldError::missing(fields.path_of(b"pieces root"))),
+        None => None,
+    };
+
+    Ok(FileNode {
+        length,
+        pieces_root,
+        extra: fields.extra(&[b"length", b"pieces root"]),
+    })
+}
+
+fn parse_directory(
+    value: &BencodeValue,
+    path: Path,
+) -> Result<BTreeMap<String, FileTreeNode>, FieldError> {
+    let dict = match value {
+        BencodeValue::Dictionary(dict) => dict,
+        _ => return Err(FieldError::wrong_type(path, "dictionary")),
+    };
+
+    let mut entries = BTreeMap::new();
+    for (key, child) in dict {
+        let child_path = path.key(key);
+        let name = match std::str::from_utf8(key) {
+            Ok(name) if is_safe_component(name.as_bytes()) => name.to_string(),
+            Ok(_) => return Err(FieldError::invalid(child_path, "unsafe path component")),
+            Err(_) => return Err(FieldError::invalid(child_path, "not valid UTF-8")),
+        };
+        entries.insert(name, parse_node(child, child_path)?);
+    }
+    Ok(entries)
+}
+
+fn parse_node(value: &BencodeValue, path: Path) -> Result<FileTreeNode, FieldError> {
+    let fields = Fields::new(value, path.clone())?;
+    match fields.get(b"") {
+        Some(file) if fields.extra(&[b""]).is_empty() => {
+            Ok(FileTreeNode::File(parse_file_node(file, path.key(b""))?))
+        }
+        Some(_) => Err(FieldError::invalid(
+            path,
+            "a file entry must not have siblings",
+        )),
+        None => Ok(FileTreeNode::Directory(parse_directory(value, path)?)),
+    }
+}
+
+impl FileNode {
+    pub fn to_value(&self) -> Result<BencodeValue<'_>, EncodingError> {
+        let mut dict = BTreeMap::new();
+        insert_extra(&mut dict, &self.extra)?;
+        dict.insert(&b"length"[..], integer_value(self.length)?);
+        if let Some(root) = &self.pieces_root {
+            dict.insert(&b"pieces root"[..], BencodeValue::ByteString(root));
+        }
+        Ok(BencodeValue::Dictionary(dict))
+    }
+}
+
+impl FileTreeNode {
+    pub fn to_value(&self) -> Result<BencodeValue<'_>, EncodingError> {
+        match self {
+            FileTreeNode::File(file) => {
+                let mut dict = BTreeMap::new();
+                dict.insert(&b""[..], file.to_value()?);
+                Ok(BencodeValue::Dictionary(dict))
+            }
+            FileTreeNode::Directory(entries) => directory_value(entries),
+        }
+    }
+}
+
+fn directory_value(
+    entries: &BTreeMap<String, FileTreeNode>,
+) -> Result<BencodeValue<'_>, EncodingError> {
+    let mut dict = BTreeMap::new();
+    for (name, node) in entries {
+        dict.insert(name.as_bytes(), node.to_value()?);
+    }
+    Ok(BencodeValue::Dictionary(dict))
+}
+
+impl FileTree {
+    pub fn from_bencode(value: &BencodeValue, path: Path) -> Result<FileTree, FieldError> {
+        let tree = FileTree(parse_directory(value, path.clone())?);
+        if tree.checked_total_length().is_none() {
+            return Err(FieldError::invalid(path, "total length overflows"));
+        }
+        Ok(tree)
+    }
+
+    pub fn to_value(&self) -> Result<BencodeValue<'_>, EncodingError> {
+        directory_value(&self.0)
+    }
+
+    /// Every file with its path, in the sorted order BEP 52 lays them out
+    pub fn files(&self) -> Vec<(Vec<String>, &FileNode)> {
+        fn walk<'t>(
+            entries: &'t BTreeMap<String, FileTreeNode>,
+            prefix: &mut Vec<String>,
+            out: &mut Vec<(Vec<String>, &'t FileNode)>,
+        ) {
+            for (name, node) in entries {
+                prefix.push(name.clone());
+                match node {
+                    FileTreeNode::File(file) => out.push((prefix.clone(), file)),
+                    FileTreeNode::Directory(children) => walk(children, prefix, out),
+                }
+                prefix.pop();
+            }
+        }
+
+        let mut out = Vec::new();
+        walk(&self.0, &mut Vec::new(), &mut out);
+        out
+    }
+
+    /// Sum of the file lengths, or `None` if it does not fit in a u64
+    pub fn checked_total_length(&self) -> Option<u64> {
+        self.files()
+            .iter()
+            .try_fold(0u64, |total, (_, f)| total.checked_add(f.length))
+    }
+
+    /// Sum of the file lengths, which parsing checks does not overflow
+    pub fn total_length(&self) -> u64 {
+        self.checked_total_length().unwrap_or(u64::MAX)
+    }
+
+    /// Add a file at the given path, creating directories on the way
+    ///
+    /// Returns false if the path is empty or collides with an existing entry.
+    pub fn insert(&mut self, path: &[String], file: FileNode) -> bool {
+        let (name, parents) = match path.split_last() {
+            Some(split) => split,
+            None => return false,
+        };
+
+        let mut entries = &mut self.0;
+        for parent in parents {
+            let node = entries
+                .entry(parent.clone())
+                .or_insert_with(|| FileTreeNode::Directory(BTreeMap::new()));
+            entries = match node {
+                FileTreeNode::Directory(children) => children,
+                FileTreeNode::File(_) => return false,
+            };
+        }
+
+        if entries.contains_key(name) {
+            return false;
+        }
+        entries.insert(name.clone(), FileTreeNode::File(file));
+        true
+    }
+
+    /// The file at the given path, if there is one
+    pub fn get(&self, path: &[String]) -> Option<&FileNode> {
+        let (name, parents) = path.split_last()?;
+        let mut entries = &self.0;
+        for parent in parents {
+            entries = match entries.get(parent)? {
+                FileTreeNode::Directory(children) => children,
+                FileTreeNode::File(_) => return None,
+            };
+        }
+        match entries.get(name)? {
+            FileTreeNode::File(file) => Some(file),
+            FileTreeNode::Directory(_) => None,
+        }
+    }
+
+    /// Build a tree from a v1 `files` list, skipping BEP 47 padding files
+    ///
+    /// v1 entries carry no merkle roots, so `pieces_root` supplies one for each
+    /// non-empty file.
+    pub fn from_v1_files<F>(
+        files: &[FileEntry],
+        path: Path,
+        mut pieces_root: F,
+    ) -> Result<FileTree, FieldError>
+    where
+        F: FnMut(&FileEntry) -> Option<[u8; PIECES_ROOT_LEN]>,
+    {
+        let mut tree = FileTree::default();
+        for (i, entry) in files.iter().enumerate() {
+            if entry.is_padding() {
+                continue;
+            }
+            let entry_path = path.index(i);
+            let root = match pieces_root(entry) {
+                None if entry.length > 0 => {
+                    return Err(FieldError::missing(entry_path.key(b"pieces root")))
+                }
+                root => root.filter(|_| entry.length > 0),
+            };
+            let node = FileNode {
+                length: entry.length,
+                pieces_root: root,
+                extra: Extra::new(),
+            };
+            if !tree.insert(&entry.path, node) {
+                return Err(FieldError::invalid(
+                    entry_path.key(b"path"),
+                    "collides with another file",
+                ));
+            }
+        }
+        Ok(tree)
+    }
+
+    /// The equivalent v1 `files` list, in tree order
+    ///
+    /// With a piece length, BEP 47 padding files are inserted after every file
+    /// but the last so that each file starts on a piece boundary, as hybrid
+    /// torrents require.
+    pub fn to_v1_files(&self, piece_length: Option<u64>) -> Vec<FileEntry> {
+        let files = self.files();
+        let mut entries = Vec::new();
+        for (i, (path, file)) in files.iter().enumerate() {
+            entries.push(FileEntry {
+                length: file.length,
+                path: path.clone(),
+                attr: None,
+                extra: Extra::new(),
+            });
+
+            if let Some(piece_length) = piece_length {
+                let remainder = file.length % piece_length;
+                if remainder != 0 && i + 1 < files.len() {
+                    let padding = piece_length - remainder;
+                    entries.push(FileEntry {
+                        length: padding,
+                        path: vec![".pad".to_string(), padding.to_string()],
+                        attr: Some("p".to_string()),
+                        extra: Extra::new(),
+                    });
+                }
+            }
+        }
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::encode_to_bytes;
+    use crate::fields::parse_document;
+
+    fn file(length: u64, root: u8) -> FileNode {
+        FileNode {
+            length,
+            pieces_root: if length > 0 { Some([root; 32]) } else { None },
+            extra: Extra::new(),
+        }
+    }
+
+    fn path(parts: &[&str]) -> Vec<String> {
+        parts.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_build_and_encode() {
+        let mut tree = FileTree::default();
+        assert!(tree.insert(&path(&["dir", "b"]), file(5, 1)));
+        assert!(tree.insert(&path(&["a"]), file(0, 0)));
+        assert!(!tree.insert(&path(&["dir", "b"]), file(1, 1)));
+        assert!(!tree.insert(&path(&["a", "c"]), file(1, 1)));
+
+        let mut expected =
+            b"d1:ad0:d6:lengthi0eee3:dird1:bd0:d6:lengthi5e11:pieces root32:".to_vec();
+        expected.extend_from_slice(&[1; 32]);
+        expected.extend_from_slice(b"eeee");
+
+        let encoded = encode_to_bytes(&tree.to_value().unwrap()).unwrap();
+        assert_eq!(encoded, expected);
+
+        let value = parse_document(&encoded).unwrap();
+        let parsed = FileTree::from_bencode(&value, Path::root()).unwrap();
+        assert_eq!(parsed, tree);
+
+        let files = parsed.files();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].0, path(&["a"]));
+        assert_eq!(files[1].0, path(&["dir", "b"]));
+        assert_eq!(parsed.total_length(), 5);
+    }
+
+    #[test]
+    fn test_v1_conversion() {
+        let mut tree = FileTree::default();
+        tree.insert(&path(&["a"]), file(20000, 1));
+        tree.insert(&path(&["b", "c"]), file(0, 0));
+        tree.insert(&path(&["b", "d"]), file(16384, 2));
+        tree.insert(&path(&["e"]), file(5, 3));
+
+        assert_eq!(tree.get(&path(&["b", "d"])), Some(&file(16384, 2)));
+        assert_eq!(tree.get(&path(&["b"])), None);
+        assert_eq!(tree.get(&path(&["a", "x"])), None);
+
+        let plain = tree.to_v1_files(None);
+        assert_eq!(plain.len(), 4);
+        assert!(plain.iter().all(|f| !f.is_padding()));
+
+        let padded = tree.to_v1_files(Some(16384));
+        let summary: Vec<(String, u64)> = padded
+            .iter()
+            .map(|f| (f.path.join("/"), f.length))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("a".to_string(), 20000),
+                (".pad/12768".to_string(), 12768),
+                ("b/c".to_string(), 0),
+                ("b/d".to_string(), 16384),
+                ("e".to_string(), 5),
+            ]
+        );
+        assert!(padded[1].is_padding());
+
+        let roots: BTreeMap<Vec<String>, [u8; 32]> = tree
+            .files()
+            .into_iter()
+            .filter_map(|(p, f)| Some((p, f.pieces_root?)))
+            .collect();
+        let rebuilt = FileTree::from_v1_files(&padded, Path::root().key(b"files"), |entry| {
+            roots.get(&entry.path).copied()
+        })
+        .unwrap();
+        assert_eq!(rebuilt, tree);
+
+        let error =
+            FileTree::from_v1_files(&padded, Path::root().key(b"files"), |_| None).unwrap_err();
+        assert_eq!(error.to_string(), "files[0].pieces root: missing");
+
+        let mut clashing = plain.clone();
+        clashing[1].path = path(&["a"]);
+        let error =
+            FileTree::from_v1_files(&clashing, Path::root().key(b"files"), |_| Some([9; 32]))
+                .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "files[1].path: collides with another file"
+        );
+    }
+
+    #[test]
+    fn test_invalid_trees() {
+        let check = |input: &[u8], expected: &str| {
+            let value = parse_document(input).unwrap();
+            let error = FileTree::from_bencode(&value, Path::root().key(b"file tree")).unwrap_err();
+            assert_eq!(error.to_string(), expected);
+        };
+
+        check(b"d1:ai1ee", "file tree.a: expected dictionary");
+        check(
+            b"d1:ad0:d6:lengthi1eeee",
+            "file tree.a.\"\".pieces root: missing",
+        );
+        check(
+            b"d1:ad0:d6:lengthi1e11:pieces root1:xeee",
+            "file tree.a.\"\".pieces root: must be 32 bytes long",
+        );
+        check(
+            b"d1:ad0:d6:lengthi0ee1:bdeee",
+            "file tree.a: a file entry must not have siblings",
+        );
+        let mut huge = b"d1:ad0:d6:lengthi9223372036854775807e11:pieces root32:".to_vec();
+        huge.extend_from_slice(&[1; 32]);
+        huge.extend_from_slice(b"ee1:bd0:d6:lengthi9223372036854775807e11:pieces root32:");
+        huge.extend_from_slice(&[2; 32]);
+        huge.extend_from_slice(b"ee1:cd0:d6:lengthi2e11:pieces root32:");
+        huge.extend_from_slice(&[3; 32]);
+        huge.extend_from_slice(b"eee");
+        check(&huge, "file tree: total length overflows");
+        check(
+            b"d2:..d0:d6:lengthi0eeee",
+            "file tree.\\.\\.: unsafe path component",
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+pub use crate::encoding::to_hex;
+use crate::fields::{parse_document, FieldError, Fields};
+use crate::path::Path;
+use crate::span::raw_value;
+
+/// Info-hashes of a torrent: SHA-1 for v1 (BEP 3), SHA-256 for v2 (BEP 52)
+///
+/// Hybrid torrents carry both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InfoHash {
+    pub v1: Option<[u8; 20]>,
+    pub v2: Option<[u8; 32]>,
+}
+
+impl InfoHash {
+    /// Hash the `info` dictionary of a complete .torrent file
+    ///
+    /// The hash is computed over the exact bytes of the `info` value as they
+    /// appear in the input, so keys out of order or other oddities that
+    /// re-encoding would change do not affect the result.
+    pub fn from_torrent(input: &[u8]) -> Result<InfoHash, FieldError> {
+        let info_path = Path::root().key(b"info");
+        match raw_value(input, &info_path)? {
+            Some(info) => InfoHash::from_info_bytes(info),
+            None => Err(FieldError::missing(info_path)),
+        }
+    }
+
+    /// Hash a raw bencoded `info` dictionary, such as one fetched with ut_metadata
+    ///
+    /// Which hashes are produced depends on the dictionary: `pieces` means v1
+    /// and `meta version` 2 means v2.
+    pub fn from_info_bytes(info: &[u8]) -> Result<InfoHash, FieldError> {
+        let value = parse_document(info)?;
+        let fields = Fields::new(&value, Path::root().key(b"info"))?;
+
+        let is_v1 = fields.get(b"pieces").is_some();
+        let is_v2 = fields.integer(b"meta version")? == Some(2);
+        if !is_v1 && !is_v2 {
+            return Err(FieldError::invalid(
+                fields.path().clone(),
+                "neither pieces nor meta version 2 present",
+            ));
+        }
+
+        Ok(InfoHash {
+            v1: if is_v1 { Some(sha1(info)) } else { None },
+            v2: if is_v2 { Some(sha256(info)) } else { None },
+        })
+    }
+
+    pub fn is_hybrid(&self) -> bool {
+        self.v1.is_some() && self.v2.is_some()
+    }
+
+    /// The v2 hash truncated to 20 bytes, as used by trackers and the DHT
+    pub fn v2_truncated(&self) -> Option<[u8; 20]> {
+        self.v2.map(|hash| {
+            let mut truncated = [0; 20];
+            truncated.copy_from_slice(&hash[..20]);
+            truncated
+        })
+    }
+
+    /// The 20-byte hash to announce with: v1 if present, otherwise truncated v2
+    pub fn announce_hash(&self) -> Option<[u8; 20]> {
+        self.v1.or_else(|| self.v2_truncated())
+    }
+}
+
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    Sha1::digest(data).into()
+}
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_v1_hash() {
+        let mut input = Vec::new();
+        input.extend_from_slice(b"d8:announce3:url4:info");
+        let info_start = input.len();
+        input.extend_from_slice(b"d6:lengthi1e4:name1:a12:piece lengthi16384e6:pieces20:");
+        input.extend_from_slice(&[0; 20]);
+        input.push(b'e');
+        let info_end = input.len();
+        input.push(b'e');
+
+        let hash = InfoHash::from_torrent(&input).unwrap();
+        assert_eq!(hash.v1, Some(sha1(&input[info_start..info_end])));
+        assert_eq!(hash.v2, None);
+        assert_eq!(hash.announce_hash(), hash.v1);
+        assert!(!hash.is_hybrid());
+    }
+
+    #[test]
+    fn test_hash_uses_original_bytes() {
+        // Unsorted keys inside info must be hashed as they are, not re-encoded
+        let info = b"d4:name1:a6:lengthi1e12:piece lengthi1e6:pieces20:AAAAAAAAAAAAAAAAAAAAe";
+        let mut input = b"d4:info".to_vec();
+        input.extend_from_slice(info);
+        input.push(b'e');
+
+        let hash = InfoHash::from_torrent(&input).unwrap();
+        assert_eq!(hash.v1, Some(sha1(info)));
+    }
+
+    #[test]
+    fn test_known_hash() {
+        assert_eq!(
+            to_hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            to_hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_v2_and_hybrid_hashes() {
+        let v2_info = b"d9:file treede12:meta versioni2e4:name1:a12:piece lengthi16384ee";
+        let hash = InfoHash::from_info_bytes(v2_info).unwrap();
+        assert_eq!(hash.v1, None);
+        assert_eq!(hash.v2, Some(sha256(v2_info)));
+        assert_eq!(hash.v2_truncated().unwrap()[..], sha256(v2_info)[..20]);
+        assert_eq!(hash.announce_hash(), hash.v2_truncated());
+
+        let hybrid_info =
+            b"d9:file treede6:lengthi0e12:meta versioni2e4:name1:a12:piece lengthi16384e6:pieces0:e";
+        let hash = InfoHash::from_info_bytes(hybrid_info).unwrap();
+        assert!(hash.is_hybrid());
+        assert_eq!(hash.v1, Some(sha1(hybrid_info)));
+        assert_eq!(hash.v2, Some(sha256(hybrid_info)));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            InfoHash::from_torrent(b"d3:fooi1ee")
+                .unwrap_err()
+                .to_string(),
+            "info: missing"
+        );
+        assert_eq!(
+            InfoHash::from_torrent(b"d4:infod4:name1:aee")
+                .unwrap_err()
+                .to_string(),
+            "info: neither pieces nor meta version 2 present"
+        );
+        assert!(InfoHash::from_torrent(b"d4:infod").is_err());
+    }
+}
@@ -26,13 +26,12 @@ fn from_integer_digits((sign, digits): (Option<char>, &[u8])) -> Result<isize, &
         return Err("No negative zero");
     }
 
-    match digits_str.parse::<isize>() {
-        Ok(x) => match sign {
-            Some(_) => Ok(-x),
-            None => Ok(x),
-        },
-        _ => Err("Invalid integer"),
-    }
+    // Parsed together with the sign so that isize::MIN fits
+    let text = match sign {
+        Some(_) => format!("-{}", digits_str),
+        None => digits_str.to_string(),
+    };
+    text.parse::<isize>().map_err(|_| "Invalid integer")
 }
 
 fn integer_digits(input: &[u8]) -> Res<&[u8], isize> {
@@ -57,6 +56,10 @@ mod tests {
         assert_eq!(integer(b"i123456789e"), Ok((&b""[..], 123456789)));
         assert_eq!(integer(b"i-42e"), Ok((&b""[..], -42)));
         assert_eq!(integer(b"i-1e"), Ok((&b""[..], -1)));
+        let min = format!("i{}e", isize::MIN);
+        assert_eq!(integer(min.as_bytes()), Ok((&b""[..], isize::MIN)));
+        let below_min = format!("i-{}0e", isize::MAX);
+        assert!(integer(below_min.as_bytes()).is_err());
     }
 
     #[test]
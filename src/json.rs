@@ -0,0 +1,392 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{Map, Number, Value};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::str;
+
+use crate::common::BencodeValue;
+
+/// Tag for byte strings that are not valid UTF-8, holding standard base64
+pub const BYTES_TAG: &str = "$bytes";
+/// Tag for integers outside the range a JSON number can carry exactly
+pub const INT_TAG: &str = "$int";
+/// Tag for dictionaries whose keys cannot be written as a plain JSON object
+pub const DICT_TAG: &str = "$dict";
+
+/// Largest integer that survives a round trip through an IEEE 754 double
+const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
+/// How bencode values are mapped onto JSON
+///
+/// `Lossless` uses the following reversible scheme:
+///
+/// - integers within ±(2^53 - 1) become JSON numbers, anything larger becomes
+///   `{"$int": "<decimal digits>"}`
+/// - byte strings that are valid UTF-8 become JSON strings, anything else
+///   becomes `{"$bytes": "<base64>"}`
+/// - lists become JSON arrays
+/// - dictionaries become JSON objects, unless a key is not valid UTF-8 or the
+///   dictionary has a single key starting with `$` (which would be mistaken
+///   for a tag), in which case they become `{"$dict": [[key, value], ...]}`
+///
+/// `Friendly` is meant for display only: byte strings that are not valid UTF-8
+/// are shown as lowercase hex, keys are converted lossily, and no tags are used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonMode {
+    Lossless,
+    Friendly,
+}
+
+/// Error type for JSON conversion
+#[derive(Debug)]
+pub enum JsonError {
+    SerdeError(serde_json::Error),
+    Base64Error(base64::DecodeError),
+    UnsupportedNumber(String),
+    UnknownTag(String),
+    InvalidTag(String),
+}
+
+impl From<serde_json::Error> for JsonError {
+    fn from(error: serde_json::Error) -> Self {
+        JsonError::SerdeError(error)
+    }
+}
+
+impl From<base64::DecodeError> for JsonError {
+    fn from(error: base64::DecodeError) -> Self {
+        JsonError::Base64Error(error)
+    }
+}
+
+impl std::fmt::Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonError::SerdeError(e) => write!(f, "JSON error: {}", e),
+            JsonError::Base64Error(e) => write!(f, "Base64 error: {}", e),
+            JsonError::UnsupportedNumber(n) => write!(f, "Unsupported number: {}", n),
+            JsonError::UnknownTag(t) => write!(f, "Unknown tag: {}", t),
+            JsonError::InvalidTag(e) => write!(f, "Invalid tag: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+/// Convert a BencodeValue to a JSON value
+pub fn to_json(value: &BencodeValue, mode: JsonMode) -> Value {
+    match value {
+        BencodeValue::Integer(i) => integer_to_json(*i as i64, mode),
+        BencodeValue::ByteString(bytes) => bytes_to_json(bytes, mode),
+        BencodeValue::List(list) => Value::Array(list.iter().map(|v| to_json(v, mode)).collect()),
+        BencodeValue::Dictionary(dict) => {
+            let needs_tag = dict.keys().any(|k| str::from_utf8(k).is_err())
+                || (dict.len() == 1 && dict.keys().all(|k| k.starts_with(b"$")));
+
+            if mode == JsonMode::Lossless && needs_tag {
+                let pairs = dict
+                    .iter()
+                    .map(|(k, v)| Value::Array(vec![bytes_to_json(k, mode), to_json(v, mode)]))
+                    .collect();
+                return tagged(DICT_TAG, Value::Array(pairs));
+            }
+
+            let object = dict
+                .iter()
+                .map(|(k, v)| (String::from_utf8_lossy(k).into_owned(), to_json(v, mode)))
+                .collect();
+            Value::Object(object)
+        }
+    }
+}
+
+/// Convert a BencodeValue to a JSON string
+pub fn to_json_string(value: &BencodeValue, mode: JsonMode) -> Result<String, JsonError> {
+    Ok(serde_json::to_string(&to_json(value, mode))?)
+}
+
+/// Convert a JSON value produced in `Lossless` mode back to bencoded bytes
+///
+/// Plain JSON that never went through `to_json` is accepted too, as long as
+/// it contains no floats, booleans or nulls.
+pub fn from_json(value: &Value) -> Result<Vec<u8>, JsonError> {
+    let mut output = Vec::new();
+    encode_json(value, &mut output)?;
+    Ok(output)
+}
+
+/// Parse a JSON string and convert it back to bencoded bytes
+pub fn from_json_str(input: &str) -> Result<Vec<u8>, JsonError> {
+    from_json(&serde_json::from_str(input)?)
+}
+
+fn tagged(tag: &str, value: Value) -> Value {
+    let mut object = Map::new();
+    object.insert(tag.to_string(), value);
+    Value::Object(object)
+}
+
+fn integer_to_json(i: i64, mode: JsonMode) -> Value {
+    if mode == JsonMode::Lossless && !(-MAX_SAFE_INTEGER..=MAX_SAFE_INTEGER).contains(&i) {
+        return tagged(INT_TAG, Value::String(i.to_string()));
+    }
+    Value::Number(Number::from(i))
+}
+
+fn bytes_to_json(bytes: &[u8], mode: JsonMode) -> Value {
+    match (str::from_utf8(bytes), mode) {
+        (Ok(s), _) => Value::String(s.to_string()),
+        (Err(_), JsonMode::Lossless) => tagged(BYTES_TAG, Value::String(STANDARD.encode(bytes))),
+        (Err(_), JsonMode::Friendly) => {
+            Value::String(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+        }
+    }
+}
+
+// Returns the tag name and its payload if the object is a single `$`-prefixed key
+fn as_tag(object: &Map<String, Value>) -> Option<(&str, &Value)> {
+    if object.len() != 1 {
+        return None;
+    }
+    object
+        .iter()
+        .next()
+        .filter(|(k, _)| k.starts_with('$'))
+        .map(|(k, v)| (k.as_str(), v))
+}
+
+// Check that a string is an integer as bencode would write it
+fn is_canonical_integer(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let leading_zero = digits.len() > 1 && digits.starts_with('0');
+    let negative_zero = s.starts_with('-') && digits == "0";
+    !digits.is_empty()
+        && digits.bytes().all(|b| b.is_ascii_digit())
+        && !leading_zero
+        && !negative_zero
+}
+
+// Decode a JSON value that must represent a byte string
+fn json_to_bytes(value: &Value) -> Result<Vec<u8>, JsonError> {
+    match value {
+        Value::String(s) => Ok(s.as_bytes().to_vec()),
+        Value::Object(object) => match as_tag(object) {
+            Some((BYTES_TAG, Value::String(s))) => Ok(STANDARD.decode(s)?),
+            Some((BYTES_TAG, _)) => Err(JsonError::InvalidTag(
+                "$bytes must hold a base64 string".to_string(),
+            )),
+            _ => Err(JsonError::InvalidTag(
+                "Dictionary key must be a string or $bytes".to_string(),
+            )),
+        },
+        _ => Err(JsonError::InvalidTag(
+            "Dictionary key must be a string or $bytes".to_string(),
+        )),
+    }
+}
+
+fn write_byte_string(bytes: &[u8], output: &mut Vec<u8>) {
+    output.extend_from_slice(format!("{}:", bytes.len()).as_bytes());
+    output.extend_from_slice(bytes);
+}
+
+fn write_dictionary(
+    pairs: BTreeMap<Vec<u8>, &Value>,
+    output: &mut Vec<u8>,
+) -> Result<(), JsonError> {
+    output.push(b'd');
+    // BTreeMap keeps the keys in the raw byte order bencode requires
+    for (key, value) in pairs {
+        write_byte_string(&key, output);
+        encode_json(value, output)?;
+    }
+    output.push(b'e');
+    Ok(())
+}
+
+// Internal helper function to encode a JSON value to a byte buffer
+fn encode_json(value: &Value, output: &mut Vec<u8>) -> Result<(), JsonError> {
+    match value {
+        Value::Number(n) => {
+            // Floats and integers beyond what `BencodeValue::Integer` holds
+            let i = n
+                .as_i64()
+                .and_then(|i| isize::try_from(i).ok())
+                .ok_or_else(|| JsonError::UnsupportedNumber(n.to_string()))?;
+            output.extend_from_slice(format!("i{}e", i).as_bytes());
+        }
+        Value::String(s) => write_byte_string(s.as_bytes(), output),
+        Value::Array(items) => {
+            output.push(b'l');
+            for item in items {
+                encode_json(item, output)?;
+            }
+            output.push(b'e');
+        }
+        Value::Object(object) => match as_tag(object) {
+            Some((INT_TAG, Value::String(s))) if is_canonical_integer(s) => {
+                if s.parse::<isize>().is_err() {
+                    return Err(JsonError::UnsupportedNumber(s.clone()));
+                }
+                output.extend_from_slice(format!("i{}e", s).as_bytes());
+            }
+            Some((INT_TAG, _)) => {
+                return Err(JsonError::InvalidTag(
+                    "$int must hold a decimal integer string".to_string(),
+                ))
+            }
+            Some((BYTES_TAG, _)) => write_byte_string(&json_to_bytes(value)?, output),
+            Some((DICT_TAG, Value::Array(items))) => {
+                let mut pairs = BTreeMap::new();
+                for item in items {
+                    match item {
+                        Value::Array(pair) if pair.len() == 2 => {
+                            pairs.insert(json_to_bytes(&pair[0])?, &pair[1]);
+                        }
+                        _ => {
+                            return Err(JsonError::InvalidTag(
+                                "$dict entries must be [key, value] pairs".to_string(),
+                            ))
+                        }
+                    }
+                }
+                write_dictionary(pairs, output)?;
+            }
+            Some((DICT_TAG, _)) => {
+                return Err(JsonError::InvalidTag(
+                    "$dict must hold an array of pairs".to_string(),
+                ))
+            }
+            Some((tag, _)) => return Err(JsonError::UnknownTag(tag.to_string())),
+            None => {
+                let pairs = object
+                    .iter()
+                    .map(|(k, v)| (k.as_bytes().to_vec(), v))
+                    .collect();
+                write_dictionary(pairs, output)?;
+            }
+        },
+        Value::Bool(_) | Value::Null => {
+            return Err(JsonError::InvalidTag(format!(
+                "{} has no bencode equivalent",
+                value
+            )))
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::encode_to_bytes;
+    use crate::parser::parse_bencode;
+    use serde_json::json;
+
+    fn round_trip(input: &[u8]) -> Vec<u8> {
+        let (_, value) = parse_bencode(input).unwrap();
+        let text = to_json_string(&value, JsonMode::Lossless).unwrap();
+        from_json_str(&text).unwrap()
+    }
+
+    #[test]
+    fn test_to_json_plain_values() {
+        let (_, value) = parse_bencode(b"d3:bar4:spam3:fooli42ei-1eee").unwrap();
+        assert_eq!(
+            to_json(&value, JsonMode::Lossless),
+            json!({"bar": "spam", "foo": [42, -1]})
+        );
+    }
+
+    #[test]
+    fn test_to_json_tags() {
+        let value = BencodeValue::ByteString(&[0xC0, 0x7F]);
+        assert_eq!(
+            to_json(&value, JsonMode::Lossless),
+            json!({"$bytes": "wH8="})
+        );
+
+        let value = BencodeValue::Integer(MAX_SAFE_INTEGER as isize + 1);
+        assert_eq!(
+            to_json(&value, JsonMode::Lossless),
+            json!({"$int": "9007199254740992"})
+        );
+
+        let (_, value) = parse_bencode(b"d6:$bytes3:abce").unwrap();
+        assert_eq!(
+            to_json(&value, JsonMode::Lossless),
+            json!({"$dict": [["$bytes", "abc"]]})
+        );
+
+        let (_, value) = parse_bencode(b"d2:\xff\xfei1ee").unwrap();
+        assert_eq!(
+            to_json(&value, JsonMode::Lossless),
+            json!({"$dict": [[{"$bytes": "//4="}, 1]]})
+        );
+    }
+
+    #[test]
+    fn test_to_json_friendly() {
+        let (_, value) = parse_bencode(b"d6:pieces2:\xc0\x7f3:bigi9007199254740993ee").unwrap();
+        assert_eq!(
+            to_json(&value, JsonMode::Friendly),
+            json!({"big": 9007199254740993i64, "pieces": "c07f"})
+        );
+    }
+
+    #[test]
+    fn test_round_trip_is_byte_identical() {
+        let inputs: &[&[u8]] = &[
+            b"i0e",
+            b"i-9223372036854775807e",
+            b"0:",
+            b"4:\x00\x01\x02\x03",
+            b"le",
+            b"de",
+            b"d4:$int3:abc1:xi1ee",
+            b"d4:$int3:abce",
+            b"d1:a1:b2:\xff\xfe3:abce",
+            b"d4:infod6:lengthi1024e4:name4:test6:pieces4:\xde\xad\xbe\xefee",
+            b"ld1:ai1eeli2eli3eeee",
+        ];
+
+        for input in inputs {
+            assert_eq!(round_trip(input), input.to_vec());
+        }
+    }
+
+    #[test]
+    fn test_from_plain_json_sorts_keys() {
+        let encoded = from_json(&json!({"spam": "eggs", "cow": "moo"})).unwrap();
+        assert_eq!(encoded, b"d3:cow3:moo4:spam4:eggse");
+
+        let (_, value) = parse_bencode(&encoded).unwrap();
+        assert_eq!(encode_to_bytes(&value).unwrap(), encoded);
+    }
+
+    #[test]
+    fn test_invalid_json() {
+        assert!(from_json(&json!(1.5)).is_err());
+        assert!(from_json(&json!(true)).is_err());
+        assert!(from_json(&json!(null)).is_err());
+        assert!(from_json(&json!({"$int": "007"})).is_err());
+        assert!(from_json(&json!({"$int": "-0"})).is_err());
+        assert!(matches!(
+            from_json(&json!({"$int": "9223372036854775808"})),
+            Err(JsonError::UnsupportedNumber(_))
+        ));
+        assert!(matches!(
+            from_json(&json!(u64::MAX)),
+            Err(JsonError::UnsupportedNumber(_))
+        ));
+        let min = from_json(&json!({ "$int": isize::MIN.to_string() })).unwrap();
+        assert_eq!(
+            parse_bencode(&min).unwrap().1,
+            BencodeValue::Integer(isize::MIN)
+        );
+        assert!(from_json(&json!({"$bytes": "not base64!"})).is_err());
+        assert!(from_json(&json!({"$dict": [["a"]]})).is_err());
+        assert!(from_json(&json!({"$unknown": 1})).is_err());
+        assert!(from_json_str("{").is_err());
+    }
+}
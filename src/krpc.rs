@@ -0,0 +1,804 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+
+use crate::common::BencodeValue;
+use crate::compact::{
+    decode_nodes_v4, decode_nodes_v6, decode_peer, encode_nodes, encode_peer, CompactNode, NodeId,
+    NODE_V4_LEN, NODE_V6_LEN,
+};
+use crate::dht_item::{ImmutableItem, Item, ItemError, MutableItem, MAX_SALT_LEN, MAX_VALUE_LEN};
+use crate::encoder::{encode_to_bytes, EncodingError, ToBencode};
+use crate::fields::{
+    as_bytes, as_integer, as_list, as_string, insert_extra, integer_value, parse_document,
+    string_list_value, Extra, FieldError, Fields,
+};
+use crate::path::Path;
+use crate::span::raw_value;
+
+/// Error codes defined by BEP 5
+pub const GENERIC_ERROR: i64 = 201;
+pub const SERVER_ERROR: i64 = 202;
+pub const PROTOCOL_ERROR: i64 = 203;
+pub const METHOD_UNKNOWN: i64 = 204;
+
+/// The method of a query and the arguments specific to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryKind {
+    Ping,
+    FindNode {
+        target: NodeId,
+    },
+    GetPeers {
+        info_hash: NodeId,
+    },
+    AnnouncePeer {
+        info_hash: NodeId,
+        port: u16,
+        /// Use the source port of the UDP packet instead of `port` when
+        /// nonzero; kept as sent, since not every client writes 0 or 1
+        implied_port: Option<i64>,
+        token: Vec<u8>,
+    },
+    /// BEP 44 `get`, optionally only if the stored `seq` is newer
+    Get {
+        target: NodeId,
+        seq: Option<i64>,
+    },
+    /// BEP 44 `put`, with compare-and-swap on `seq` for mutable items
+    Put {
+        token: Vec<u8>,
+        item: Item,
+        cas: Option<i64>,
+    },
+    /// A method not modelled here; its arguments other than `id` stay in `extra`
+    Other(Vec<u8>),
+}
+
+/// A `y=q` message body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    pub id: NodeId,
+    pub kind: QueryKind,
+    /// BEP 32 `want`, such as `n4` and `n6`
+    pub want: Vec<String>,
+    /// Arguments not covered by `kind`
+    pub extra: Extra,
+}
+
+/// A `y=r` message body
+///
+/// Responses do not name the query they answer, so every field that any of
+/// the BEP 5 responses can carry is optional.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub id: NodeId,
+    pub nodes: Vec<CompactNode>,
+    /// BEP 32 IPv6 nodes
+    pub nodes6: Vec<CompactNode>,
+    /// Peers from `get_peers`
+    pub values: Vec<SocketAddr>,
+    pub token: Option<Vec<u8>>,
+    /// BEP 44 item returned by `get`
+    pub item: Option<Item>,
+    pub extra: Extra,
+}
+
+/// A `y=e` message body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KrpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageBody {
+    Query(Query),
+    Response(Response),
+    Error(KrpcError),
+}
+
+/// A KRPC message with its `t`/`y` envelope
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub transaction_id: Vec<u8>,
+    pub body: MessageBody,
+    /// Client version, `v`
+    pub version: Option<Vec<u8>>,
+    /// BEP 42 external address of the recipient, `ip`
+    pub ip: Option<SocketAddr>,
+    /// BEP 43 read-only node, `ro`, which any nonzero value sets; kept as sent
+    pub read_only: Option<i64>,
+    pub extra: Extra,
+}
+
+const ENVELOPE_KEYS: &[&[u8]] = &[b"t", b"y", b"v", b"ip", b"ro"];
+
+impl Query {
+    pub fn new(id: NodeId, kind: QueryKind) -> Self {
+        Query {
+            id,
+            kind,
+            want: Vec::new(),
+            extra: Extra::new(),
+        }
+    }
+
+    pub fn method(&self) -> &[u8] {
+        match &self.kind {
+            QueryKind::Ping => b"ping",
+            QueryKind::FindNode { .. } => b"find_node",
+            QueryKind::GetPeers { .. } => b"get_peers",
+            QueryKind::AnnouncePeer { .. } => b"announce_peer",
+            QueryKind::Get { .. } => b"get",
+            QueryKind::Put { .. } => b"put",
+            QueryKind::Other(method) => method,
+        }
+    }
+
+    fn from_bencode(
+        method: &[u8],
+        args: &Fields,
+        input: Option<&[u8]>,
+    ) -> Result<Query, FieldError> {
+        let id = args.require_fixed_bytes(b"id")?;
+        let (kind, known): (_, &[&[u8]]) = match method {
+            b"ping" => (QueryKind::Ping, &[b"id", b"want"]),
+            b"find_node" => (
+                QueryKind::FindNode {
+                    target: args.require_fixed_bytes(b"target")?,
+                },
+                &[b"id", b"want", b"target"],
+            ),
+            b"get_peers" => (
+                QueryKind::GetPeers {
+                    info_hash: args.require_fixed_bytes(b"info_hash")?,
+                },
+                &[b"id", b"want", b"info_hash"],
+            ),
+            b"announce_peer" => {
+                let port = args.require_unsigned(b"port")?;
+                let port = u16::try_from(port).map_err(|_| {
+                    FieldError::invalid(args.path_of(b"port"), "must be a valid port")
+                })?;
+                (
+                    QueryKind::AnnouncePeer {
+                        info_hash: args.require_fixed_bytes(b"info_hash")?,
+                        port,
+                        implied_port: args.integer(b"implied_port")?,
+                        token: args.require_bytes(b"token")?.to_vec(),
+                    },
+                    &[
+                        b"id",
+                        b"want",
+                        b"info_hash",
+                        b"port",
+                        b"implied_port",
+                        b"token",
+                    ],
+                )
+            }
+            b"get" => (
+                QueryKind::Get {
+                    target: args.require_fixed_bytes(b"target")?,
+                    seq: args.integer(b"seq")?,
+                },
+                &[b"id", b"want", b"target", b"seq"],
+            ),
+            b"put" => (
+                QueryKind::Put {
+                    token: args.require_bytes(b"token")?.to_vec(),
+                    item: parse_item(args, input)?,
+                    cas: args.integer(b"cas")?,
+                },
+                &[b"id", b"want", b"token", b"cas"],
+            ),
+            _ => (QueryKind::Other(method.to_vec()), &[b"id", b"want"]),
+        };
+        let item = match &kind {
+            QueryKind::Put { item, .. } => Some(item),
+            _ => None,
+        };
+        let known: Vec<&[u8]> = known.iter().chain(item_keys(item)).copied().collect();
+
+        let mut want = Vec::new();
+        if let Some(list) = args.list(b"want")? {
+            for (i, entry) in list.iter().enumerate() {
+                want.push(as_string(entry, &args.path_of(b"want").index(i))?);
+            }
+        }
+
+        Ok(Query {
+            id,
+            kind,
+            want,
+            extra: args.extra(&known),
+        })
+    }
+}
+
+// A BEP 44 item from `v` and, for mutable items, `k`, `sig`, `seq` and `salt`;
+// `v` is taken verbatim from `input` when it is given, as the signature and
+// the immutable target cover the bytes that were sent, canonical or not
+fn parse_item(fields: &Fields, input: Option<&[u8]>) -> Result<Item, FieldError> {
+    let value = fields.require(b"v")?;
+    let encoded = match input {
+        Some(input) => raw_value(input, &fields.path_of(b"v"))?
+            .ok_or_else(|| FieldError::missing(fields.path_of(b"v")))?
+            .to_vec(),
+        None => encode_to_bytes(value)
+            .map_err(|e| FieldError::invalid(fields.path_of(b"v"), e.to_string()))?,
+    };
+
+    let item = match fields.fixed_bytes(b"k")? {
+        Some(public_key) => MutableItem::from_parts(
+            public_key,
+            fields.bytes(b"salt")?.unwrap_or_default().to_vec(),
+            fields
+                .integer(b"seq")?
+                .ok_or_else(|| FieldError::missing(fields.path_of(b"seq")))?,
+            encoded,
+            fields.require_fixed_bytes(b"sig")?,
+        )
+        .map(Item::Mutable),
+        None => ImmutableItem::from_encoded(encoded).map(Item::Immutable),
+    };
+
+    item.map_err(|e| match e {
+        ItemError::SaltTooLarge(_) => FieldError::invalid(
+            fields.path_of(b"salt"),
+            format!("must not exceed {} bytes", MAX_SALT_LEN),
+        ),
+        _ => FieldError::invalid(
+            fields.path_of(b"v"),
+            format!("must not exceed {} bytes when encoded", MAX_VALUE_LEN),
+        ),
+    })
+}
+
+// Keys consumed by an item; without a mutable item they stay in `extra`
+fn item_keys(item: Option<&Item>) -> &'static [&'static [u8]] {
+    match item {
+        Some(Item::Mutable(_)) => &[b"v", b"k", b"sig", b"seq", b"salt"],
+        Some(Item::Immutable(_)) => &[b"v"],
+        None => &[],
+    }
+}
+
+// Insert the `v`, `k`, `sig`, `seq` and `salt` entries of an item
+fn insert_item<'a>(
+    dict: &mut BTreeMap<&'a [u8], BencodeValue<'a>>,
+    item: &'a Item,
+) -> Result<(), EncodingError> {
+    dict.insert(&b"v"[..], item.value());
+    if let Item::Mutable(item) = item {
+        dict.insert(&b"k"[..], BencodeValue::ByteString(&item.public_key));
+        dict.insert(&b"sig"[..], BencodeValue::ByteString(&item.signature));
+        dict.insert(&b"seq"[..], integer_value(item.seq)?);
+        if !item.salt.is_empty() {
+            dict.insert(&b"salt"[..], BencodeValue::ByteString(&item.salt));
+        }
+    }
+    Ok(())
+}
+
+impl Response {
+    pub fn new(id: NodeId) -> Self {
+        Response {
+            id,
+            nodes: Vec::new(),
+            nodes6: Vec::new(),
+            values: Vec::new(),
+            token: None,
+            item: None,
+            extra: Extra::new(),
+        }
+    }
+
+    fn from_bencode(fields: &Fields, input: Option<&[u8]>) -> Result<Response, FieldError> {
+        let mut values = Vec::new();
+        if let Some(list) = fields.list(b"values")? {
+            for (i, entry) in list.iter().enumerate() {
+                let path = fields.path_of(b"values").index(i);
+                let peer = decode_peer(as_bytes(entry, &path)?)
+                    .ok_or_else(|| FieldError::invalid(path, "not a compact peer"))?;
+                values.push(peer);
+            }
+        }
+
+        let item = match fields.get(b"v") {
+            Some(_) => Some(parse_item(fields, input)?),
+            None => None,
+        };
+        let known: Vec<&[u8]> = [&b"id"[..], b"nodes", b"nodes6", b"values", b"token"]
+            .iter()
+            .chain(item_keys(item.as_ref()))
+            .copied()
+            .collect();
+        Ok(Response {
+            id: fields.require_fixed_bytes(b"id")?,
+            nodes: fields
+                .compact(b"nodes", NODE_V4_LEN, decode_nodes_v4)?
+                .unwrap_or_default(),
+            nodes6: fields
+                .compact(b"nodes6", NODE_V6_LEN, decode_nodes_v6)?
+                .unwrap_or_default(),
+            values,
+            token: fields.bytes(b"token")?.map(<[u8]>::to_vec),
+            extra: fields.extra(&known),
+            item,
+        })
+    }
+}
+
+impl KrpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        KrpcError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn from_bencode(value: &BencodeValue, path: Path) -> Result<KrpcError, FieldError> {
+        let list = as_list(value, &path)?;
+        let code = match list.first() {
+            Some(code) => as_integer(code, &path.index(0))?,
+            None => return Err(FieldError::missing(path.index(0))),
+        };
+        // Some clients send non-UTF-8 messages, which are not worth rejecting
+        let message = match list.get(1) {
+            Some(message) => String::from_utf8_lossy(as_bytes(message, &path.index(1))?).into(),
+            None => String::new(),
+        };
+        Ok(KrpcError { code, message })
+    }
+}
+
+impl Message {
+    pub fn new(transaction_id: impl Into<Vec<u8>>, body: MessageBody) -> Self {
+        Message {
+            transaction_id: transaction_id.into(),
+            body,
+            version: None,
+            ip: None,
+            read_only: None,
+            extra: Extra::new(),
+        }
+    }
+
+    /// Parse a single UDP payload, keeping the BEP 44 `v` exactly as sent
+    pub fn from_bytes(input: &[u8]) -> Result<Message, FieldError> {
+        Message::parse(&parse_document(input)?, Some(input))
+    }
+
+    /// Parse an already decoded message; a BEP 44 `v` is re-encoded, so a
+    /// non-canonical one will not match its signature or target
+    pub fn from_bencode(value: &BencodeValue) -> Result<Message, FieldError> {
+        Message::parse(value, None)
+    }
+
+    fn parse(value: &BencodeValue, input: Option<&[u8]>) -> Result<Message, FieldError> {
+        let fields = Fields::new(value, Path::root())?;
+
+        let (body, body_keys): (_, &[&[u8]]) = match fields.require_bytes(b"y")? {
+            b"q" => {
+                let method = fields.require_bytes(b"q")?;
+                let args = fields.require_dictionary(b"a")?;
+                (
+                    MessageBody::Query(Query::from_bencode(method, &args, input)?),
+                    &[b"q", b"a"],
+                )
+            }
+            b"r" => (
+                MessageBody::Response(Response::from_bencode(
+                    &fields.require_dictionary(b"r")?,
+                    input,
+                )?),
+                &[b"r"],
+            ),
+            b"e" => (
+                MessageBody::Error(KrpcError::from_bencode(
+                    fields.require(b"e")?,
+                    fields.path_of(b"e"),
+                )?),
+                &[b"e"],
+            ),
+            _ => {
+                return Err(FieldError::invalid(
+                    fields.path_of(b"y"),
+                    "unknown message type",
+                ))
+            }
+        };
+
+        let ip = match fields.bytes(b"ip")? {
+            Some(bytes) => Some(decode_peer(bytes).ok_or_else(|| {
+                FieldError::invalid(fields.path_of(b"ip"), "not a compact address")
+            })?),
+            None => None,
+        };
+
+        let known: Vec<&[u8]> = ENVELOPE_KEYS.iter().chain(body_keys).copied().collect();
+        Ok(Message {
+            transaction_id: fields.require_bytes(b"t")?.to_vec(),
+            body,
+            version: fields.bytes(b"v")?.map(<[u8]>::to_vec),
+            ip,
+            read_only: fields.integer(b"ro")?,
+            extra: fields.extra(&known),
+        })
+    }
+}
+
+impl ToBencode for Message {
+    fn to_bencode(&self) -> Result<Vec<u8>, EncodingError> {
+        // Compact strings are built up front so the value tree can borrow them
+        let ip = self.ip.as_ref().map(encode_peer);
+        let (nodes, nodes6, values) = match &self.body {
+            MessageBody::Response(response) => (
+                encode_nodes(&response.nodes),
+                encode_nodes(&response.nodes6),
+                response.values.iter().map(encode_peer).collect(),
+            ),
+            _ => (Vec::new(), Vec::new(), Vec::new()),
+        };
+
+        let mut dict = BTreeMap::new();
+        insert_extra(&mut dict, &self.extra)?;
+        dict.insert(&b"t"[..], BencodeValue::ByteString(&self.transaction_id));
+        if let Some(version) = &self.version {
+            dict.insert(&b"v"[..], BencodeValue::ByteString(version));
+        }
+        if let Some(ip) = &ip {
+            dict.insert(&b"ip"[..], BencodeValue::ByteString(ip));
+        }
+        if let Some(read_only) = self.read_only {
+            dict.insert(&b"ro"[..], integer_value(read_only)?);
+        }
+
+        match &self.body {
+            MessageBody::Query(query) => {
+                let mut args = BTreeMap::new();
+                insert_extra(&mut args, &query.extra)?;
+                args.insert(&b"id"[..], BencodeValue::ByteString(&query.id));
+                if !query.want.is_empty() {
+                    args.insert(&b"want"[..], string_list_value(&query.want));
+                }
+                match &query.kind {
+                    QueryKind::Ping | QueryKind::Other(_) => {}
+                    QueryKind::FindNode { target } => {
+                        args.insert(&b"target"[..], BencodeValue::ByteString(target));
+                    }
+                    QueryKind::GetPeers { info_hash } => {
+                        args.insert(&b"info_hash"[..], BencodeValue::ByteString(info_hash));
+                    }
+                    QueryKind::AnnouncePeer {
+                        info_hash,
+                        port,
+                        implied_port,
+                        token,
+                    } => {
+                        args.insert(&b"info_hash"[..], BencodeValue::ByteString(info_hash));
+                        args.insert(&b"port"[..], integer_value(*port)?);
+                        if let Some(implied_port) = implied_port {
+                            args.insert(&b"implied_port"[..], integer_value(*implied_port)?);
+                        }
+                        args.insert(&b"token"[..], BencodeValue::ByteString(token));
+                    }
+                    QueryKind::Get { target, seq } => {
+                        args.insert(&b"target"[..], BencodeValue::ByteString(target));
+                        if let Some(seq) = seq {
+                            args.insert(&b"seq"[..], integer_value(*seq)?);
+                        }
+                    }
+                    QueryKind::Put { token, item, cas } => {
+                        insert_item(&mut args, item)?;
+                        args.insert(&b"token"[..], BencodeValue::ByteString(token));
+                        if let Some(cas) = cas {
+                            args.insert(&b"cas"[..], integer_value(*cas)?);
+                        }
+                    }
+                }
+                dict.insert(&b"y"[..], BencodeValue::ByteString(b"q"));
+                dict.insert(&b"q"[..], BencodeValue::ByteString(query.method()));
+                dict.insert(&b"a"[..], BencodeValue::Dictionary(args));
+            }
+            MessageBody::Response(response) => {
+                let mut r = BTreeMap::new();
+                insert_extra(&mut r, &response.extra)?;
+                r.insert(&b"id"[..], BencodeValue::ByteString(&response.id));
+                if !nodes.is_empty() {
+                    r.insert(&b"nodes"[..], BencodeValue::ByteString(&nodes));
+                }
+                if !nodes6.is_empty() {
+                    r.insert(&b"nodes6"[..], BencodeValue::ByteString(&nodes6));
+                }
+                if !values.is_empty() {
+                    let list = values
+                        .iter()
+                        .map(|peer: &Vec<u8>| BencodeValue::ByteString(peer))
+                        .collect();
+                    r.insert(&b"values"[..], BencodeValue::List(list));
+                }
+                if let Some(token) = &response.token {
+                    r.insert(&b"token"[..], BencodeValue::ByteString(token));
+                }
+                if let Some(item) = &response.item {
+                    insert_item(&mut r, item)?;
+                }
+                dict.insert(&b"y"[..], BencodeValue::ByteString(b"r"));
+                dict.insert(&b"r"[..], BencodeValue::Dictionary(r));
+            }
+            MessageBody::Error(error) => {
+                let list = vec![
+                    integer_value(error.code)?,
+                    BencodeValue::ByteString(error.message.as_bytes()),
+                ];
+                dict.insert(&b"y"[..], BencodeValue::ByteString(b"e"));
+                dict.insert(&b"e"[..], BencodeValue::List(list));
+            }
+        }
+
+        encode_to_bytes(&BencodeValue::Dictionary(dict))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &[u8; 20] = b"abcdefghij0123456789";
+    const OTHER: &[u8; 20] = b"mnopqrstuvwxyz123456";
+
+    fn round_trip(input: &[u8]) -> Message {
+        let message = Message::from_bytes(input).unwrap();
+        assert_eq!(message.to_bencode().unwrap(), input);
+        message
+    }
+
+    #[test]
+    fn test_bep5_queries() {
+        let ping = round_trip(b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:aa1:y1:qe");
+        assert_eq!(ping.transaction_id, b"aa");
+        assert_eq!(
+            ping.body,
+            MessageBody::Query(Query::new(*ID, QueryKind::Ping))
+        );
+
+        let find = round_trip(b"d1:ad2:id20:abcdefghij01234567896:target20:mnopqrstuvwxyz123456e1:q9:find_node1:t2:aa1:y1:qe");
+        assert_eq!(
+            find.body,
+            MessageBody::Query(Query::new(*ID, QueryKind::FindNode { target: *OTHER }))
+        );
+
+        // Flags other than 0 and 1 are what some nodes send, so they are kept
+        let odd = round_trip(b"d1:ad2:id20:abcdefghij012345678912:implied_porti2e9:info_hash20:mnopqrstuvwxyz1234564:porti1e5:token1:xe1:q13:announce_peer1:t2:aa1:y1:qe");
+        assert!(matches!(
+            odd.body,
+            MessageBody::Query(Query {
+                kind: QueryKind::AnnouncePeer {
+                    implied_port: Some(2),
+                    ..
+                },
+                ..
+            })
+        ));
+        assert_eq!(
+            round_trip(b"d1:eli201e0:e2:roi-1e1:t2:aa1:y1:ee").read_only,
+            Some(-1)
+        );
+        let announce = round_trip(b"d1:ad2:id20:abcdefghij012345678912:implied_porti1e9:info_hash20:mnopqrstuvwxyz1234564:porti6881e5:token8:aoeusnthe1:q13:announce_peer1:t2:aa1:y1:qe");
+        match announce.body {
+            MessageBody::Query(Query {
+                kind:
+                    QueryKind::AnnouncePeer {
+                        port,
+                        implied_port,
+                        token,
+                        ..
+                    },
+                ..
+            }) => {
+                assert_eq!(port, 6881);
+                assert_eq!(implied_port, Some(1));
+                assert_eq!(token, b"aoeusnth");
+            }
+            _ => panic!("Expected announce_peer"),
+        }
+    }
+
+    #[test]
+    fn test_responses() {
+        let mut input = b"d1:rd2:id20:mnopqrstuvwxyz1234565:nodes26:".to_vec();
+        input.extend_from_slice(ID);
+        input.extend_from_slice(&[127, 0, 0, 1, 0x1a, 0xe1]);
+        input.extend_from_slice(b"6:nodes638:");
+        input.extend_from_slice(OTHER);
+        input.extend_from_slice(&[0; 15]);
+        input.extend_from_slice(&[1, 0, 80]);
+        input.extend_from_slice(b"5:token8:aoeusnth6:valuesl6:axje.u6:idhtnmee1:t2:aa1:y1:re");
+
+        let message = round_trip(&input);
+        let response = match message.body {
+            MessageBody::Response(response) => response,
+            _ => panic!("Expected response"),
+        };
+        assert_eq!(response.id, *OTHER);
+        assert_eq!(response.nodes[0].addr, "127.0.0.1:6881".parse().unwrap());
+        assert_eq!(response.nodes6[0].addr, "[::1]:80".parse().unwrap());
+        assert_eq!(response.values.len(), 2);
+        assert_eq!(response.token.as_deref(), Some(&b"aoeusnth"[..]));
+    }
+
+    #[test]
+    fn test_error_message() {
+        let message = round_trip(b"d1:eli201e23:A Generic Error Ocurrede1:t2:aa1:y1:ee");
+        assert_eq!(
+            message.body,
+            MessageBody::Error(KrpcError::new(GENERIC_ERROR, "A Generic Error Ocurred"))
+        );
+    }
+
+    #[test]
+    fn test_extra_keys_and_envelope() {
+        // Real clients add keys of their own at every level
+        let input = b"d1:ad2:id20:abcdefghij01234567893:seqi5e4:wantl2:n42:n6ee2:ip6:\x01\x02\x03\x04\x00\x501:q17:sample_infohashes2:roi1e1:t2:aa1:v4:LT011:y1:q3:zzz0:e";
+        let message = round_trip(input);
+        assert_eq!(message.version.as_deref(), Some(&b"LT01"[..]));
+        assert_eq!(message.ip, Some("1.2.3.4:80".parse().unwrap()));
+        assert_eq!(message.read_only, Some(1));
+        assert!(message.extra.contains_key(&b"zzz"[..]));
+
+        let query = match message.body {
+            MessageBody::Query(query) => query,
+            _ => panic!("Expected query"),
+        };
+        assert_eq!(query.kind, QueryKind::Other(b"sample_infohashes".to_vec()));
+        assert_eq!(query.method(), b"sample_infohashes");
+        assert_eq!(query.want, vec!["n4", "n6"]);
+        assert_eq!(query.extra.get(&b"seq"[..]), Some(&b"i5e".to_vec()));
+    }
+
+    #[test]
+    fn test_bep44_get_and_put() {
+        let get = round_trip(b"d1:ad2:id20:abcdefghij01234567893:seqi4e6:target20:mnopqrstuvwxyz123456e1:q3:get1:t2:aa1:y1:qe");
+        assert_eq!(
+            get.body,
+            MessageBody::Query(Query::new(
+                *ID,
+                QueryKind::Get {
+                    target: *OTHER,
+                    seq: Some(4)
+                }
+            ))
+        );
+
+        let item = MutableItem::from_parts(
+            [1; 32],
+            b"foobar".to_vec(),
+            4,
+            b"12:Hello World!".to_vec(),
+            [2; 64],
+        )
+        .unwrap();
+        let put = Message::new(
+            b"aa".to_vec(),
+            MessageBody::Query(Query::new(
+                *ID,
+                QueryKind::Put {
+                    token: b"tok".to_vec(),
+                    item: Item::Mutable(item.clone()),
+                    cas: Some(3),
+                },
+            )),
+        );
+        let encoded = put.to_bencode().unwrap();
+        let mut expected = b"d1:ad3:casi3e2:id20:abcdefghij01234567891:k32:".to_vec();
+        expected.extend_from_slice(&[1; 32]);
+        expected.extend_from_slice(b"4:salt6:foobar3:seqi4e3:sig64:");
+        expected.extend_from_slice(&[2; 64]);
+        expected.extend_from_slice(b"5:token3:tok1:v12:Hello World!e1:q3:put1:t2:aa1:y1:qe");
+        assert_eq!(encoded, expected);
+        assert_eq!(Message::from_bytes(&encoded).unwrap(), put);
+
+        let response = round_trip(
+            b"d1:rd2:id20:mnopqrstuvwxyz1234565:token3:tok1:v12:Hello World!e1:t2:aa1:y1:re",
+        );
+        match response.body {
+            MessageBody::Response(Response {
+                item: Some(Item::Immutable(item)),
+                ..
+            }) => assert_eq!(item.encoded(), b"12:Hello World!"),
+            _ => panic!("Expected an immutable item"),
+        }
+
+        let mut oversized = b"d1:ad2:id20:abcdefghij01234567895:token1:x1:v1000:".to_vec();
+        oversized.extend_from_slice(&[b'x'; 1000]);
+        oversized.extend_from_slice(b"e1:q3:put1:t2:aa1:y1:qe");
+        assert_eq!(
+            Message::from_bytes(&oversized).unwrap_err().to_string(),
+            "a.v: must not exceed 1000 bytes when encoded"
+        );
+    }
+
+    #[test]
+    fn test_item_value_kept_verbatim() {
+        // `v` with its keys out of order is hashed and signed as sent
+        let input = b"d1:rd2:id20:mnopqrstuvwxyz1234561:vd1:bi1e1:ai2eee1:t2:aa1:y1:re";
+        let item = |message: Message| match message.body {
+            MessageBody::Response(Response {
+                item: Some(Item::Immutable(item)),
+                ..
+            }) => item,
+            _ => panic!("Expected an immutable item"),
+        };
+        let raw = item(Message::from_bytes(input).unwrap());
+        assert_eq!(raw.encoded(), b"d1:bi1e1:ai2ee");
+        let decoded = item(Message::from_bencode(&parse_document(input).unwrap()).unwrap());
+        assert_eq!(decoded.encoded(), b"d1:ai2e1:bi1ee");
+
+        // Mutable item keys without `v` are not an item and are kept as is
+        let input = b"d1:rd2:id20:mnopqrstuvwxyz1234563:seqi4e3:sig1:xe1:t2:aa1:y1:re";
+        let message = round_trip(input);
+        match message.body {
+            MessageBody::Response(response) => {
+                assert_eq!(response.item, None);
+                assert_eq!(response.extra.get(&b"seq"[..]), Some(&b"i4e".to_vec()));
+                assert_eq!(response.extra.get(&b"sig"[..]), Some(&b"1:x".to_vec()));
+            }
+            _ => panic!("Expected response"),
+        }
+    }
+
+    #[cfg(feature = "sign")]
+    #[test]
+    fn test_non_canonical_mutable_item_verifies() {
+        use crate::dht_item::signing_buffer;
+        use ed25519_dalek::Signer;
+
+        let key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+        let signature = key.sign(&signing_buffer(b"", 1, b"d1:bi1e1:ai2ee"));
+        let mut input = b"d1:rd2:id20:mnopqrstuvwxyz1234561:k32:".to_vec();
+        input.extend_from_slice(&key.verifying_key().to_bytes());
+        input.extend_from_slice(b"3:seqi1e3:sig64:");
+        input.extend_from_slice(&signature.to_bytes());
+        input.extend_from_slice(b"1:vd1:bi1e1:ai2eee1:t2:aa1:y1:re");
+
+        match Message::from_bytes(&input).unwrap().body {
+            MessageBody::Response(Response {
+                item: Some(Item::Mutable(item)),
+                ..
+            }) => item.verify().unwrap(),
+            _ => panic!("Expected a mutable item"),
+        }
+    }
+
+    #[test]
+    fn test_invalid_messages() {
+        let check = |input: &[u8], expected: &str| {
+            assert_eq!(
+                Message::from_bytes(input).unwrap_err().to_string(),
+                expected
+            );
+        };
+
+        check(b"d1:t2:aa1:y1:xe", "y: unknown message type");
+        check(b"d1:y1:qe", "q: missing");
+        check(
+            b"d1:ad2:id3:abce1:q4:ping1:t2:aa1:y1:qe",
+            "a.id: must be 20 bytes long",
+        );
+        check(
+            b"d1:rd2:id20:abcdefghij01234567895:nodes3:abce1:t2:aa1:y1:re",
+            "r.nodes: length must be a multiple of 26",
+        );
+        check(
+            b"d1:ad2:id20:abcdefghij01234567899:info_hash20:mnopqrstuvwxyz1234564:porti70000e5:token1:xe1:q13:announce_peer1:t2:aa1:y1:qe",
+            "a.port: must be a valid port",
+        );
+        check(
+            b"d1:ad2:id20:abcdefghij01234567895:token1:xe1:q3:put1:t2:aa1:y1:qe",
+            "a.v: missing",
+        );
+    }
+}
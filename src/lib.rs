@@ -1,7 +1,41 @@
 pub mod byte_string;
+pub mod canonical;
 pub mod common;
+pub mod compact;
+#[cfg(feature = "hash")]
+pub mod create;
+pub mod dht_item;
 pub mod dictionary;
+pub mod diff;
+pub mod edit;
 pub mod encoder;
+pub mod encoding;
+pub mod explain;
+pub mod extension;
+pub mod fastresume;
+pub mod fields;
+pub mod file_tree;
+#[cfg(feature = "hash")]
+pub mod infohash;
 pub mod integer;
+pub mod json;
+pub mod krpc;
 pub mod list;
+pub mod magnet;
+#[cfg(feature = "hash")]
+pub mod merkle;
 pub mod parser;
+pub mod patch;
+pub mod path;
+pub mod pex;
+pub mod resume;
+pub mod schema;
+pub mod signature;
+pub mod span;
+pub mod tiers;
+pub mod torrent;
+pub mod tracker;
+pub mod update;
+#[cfg(feature = "hash")]
+pub mod verify;
+pub mod webseed;
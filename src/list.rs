@@ -7,7 +7,7 @@ use crate::common::{bencode_value, BencodeValue};
 type Res<T, U> = IResult<T, U, VerboseError<T>>;
 
 // Parse a bencode list
-pub fn list(input: &[u8]) -> Res<&[u8], Vec<BencodeValue>> {
+pub fn list(input: &[u8]) -> Res<&[u8], Vec<BencodeValue<'_>>> {
     delimited(char('l'), many0(bencode_value), char('e'))(input)
 }
 
@@ -0,0 +1,425 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+#[cfg(feature = "hash")]
+use crate::common::BencodeValue;
+use crate::encoding::{
+    decode_hex, percent_decode, percent_encode, strip_prefix_ignore_case, to_hex,
+};
+#[cfg(feature = "hash")]
+use crate::fields::{parse_document, FieldError};
+#[cfg(feature = "hash")]
+use crate::infohash::InfoHash;
+#[cfg(feature = "hash")]
+use crate::torrent::Metainfo;
+
+/// Multihash prefix of a SHA-256 digest: function code 0x12, length 32
+const SHA256_MULTIHASH_PREFIX: [u8; 2] = [0x12, 0x20];
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Error type for magnet URI parsing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MagnetError {
+    NotMagnet,
+    MissingHash,
+    MissingPublicKey,
+    InvalidHash(String),
+    InvalidEncoding(String),
+    InvalidParameter { name: String, value: String },
+}
+
+impl fmt::Display for MagnetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MagnetError::NotMagnet => write!(f, "Not a magnet URI"),
+            MagnetError::MissingHash => write!(f, "No btih or btmh exact topic"),
+            MagnetError::MissingPublicKey => write!(f, "No btpk exact source"),
+            MagnetError::InvalidHash(xt) => write!(f, "Invalid exact topic: {}", xt),
+            MagnetError::InvalidEncoding(s) => write!(f, "Invalid percent-encoding: {}", s),
+            MagnetError::InvalidParameter { name, value } => {
+                write!(f, "Invalid value for {}: {}", name, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MagnetError {}
+
+/// A BEP 9 magnet link
+///
+/// Parameters that are not understood are kept in `extra`, decoded, in the
+/// order they appeared.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Magnet {
+    /// v1 info-hash (`xt=urn:btih:`)
+    pub btih: Option<[u8; 20]>,
+    /// v2 info-hash (`xt=urn:btmh:` with a SHA-256 multihash)
+    pub btmh: Option<[u8; 32]>,
+    /// `dn`
+    pub display_name: Option<String>,
+    /// `tr`
+    pub trackers: Vec<String>,
+    /// `ws`
+    pub web_seeds: Vec<String>,
+    /// `xl`
+    pub exact_length: Option<u64>,
+    /// BEP 53 `so`, file indices to download
+    pub select_only: Vec<RangeInclusive<usize>>,
+    pub extra: Vec<(String, String)>,
+}
+
+impl Magnet {
+    /// Magnet link for a complete .torrent file
+    ///
+    /// The info-hashes are taken over the raw `info` bytes, like
+    /// `InfoHash::from_torrent`.
+    #[cfg(feature = "hash")]
+    pub fn from_torrent(input: &[u8]) -> Result<Magnet, FieldError> {
+        let metainfo = Metainfo::from_bytes(input)?;
+        let hash = InfoHash::from_torrent(input)?;
+        Ok(Magnet::from_metainfo(&metainfo, &hash))
+    }
+
+    /// Magnet link for a parsed torrent whose info-hashes are already known
+    #[cfg(feature = "hash")]
+    pub fn from_metainfo(metainfo: &Metainfo, hash: &InfoHash) -> Magnet {
+        let mut trackers: Vec<String> = Vec::new();
+        let tiers = metainfo.announce_list.iter().flatten();
+        for url in metainfo.announce.iter().chain(tiers) {
+            if !trackers.contains(url) {
+                trackers.push(url.clone());
+            }
+        }
+
+        let info = &metainfo.info;
+        let exact_length = match &info.file_tree {
+            Some(tree) => tree.total_length(),
+            None => info.total_length(),
+        };
+
+        Magnet {
+            btih: hash.v1,
+            btmh: hash.v2,
+            display_name: Some(info.name_lossy().into_owned()),
+            trackers,
+            web_seeds: url_list(metainfo),
+            exact_length: Some(exact_length),
+            select_only: Vec::new(),
+            extra: Vec::new(),
+        }
+    }
+
+    /// Parse a `magnet:?` URI
+    pub fn parse(uri: &str) -> Result<Magnet, MagnetError> {
+        let query = match uri.get(..8) {
+            Some(scheme) if scheme.eq_ignore_ascii_case("magnet:?") => &uri[8..],
+            _ => return Err(MagnetError::NotMagnet),
+        };
+
+        let mut magnet = Magnet::default();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (raw_name, raw_value) = pair.split_once('=').unwrap_or((pair, ""));
+            let name = decode_component(raw_name)?;
+            let value = decode_component(raw_value)?;
+            let invalid = || MagnetError::InvalidParameter {
+                name: name.clone(),
+                value: value.clone(),
+            };
+
+            // BEP 9 allows numbered variants like `tr.1` for repeated parameters
+            let base = name.split('.').next().unwrap_or("");
+            match base {
+                "xt" => magnet.parse_exact_topic(&value)?,
+                "dn" => magnet.display_name = Some(value),
+                "tr" => magnet.trackers.push(value),
+                "ws" => magnet.web_seeds.push(value),
+                "xl" => magnet.exact_length = Some(value.parse().map_err(|_| invalid())?),
+                "so" => magnet.select_only = parse_selection(&value).ok_or_else(invalid)?,
+                _ => magnet.extra.push((name, value)),
+            }
+        }
+
+        if magnet.btih.is_none() && magnet.btmh.is_none() {
+            return Err(MagnetError::MissingHash);
+        }
+        Ok(magnet)
+    }
+
+    fn parse_exact_topic(&mut self, xt: &str) -> Result<(), MagnetError> {
+        let invalid = || MagnetError::InvalidHash(xt.to_string());
+        if let Some(hash) = strip_prefix_ignore_case(xt, "urn:btih:") {
+            let bytes = match hash.len() {
+                40 => decode_hex(hash),
+                32 => decode_base32(hash),
+                _ => None,
+            };
+            self.btih = Some(bytes.and_then(|b| b.try_into().ok()).ok_or_else(invalid)?);
+        } else if let Some(hash) = strip_prefix_ignore_case(xt, "urn:btmh:") {
+            let bytes = decode_hex(hash).ok_or_else(invalid)?;
+            match bytes.strip_prefix(&SHA256_MULTIHASH_PREFIX[..]) {
+                Some(digest) => self.btmh = Some(digest.try_into().map_err(|_| invalid())?),
+                None => return Err(invalid()),
+            }
+        } else {
+            return Err(invalid());
+        }
+        Ok(())
+    }
+}
+
+/// Writes the URI, hashes in lowercase hex and every other value percent-encoded
+impl fmt::Display for Magnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut params: Vec<(&str, String)> = Vec::new();
+        if let Some(btih) = &self.btih {
+            params.push(("xt", format!("urn:btih:{}", to_hex(btih))));
+        }
+        if let Some(btmh) = &self.btmh {
+            let prefix = to_hex(&SHA256_MULTIHASH_PREFIX);
+            params.push(("xt", format!("urn:btmh:{}{}", prefix, to_hex(btmh))));
+        }
+        if let Some(name) = &self.display_name {
+            params.push(("dn", percent_encode(name.as_bytes())));
+        }
+        if let Some(length) = self.exact_length {
+            params.push(("xl", length.to_string()));
+        }
+        for tracker in &self.trackers {
+            params.push(("tr", percent_encode(tracker.as_bytes())));
+        }
+        for seed in &self.web_seeds {
+            params.push(("ws", percent_encode(seed.as_bytes())));
+        }
+        if !self.select_only.is_empty() {
+            params.push(("so", format_selection(&self.select_only)));
+        }
+
+        let extra = self.extra.iter().map(|(name, value)| {
+            (
+                percent_encode(name.as_bytes()),
+                percent_encode(value.as_bytes()),
+            )
+        });
+        let query: Vec<String> = params
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .chain(extra)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        write!(f, "magnet:?{}", query.join("&"))
+    }
+}
+
+impl std::str::FromStr for Magnet {
+    type Err = MagnetError;
+
+    fn from_str(uri: &str) -> Result<Magnet, MagnetError> {
+        Magnet::parse(uri)
+    }
+}
+
+// Web seeds from a `url-list` key, which may be one URL or a list of them
+#[cfg(feature = "hash")]
+fn url_list(metainfo: &Metainfo) -> Vec<String> {
+    let raw = match metainfo.extra.get(&b"url-list"[..]) {
+        Some(raw) => raw,
+        None => return Vec::new(),
+    };
+    let urls = match parse_document(raw) {
+        Ok(BencodeValue::ByteString(url)) => vec![url],
+        Ok(BencodeValue::List(list)) => list
+            .into_iter()
+            .filter_map(|v| match v {
+                BencodeValue::ByteString(url) => Some(url),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    urls.into_iter()
+        .filter(|url| !url.is_empty())
+        .filter_map(|url| String::from_utf8(url.to_vec()).ok())
+        .collect()
+}
+
+fn parse_selection(value: &str) -> Option<Vec<RangeInclusive<usize>>> {
+    value
+        .split(',')
+        .map(|part| match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                if start <= end {
+                    Some(start..=end)
+                } else {
+                    None
+                }
+            }
+            None => part.parse().ok().map(|index| index..=index),
+        })
+        .collect()
+}
+
+fn format_selection(ranges: &[RangeInclusive<usize>]) -> String {
+    ranges
+        .iter()
+        .map(|range| {
+            if range.start() == range.end() {
+                range.start().to_string()
+            } else {
+                format!("{}-{}", range.start(), range.end())
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// A query parameter name or value, which must decode to UTF-8
+pub(crate) fn decode_component(s: &str) -> Result<String, MagnetError> {
+    percent_decode(s).ok_or_else(|| MagnetError::InvalidEncoding(s.to_string()))
+}
+
+// RFC 4648 base32 without padding, as used by old-style btih links
+fn decode_base32(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in s.bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BTIH_HEX: &str = "c12fe1c06bba254a9dc9f519b335aa7c1367a88a";
+
+    #[test]
+    fn test_parse() {
+        let uri = format!(
+            "magnet:?xt=urn:btih:{}&dn=Some%20File&tr=udp%3A%2F%2Ftracker.example%3A80&tr.1=http://b/announce&xl=1234&so=0,2,4-6&x.pe=1.2.3.4%3A5",
+            BTIH_HEX.to_uppercase()
+        );
+        let magnet = Magnet::parse(&uri).unwrap();
+        assert_eq!(to_hex(&magnet.btih.unwrap()), BTIH_HEX);
+        assert_eq!(magnet.btmh, None);
+        assert_eq!(magnet.display_name.as_deref(), Some("Some File"));
+        assert_eq!(
+            magnet.trackers,
+            vec!["udp://tracker.example:80", "http://b/announce"]
+        );
+        assert_eq!(magnet.exact_length, Some(1234));
+        assert_eq!(magnet.select_only, vec![0..=0, 2..=2, 4..=6]);
+        assert_eq!(
+            magnet.extra,
+            vec![("x.pe".to_string(), "1.2.3.4:5".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let magnet = Magnet {
+            btih: Some([0xab; 20]),
+            btmh: Some([0x01; 32]),
+            display_name: Some("a & b/c?".to_string()),
+            trackers: vec!["http://t.example/announce?k=1&x=2".to_string()],
+            web_seeds: vec!["https://seed.example/files/".to_string()],
+            exact_length: Some(42),
+            select_only: vec![1..=1, 3..=5],
+            extra: vec![("kt".to_string(), "one two".to_string())],
+        };
+        let uri = magnet.to_string();
+        assert!(uri.starts_with(&format!(
+            "magnet:?xt=urn:btih:{}&xt=urn:btmh:1220{}&dn=a%20%26%20b%2Fc%3F&xl=42",
+            "ab".repeat(20),
+            "01".repeat(32)
+        )));
+        assert!(uri.ends_with("&so=1,3-5&kt=one%20two"));
+        assert_eq!(uri.parse::<Magnet>().unwrap(), magnet);
+    }
+
+    #[test]
+    fn test_base32_btih() {
+        let magnet = Magnet::parse("magnet:?xt=urn:btih:YEX6DQDLXISUVHOJ6UM3GNNKPQJWPKEK").unwrap();
+        assert_eq!(to_hex(&magnet.btih.unwrap()), BTIH_HEX);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            Magnet::parse("http://example.com"),
+            Err(MagnetError::NotMagnet)
+        );
+        assert_eq!(Magnet::parse("magnet:?dn=x"), Err(MagnetError::MissingHash));
+        assert_eq!(
+            Magnet::parse("magnet:?xt=urn:btih:abcd"),
+            Err(MagnetError::InvalidHash("urn:btih:abcd".to_string()))
+        );
+        let sha1_multihash = format!("urn:btmh:1114{}", "00".repeat(20));
+        assert_eq!(
+            Magnet::parse(&format!("magnet:?xt={}", sha1_multihash)),
+            Err(MagnetError::InvalidHash(sha1_multihash))
+        );
+        assert_eq!(
+            Magnet::parse(&format!("magnet:?xt=urn:btih:{}&dn=%zz", BTIH_HEX)),
+            Err(MagnetError::InvalidEncoding("%zz".to_string()))
+        );
+        assert_eq!(
+            Magnet::parse(&format!("magnet:?xt=urn:btih:{}&dn=%+f", BTIH_HEX)),
+            Err(MagnetError::InvalidEncoding("%+f".to_string()))
+        );
+        let signed = format!("urn:btih:+f{}", &BTIH_HEX[2..]);
+        assert_eq!(
+            Magnet::parse(&format!("magnet:?xt={}", signed)),
+            Err(MagnetError::InvalidHash(signed))
+        );
+        assert!(matches!(
+            Magnet::parse(&format!("magnet:?xt=urn:btih:{}&so=3-1", BTIH_HEX)),
+            Err(MagnetError::InvalidParameter { .. })
+        ));
+    }
+
+    #[cfg(feature = "hash")]
+    #[test]
+    fn test_from_torrent() {
+        let mut input = Vec::new();
+        input.extend_from_slice(
+            b"d8:announce8:http://a13:announce-listll8:http://ael8:http://bee4:info",
+        );
+        input.extend_from_slice(b"d6:lengthi5e4:name5:a b c12:piece lengthi16384e6:pieces20:");
+        input.extend_from_slice(&[7; 20]);
+        input.extend_from_slice(b"e8:url-listl9:http://w/ee");
+
+        let hash = InfoHash::from_torrent(&input).unwrap();
+        let magnet = Magnet::from_torrent(&input).unwrap();
+        assert_eq!(
+            magnet.to_string(),
+            format!(
+                "magnet:?xt=urn:btih:{}&dn=a%20b%20c&xl=5&tr=http%3A%2F%2Fa&tr=http%3A%2F%2Fb&ws=http%3A%2F%2Fw%2F",
+                to_hex(&hash.v1.unwrap())
+            )
+        );
+        assert_eq!(Magnet::parse(&magnet.to_string()).unwrap(), magnet);
+
+        // A bad entry only loses itself, and `httpseeds` is not a `ws`
+        let input = [
+            &input[..input.len() - 2],
+            b"i1e0:9:http://x/e9:httpseedsi1ee",
+        ]
+        .concat();
+        let magnet = Magnet::from_torrent(&input).unwrap();
+        assert_eq!(magnet.web_seeds, vec!["http://w/", "http://x/"]);
+    }
+}
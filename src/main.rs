@@ -1,60 +1,697 @@
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::str;
+
+use acornbencode::canonical::canonicalize;
 use acornbencode::common::BencodeValue;
-use acornbencode::encoder;
-use acornbencode::parser::parse_bencode;
-use std::collections::BTreeMap;
-
-fn main() {
-    // -------------------------------------------------------------------------
-    // Example: parsing
-    // -------------------------------------------------------------------------
-    let input = b"d3:fooi42e3:bar4:spame";
-    println!("Parsing: {}", String::from_utf8_lossy(input));
-    match parse_bencode(input) {
-        Ok((_, value)) => println!("Parsed value: {:?}", value),
-        Err(e) => println!("Error parsing: {:?}", e),
-    }
-
-    // -------------------------------------------------------------------------
-    // Example: encoding
-    // -------------------------------------------------------------------------
-    let integer = BencodeValue::Integer(42);
-    match encoder::encode_to_string(&integer) {
-        Ok(encoded) => println!("Encoded integer: {}", encoded),
-        Err(e) => println!("Error encoding: {:?}", e),
-    }
-
-    let bytestring = BencodeValue::ByteString(b"spam");
-    match encoder::encode_to_string(&bytestring) {
-        Ok(encoded) => println!("Encoded string: {}", encoded),
-        Err(e) => println!("Error encoding: {:?}", e),
-    }
-
-    let list = BencodeValue::List(vec![
-        BencodeValue::Integer(1),
-        BencodeValue::Integer(2),
-        BencodeValue::Integer(3),
-    ]);
-    match encoder::encode_to_string(&list) {
-        Ok(encoded) => println!("Encoded list: {}", encoded),
-        Err(e) => println!("Error encoding: {:?}", e),
-    }
-
-    let mut dict = BTreeMap::new();
-    dict.insert(b"key".as_slice(), BencodeValue::ByteString(b"value"));
-    dict.insert(b"num".as_slice(), BencodeValue::Integer(42));
-    let dictionary = BencodeValue::Dictionary(dict);
-    match encoder::encode_to_string(&dictionary) {
-        Ok(encoded) => println!("Encoded dictionary: {}", encoded),
-        Err(e) => println!("Error encoding: {:?}", e),
-    }
-
-    // -------------------------------------------------------------------------
-    // Example: binary data
-    // -------------------------------------------------------------------------
-    let binary_data = vec![0xC0, 0x7F]; // Invalid UTF-8 bytes
-    let binary_value = BencodeValue::ByteString(&binary_data);
-    match encoder::encode_to_bytes(&binary_value) {
-        Ok(encoded) => println!("Encoded binary data length: {}", encoded.len()),
-        Err(e) => println!("Error encoding: {:?}", e),
+use acornbencode::diff::{attach_spans, diff, Change};
+use acornbencode::edit::{delete_value, set_value};
+use acornbencode::fields::{parse_document, parse_strict};
+use acornbencode::json::{from_json_str, to_json, JsonMode};
+use acornbencode::path::Path;
+use acornbencode::span::raw_value;
+
+const USAGE: &str = "\
+Usage: acornbencode <command> [options] [FILE]
+
+Commands:
+  show [FILE]              Print the value as an indented tree
+  validate [FILE...]       Check that each input is canonical bencode
+  json [--friendly] [FILE] Convert to JSON (lossless unless --friendly)
+  get [--raw] FILE PATH    Print the value at PATH (exact bytes with --raw)
+  set [--int|--json] [-o OUT] FILE PATH VALUE
+                           Set PATH to VALUE, a string unless --int or --json
+  delete [-o OUT] FILE PATH
+                           Remove the dictionary entry or list element at PATH
+  canonicalize [--check] [-o OUT] [FILE]
+                           Re-encode leniently parsed input canonically,
+                           reporting each irregularity; --check only reports
+  diff [--json] OLD NEW    List added, removed and changed values with byte
+                           ranges, as JSON lines with --json
+
+FILE defaults to standard input; `-` also means standard input.
+
+PATH uses the same syntax as error messages: `info.name`, `announce-list[0][0]`,
+with `\\.` for a literal dot in a key. The root is `\"\"`.
+`set` and `delete` rewrite FILE in place unless -o is given, or print the
+result when reading standard input. Bytes outside the edited value are kept.
+
+Exit status is 0 on success, 1 when an input is not valid bencode or
+`canonicalize --check` found irregularities, and 2 for usage or I/O errors.
+`diff` follows diff(1): 1 when the inputs differ, and 2 when either of them
+cannot be read or is not valid bencode.";
+
+/// Byte strings that are not UTF-8 show at most this many bytes as hex
+const HEX_PREVIEW: usize = 32;
+
+#[derive(Debug)]
+enum CliError {
+    Usage(String),
+    Io(String, io::Error),
+    /// The input was read but is not acceptable; the message is already formatted
+    Invalid(String),
+    /// Like `Invalid`, but exits with 2: for `diff`, where 1 means the inputs
+    /// differ, and for `validate` when some input could not be read
+    Trouble(String),
+}
+
+impl CliError {
+    fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Invalid(_) => 1,
+            CliError::Usage(_) | CliError::Io(..) | CliError::Trouble(_) => 2,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            match &error {
+                CliError::Usage(message) => eprintln!("{}\n\n{}", message, USAGE),
+                CliError::Io(name, e) => eprintln!("{}: {}", name, e),
+                CliError::Invalid(message) | CliError::Trouble(message) => {
+                    eprintln!("{}", message)
+                }
+            }
+            ExitCode::from(error.exit_code())
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<(), CliError> {
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => return Err(CliError::Usage("No command given".to_string())),
+    };
+
+    match command {
+        "show" => {
+            let (name, input) = read_input(single_file(rest)?)?;
+            let value = parse_document(&input)
+                .map_err(|e| CliError::Invalid(format!("{}: {}", name, e)))?;
+            write_stdout(&render_tree(&value))
+        }
+        "validate" => validate(rest),
+        "json" => {
+            let mut mode = JsonMode::Lossless;
+            let mut files = Vec::new();
+            for arg in rest {
+                match arg.as_str() {
+                    "--friendly" => mode = JsonMode::Friendly,
+                    _ => files.push(arg.clone()),
+                }
+            }
+            let (name, input) = read_input(single_file(&files)?)?;
+            let value = parse_document(&input)
+                .map_err(|e| CliError::Invalid(format!("{}: {}", name, e)))?;
+            let json = serde_json::to_string_pretty(&to_json(&value, mode))
+                .map_err(|e| CliError::Invalid(format!("{}: {}", name, e)))?;
+            write_stdout(&format!("{}\n", json))
+        }
+        "get" => get(rest),
+        "set" | "delete" => edit(command, rest),
+        "canonicalize" => canonicalize_file(rest),
+        "diff" => diff_files(rest),
+        "help" | "-h" | "--help" => write_stdout(&format!("{}\n", USAGE)),
+        other => Err(CliError::Usage(format!("Unknown command: {}", other))),
+    }
+}
+
+// Every input is checked and reported, and the exit status reflects all of them
+fn validate(files: &[String]) -> Result<(), CliError> {
+    let files = if files.is_empty() {
+        vec!["-".to_string()]
+    } else {
+        files.to_vec()
+    };
+
+    let mut failures = 0;
+    let mut unreadable = 0;
+    for file in &files {
+        let (name, input) = match read_input(Some(file)) {
+            Ok(input) => input,
+            Err(CliError::Io(name, e)) => {
+                eprintln!("{}: {}", name, e);
+                unreadable += 1;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        match parse_strict(&input) {
+            Ok(_) => println!("{}: ok", name),
+            Err(e) => {
+                eprintln!("{}: {}", name, e);
+                failures += 1;
+            }
+        }
+    }
+
+    let message = format!(
+        "{} of {} inputs failed validation",
+        failures + unreadable,
+        files.len()
+    );
+    match (failures, unreadable) {
+        (0, 0) => Ok(()),
+        (_, 0) => Err(CliError::Invalid(message)),
+        _ => Err(CliError::Trouble(message)),
+    }
+}
+
+fn get(args: &[String]) -> Result<(), CliError> {
+    let mut raw = false;
+    let mut positional = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--raw" => raw = true,
+            option if option.starts_with("--") => {
+                return Err(CliError::Usage(format!("Unknown option: {}", option)))
+            }
+            _ => positional.push(arg),
+        }
+    }
+    let (file, path) = match positional.as_slice() {
+        [file, path] => (*file, parse_path(path)?),
+        _ => return Err(CliError::Usage("Expected FILE and PATH".to_string())),
+    };
+
+    let (name, input) = read_input(Some(file))?;
+    let invalid = |message: String| CliError::Invalid(format!("{}: {}", name, message));
+    let bytes = raw_value(&input, &path)
+        .map_err(|e| invalid(e.to_string()))?
+        .ok_or_else(|| invalid(format!("{}: missing", path)))?;
+    if raw {
+        return io::stdout()
+            .write_all(bytes)
+            .map_err(|e| CliError::Io("<stdout>".to_string(), e));
+    }
+    let value = parse_document(bytes).map_err(|e| invalid(e.to_string()))?;
+    write_stdout(&render_tree(&value))
+}
+
+// Shared by `set` and `delete`, which differ only in the trailing VALUE
+fn edit(command: &str, args: &[String]) -> Result<(), CliError> {
+    let mut encoding = "string";
+    let mut output = None;
+    let mut positional = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--int" | "--json" if command == "set" => encoding = &arg[2..],
+            "-o" => match iter.next() {
+                Some(out) => output = Some(out),
+                None => return Err(CliError::Usage("-o needs a file name".to_string())),
+            },
+            option if option.starts_with("--") => {
+                return Err(CliError::Usage(format!("Unknown option: {}", option)))
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let (file, path, value) = match (command, positional.as_slice()) {
+        ("set", [file, path, value]) => (*file, parse_path(path)?, Some(*value)),
+        ("delete", [file, path]) => (*file, parse_path(path)?, None),
+        ("set", _) => return Err(CliError::Usage("Expected FILE, PATH and VALUE".to_string())),
+        _ => return Err(CliError::Usage("Expected FILE and PATH".to_string())),
+    };
+
+    let (name, input) = read_input(Some(file))?;
+    let result = match value {
+        Some(value) => {
+            let encoded = encode_argument(value, encoding)?;
+            set_value(&input, &path, &encoded)
+        }
+        None => delete_value(&input, &path),
+    }
+    .map_err(|e| CliError::Invalid(format!("{}: {}", name, e)))?;
+
+    match output.unwrap_or(file).as_str() {
+        "-" => io::stdout()
+            .write_all(&result)
+            .map_err(|e| CliError::Io("<stdout>".to_string(), e)),
+        target => write_replacing(target, &result),
+    }
+}
+
+fn canonicalize_file(args: &[String]) -> Result<(), CliError> {
+    let mut check = false;
+    let mut output = None;
+    let mut files = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--check" => check = true,
+            "-o" => match iter.next() {
+                Some(out) => output = Some(out),
+                None => return Err(CliError::Usage("-o needs a file name".to_string())),
+            },
+            _ => files.push(arg.clone()),
+        }
+    }
+    let (name, input) = read_input(single_file(&files)?)?;
+    let (canonical, issues) =
+        canonicalize(&input).map_err(|e| CliError::Invalid(format!("{}: {}", name, e)))?;
+    for issue in &issues {
+        eprintln!("{}: {}", name, issue);
+    }
+
+    if check {
+        return match issues.len() {
+            0 => Ok(()),
+            n => Err(CliError::Invalid(format!("{}: {} irregularities", name, n))),
+        };
+    }
+    match output.map(String::as_str) {
+        None | Some("-") => io::stdout()
+            .write_all(&canonical)
+            .map_err(|e| CliError::Io("<stdout>".to_string(), e)),
+        Some(target) => write_replacing(target, &canonical),
+    }
+}
+
+fn diff_files(args: &[String]) -> Result<(), CliError> {
+    let mut json = false;
+    let mut files = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            option if option.starts_with("--") => {
+                return Err(CliError::Usage(format!("Unknown option: {}", option)))
+            }
+            _ => files.push(arg),
+        }
+    }
+    let (old_file, new_file) = match files.as_slice() {
+        [old, new] if !(old.as_str() == "-" && new.as_str() == "-") => (*old, *new),
+        _ => return Err(CliError::Usage("Expected OLD and NEW files".to_string())),
+    };
+
+    let (old_name, old_input) = read_input(Some(old_file))?;
+    let (new_name, new_input) = read_input(Some(new_file))?;
+    let old = parse_document(&old_input)
+        .map_err(|e| CliError::Trouble(format!("{}: {}", old_name, e)))?;
+    let new = parse_document(&new_input)
+        .map_err(|e| CliError::Trouble(format!("{}: {}", new_name, e)))?;
+    let mut changes = diff(&old, &new);
+    // Both inputs parsed, so their spans can be computed
+    attach_spans(&mut changes, &old_input, &new_input)
+        .map_err(|e| CliError::Trouble(e.to_string()))?;
+
+    let mut out = String::new();
+    for change in &changes {
+        if json {
+            let _ = writeln!(out, "{}", change_json(change));
+        } else {
+            out.push_str(&render_change(change));
+        }
+    }
+    write_stdout(&out)?;
+
+    match changes.len() {
+        0 => Ok(()),
+        1 => Err(CliError::Invalid("1 difference".to_string())),
+        n => Err(CliError::Invalid(format!("{} differences", n))),
+    }
+}
+
+// `~ info.name: "a" -> "ab"  [14..17 -> 14..18]`
+fn render_change(change: &Change) -> String {
+    let path = if change.path.is_root() {
+        "<root>".to_string()
+    } else {
+        change.path.to_string()
+    };
+    let span = |span: &Option<std::ops::Range<usize>>| {
+        span.as_ref()
+            .map_or_else(String::new, |r| format!("{}..{}", r.start, r.end))
+    };
+    match (change.old, change.new) {
+        (Some(old), Some(new)) => format!(
+            "~ {}: {} -> {}  [{} -> {}]\n",
+            path,
+            render_inline(old),
+            render_inline(new),
+            span(&change.old_span),
+            span(&change.new_span)
+        ),
+        (Some(old), None) => format!(
+            "- {}: {}  [{}]\n",
+            path,
+            render_inline(old),
+            span(&change.old_span)
+        ),
+        (None, Some(new)) => format!(
+            "+ {}: {}  [{}]\n",
+            path,
+            render_inline(new),
+            span(&change.new_span)
+        ),
+        (None, None) => String::new(),
+    }
+}
+
+fn change_json(change: &Change) -> serde_json::Value {
+    let span = |span: &Option<std::ops::Range<usize>>| {
+        span.as_ref().map(|r| serde_json::json!([r.start, r.end]))
+    };
+    serde_json::json!({
+        "kind": change.kind.to_string(),
+        "path": change.path.to_string(),
+        "old": change.old.map(|v| to_json(v, JsonMode::Lossless)),
+        "new": change.new.map(|v| to_json(v, JsonMode::Lossless)),
+        "old_span": span(&change.old_span),
+        "new_span": span(&change.new_span),
+    })
+}
+
+// Scalars as in the tree view, containers summarised by size
+fn render_inline(value: &BencodeValue) -> String {
+    match value {
+        BencodeValue::Integer(i) => i.to_string(),
+        BencodeValue::ByteString(bytes) => render_bytes(bytes),
+        BencodeValue::List(list) => format!("[{} items]", list.len()),
+        BencodeValue::Dictionary(dict) => format!("{{{} keys}}", dict.len()),
+    }
+}
+
+fn parse_path(arg: &str) -> Result<Path, CliError> {
+    arg.parse()
+        .map_err(|e| CliError::Usage(format!("{}: {}", arg, e)))
+}
+
+fn encode_argument(value: &str, encoding: &str) -> Result<Vec<u8>, CliError> {
+    match encoding {
+        "int" => {
+            let n: i64 = value
+                .parse()
+                .map_err(|_| CliError::Usage(format!("Not an integer: {}", value)))?;
+            Ok(format!("i{}e", n).into_bytes())
+        }
+        "json" => {
+            from_json_str(value).map_err(|e| CliError::Usage(format!("Invalid JSON value: {}", e)))
+        }
+        _ => {
+            let mut encoded = format!("{}:", value.len()).into_bytes();
+            encoded.extend_from_slice(value.as_bytes());
+            Ok(encoded)
+        }
+    }
+}
+
+// Written beside the target and renamed over it so a failed write leaves the
+// original file intact
+fn write_replacing(target: &str, contents: &[u8]) -> Result<(), CliError> {
+    let io_error = |e| CliError::Io(target.to_string(), e);
+    let mut temp = PathBuf::from(target).into_os_string();
+    temp.push(".tmp");
+    fs::write(&temp, contents).map_err(io_error)?;
+    fs::rename(&temp, target).map_err(|e| {
+        let _ = fs::remove_file(&temp);
+        io_error(e)
+    })
+}
+
+fn single_file(args: &[String]) -> Result<Option<&String>, CliError> {
+    if let Some(option) = args.iter().find(|a| a.starts_with("--")) {
+        return Err(CliError::Usage(format!("Unknown option: {}", option)));
+    }
+    match args {
+        [] => Ok(None),
+        [file] => Ok(Some(file)),
+        _ => Err(CliError::Usage("Expected at most one file".to_string())),
+    }
+}
+
+// Returns a display name for messages along with the contents
+fn read_input(file: Option<&String>) -> Result<(String, Vec<u8>), CliError> {
+    match file.map(String::as_str) {
+        None | Some("-") => {
+            let mut input = Vec::new();
+            io::stdin()
+                .read_to_end(&mut input)
+                .map_err(|e| CliError::Io("<stdin>".to_string(), e))?;
+            Ok(("<stdin>".to_string(), input))
+        }
+        Some(path) => {
+            let input = fs::read(path).map_err(|e| CliError::Io(path.to_string(), e))?;
+            Ok((path.to_string(), input))
+        }
+    }
+}
+
+fn write_stdout(output: &str) -> Result<(), CliError> {
+    io::stdout()
+        .write_all(output.as_bytes())
+        .map_err(|e| CliError::Io("<stdout>".to_string(), e))
+}
+
+/// Render a value as an indented tree, one scalar or container edge per line
+fn render_tree(value: &BencodeValue) -> String {
+    let mut out = String::new();
+    render_value(value, 0, &mut out);
+    out.push('\n');
+    out
+}
+
+fn render_value(value: &BencodeValue, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth + 1);
+    match value {
+        BencodeValue::Integer(i) => {
+            let _ = write!(out, "{}", i);
+        }
+        BencodeValue::ByteString(bytes) => out.push_str(&render_bytes(bytes)),
+        BencodeValue::List(list) if list.is_empty() => out.push_str("[]"),
+        BencodeValue::List(list) => {
+            out.push_str("[\n");
+            for item in list {
+                out.push_str(&indent);
+                render_value(item, depth + 1, out);
+                out.push('\n');
+            }
+            let _ = write!(out, "{}]", "  ".repeat(depth));
+        }
+        BencodeValue::Dictionary(dict) if dict.is_empty() => out.push_str("{}"),
+        BencodeValue::Dictionary(dict) => {
+            out.push_str("{\n");
+            for (key, item) in dict {
+                let _ = write!(out, "{}{}: ", indent, render_key(key));
+                render_value(item, depth + 1, out);
+                out.push('\n');
+            }
+            let _ = write!(out, "{}}}", "  ".repeat(depth));
+        }
+    }
+}
+
+// Keys made of printable characters are shown bare
+fn render_key(key: &[u8]) -> String {
+    match str::from_utf8(key) {
+        Ok(s) if !s.is_empty() && s.chars().all(|c| !c.is_control() && c != ':' && c != '"') => {
+            s.to_string()
+        }
+        _ => render_bytes(key),
+    }
+}
+
+fn render_bytes(bytes: &[u8]) -> String {
+    match str::from_utf8(bytes) {
+        Ok(s) => format!("{:?}", s),
+        Err(_) => {
+            let hex: String = bytes
+                .iter()
+                .take(HEX_PREVIEW)
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            let more = if bytes.len() > HEX_PREVIEW { "..." } else { "" };
+            format!("<{} bytes {}{}>", bytes.len(), hex, more)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_render_tree() {
+        let value =
+            parse_document(b"d4:infod6:lengthi5e6:pieces2:\xff\x00e4:listl0:lee1:xdee").unwrap();
+        assert_eq!(
+            render_tree(&value),
+            "{\n  info: {\n    length: 5\n    pieces: <2 bytes ff00>\n  }\n  list: [\n    \"\"\n    []\n  ]\n  x: {}\n}\n"
+        );
+        let value = parse_document(b"d2:a:i1ee").unwrap();
+        assert_eq!(render_tree(&value), "{\n  \"a:\": 1\n}\n");
+    }
+
+    #[test]
+    fn test_usage_errors() {
+        assert!(matches!(run(&[]), Err(CliError::Usage(_))));
+        assert!(matches!(
+            run(&args(&["frobnicate"])),
+            Err(CliError::Usage(_))
+        ));
+        assert!(matches!(
+            run(&args(&["show", "a", "b"])),
+            Err(CliError::Usage(_))
+        ));
+        assert!(matches!(
+            run(&args(&["show", "--tree"])),
+            Err(CliError::Usage(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate_exit_codes() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = dir.path().join("good");
+        let unsorted = dir.path().join("unsorted");
+        fs::write(&good, b"d1:ai1e1:bi2ee").unwrap();
+        fs::write(&unsorted, b"d1:bi2e1:ai1ee").unwrap();
+        let path = |p: &std::path::Path| p.to_string_lossy().into_owned();
+
+        assert!(run(&["validate".to_string(), path(&good)]).is_ok());
+        let error = run(&["validate".to_string(), path(&good), path(&unsorted)]).unwrap_err();
+        assert_eq!(error.exit_code(), 1);
+        let missing = dir.path().join("missing");
+        let error = run(&["validate".to_string(), path(&missing)]).unwrap_err();
+        assert_eq!(error.exit_code(), 2);
+
+        // Later inputs are still checked after one that cannot be read
+        let error = run(&[
+            "validate".to_string(),
+            path(&missing),
+            path(&good),
+            path(&unsorted),
+        ])
+        .unwrap_err();
+        assert!(matches!(
+            error,
+            CliError::Trouble(message) if message == "2 of 3 inputs failed validation"
+        ));
+    }
+
+    #[test]
+    fn test_set_and_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.torrent");
+        let file = file.to_string_lossy().into_owned();
+        fs::write(
+            &file,
+            b"d8:announce3:old13:announce-listll1:aee4:infod4:name1:xee",
+        )
+        .unwrap();
+
+        run(&args(&["set", &file, "announce", "http://t/a"])).unwrap();
+        run(&args(&["set", "--int", &file, "info.private", "1"])).unwrap();
+        run(&args(&["delete", &file, "announce-list"])).unwrap();
+        assert_eq!(
+            fs::read(&file).unwrap(),
+            b"d8:announce10:http://t/a4:infod4:name1:x7:privatei1eee"
+        );
+
+        let copy = dir.path().join("copy").to_string_lossy().into_owned();
+        run(&args(&[
+            "set",
+            "--json",
+            "-o",
+            &copy,
+            &file,
+            "info.name",
+            "[1]",
+        ]))
+        .unwrap();
+        assert_eq!(
+            fs::read(&copy).unwrap(),
+            b"d8:announce10:http://t/a4:infod4:nameli1ee7:privatei1eee"
+        );
+
+        let error = run(&args(&["get", &file, "comment"])).unwrap_err();
+        assert_eq!(error.exit_code(), 1);
+        assert!(matches!(
+            run(&args(&["set", &file, "announce"])),
+            Err(CliError::Usage(_))
+        ));
+        assert!(matches!(
+            run(&args(&["delete", "--int", &file, "announce"])),
+            Err(CliError::Usage(_))
+        ));
+        assert!(matches!(
+            run(&args(&["get", &file, "info["])),
+            Err(CliError::Usage(_))
+        ));
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in").to_string_lossy().into_owned();
+        let output = dir.path().join("out").to_string_lossy().into_owned();
+        fs::write(&input, b"d1:bi01e1:ai-0ee").unwrap();
+
+        let error = run(&args(&["canonicalize", "--check", &input])).unwrap_err();
+        assert_eq!(error.exit_code(), 1);
+        run(&args(&["canonicalize", "-o", &output, &input])).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"d1:ai0e1:bi1ee");
+        run(&args(&["canonicalize", "--check", &output])).unwrap();
+    }
+
+    #[test]
+    fn test_diff_output() {
+        let old = parse_document(b"d1:ai1e1:bl1:xe1:cdee").unwrap();
+        let new = parse_document(b"d1:bl1:y1:ze1:ci0ee").unwrap();
+        let mut changes = diff(&old, &new);
+        attach_spans(
+            &mut changes,
+            b"d1:ai1e1:bl1:xe1:cdee",
+            b"d1:bl1:y1:ze1:ci0ee",
+        )
+        .unwrap();
+        let lines: Vec<String> = changes.iter().map(render_change).collect();
+        assert_eq!(
+            lines,
+            [
+                "- a: 1  [4..7]\n",
+                "~ b[0]: \"x\" -> \"y\"  [11..14 -> 5..8]\n",
+                "+ b[1]: \"z\"  [8..11]\n",
+                "~ c: {0 keys} -> 0  [18..20 -> 15..18]\n",
+            ]
+        );
+        assert_eq!(
+            change_json(&changes[0]).to_string(),
+            r#"{"kind":"removed","new":null,"new_span":null,"old":1,"old_span":[4,7],"path":"a"}"#
+        );
+    }
+
+    #[test]
+    fn test_diff_exit_codes() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = |name: &str, contents: &[u8]| {
+            let path = dir.path().join(name);
+            fs::write(&path, contents).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        let old = file("old", b"d1:ai1ee");
+        let new = file("new", b"d1:ai2ee");
+        let broken = file("broken", b"d1:a");
+        let missing = dir.path().join("missing").to_string_lossy().into_owned();
+
+        run(&args(&["diff", &old, &old])).unwrap();
+        let exit_code = |a: &str, b: &str| run(&args(&["diff", a, b])).unwrap_err().exit_code();
+        assert_eq!(exit_code(&old, &new), 1);
+        assert_eq!(exit_code(&old, &broken), 2);
+        assert_eq!(exit_code(&missing, &new), 2);
     }
 }
@@ -0,0 +1,172 @@
+use sha2::{Digest, Sha256};
+
+/// Size of the blocks hashed into the leaves of a BEP 52 merkle tree
+pub const BLOCK_SIZE: u64 = 16 * 1024;
+
+pub type Hash = [u8; 32];
+
+fn hash_pair(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Hash one 16 KiB block (the last block of a file may be shorter)
+pub fn hash_block(block: &[u8]) -> Hash {
+    Sha256::digest(block).into()
+}
+
+/// Root of a tree of `width` zero leaves, where `width` is a power of two
+pub fn zero_root(width: usize) -> Hash {
+    let mut hash = [0; 32];
+    let mut w = 1;
+    while w < width {
+        hash = hash_pair(&hash, &hash);
+        w *= 2;
+    }
+    hash
+}
+
+/// Root of a tree over `leaves`, padded with `pad` up to `width` leaves
+///
+/// `width` must be a power of two no smaller than `leaves.len()`.
+pub fn root_with_padding(leaves: &[Hash], width: usize, pad: Hash) -> Hash {
+    let mut layer: Vec<Hash> = leaves.to_vec();
+    layer.resize(width.max(1), pad);
+    while layer.len() > 1 {
+        layer = layer
+            .chunks_exact(2)
+            .map(|pair| hash_pair(&pair[0], &pair[1]))
+            .collect();
+    }
+    layer[0]
+}
+
+/// Merkle root of a file given the hashes of its 16 KiB blocks
+pub fn file_root(block_hashes: &[Hash]) -> Hash {
+    let width = block_hashes.len().max(1).next_power_of_two();
+    root_with_padding(block_hashes, width, [0; 32])
+}
+
+/// The piece layer of a file: one hash per piece, each the root of that piece's blocks
+///
+/// `piece_length` must be a power of two of at least 16 KiB. The final piece is
+/// padded with zero leaves, as BEP 52 requires.
+pub fn piece_layer(block_hashes: &[Hash], piece_length: u64) -> Vec<Hash> {
+    let blocks_per_piece = (piece_length / BLOCK_SIZE) as usize;
+    block_hashes
+        .chunks(blocks_per_piece)
+        .map(|blocks| root_with_padding(blocks, blocks_per_piece, [0; 32]))
+        .collect()
+}
+
+/// Merkle root of a file computed from its piece layer
+///
+/// Padding pieces are the roots of all-zero subtrees, so this matches
+/// `file_root` over the original blocks.
+pub fn root_from_piece_layer(layer: &[Hash], piece_length: u64) -> Hash {
+    let blocks_per_piece = (piece_length / BLOCK_SIZE) as usize;
+    let width = layer.len().max(1).next_power_of_two();
+    root_with_padding(layer, width, zero_root(blocks_per_piece))
+}
+
+/// Incrementally hashes a file's data into 16 KiB block hashes
+#[derive(Default)]
+pub struct BlockHasher {
+    hashes: Vec<Hash>,
+    buffer: Vec<u8>,
+}
+
+impl BlockHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        let block = BLOCK_SIZE as usize;
+        while !data.is_empty() {
+            let take = (block - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffer.len() == block {
+                self.hashes.push(hash_block(&self.buffer));
+                self.buffer.clear();
+            }
+        }
+    }
+
+    /// The block hashes of everything written so far
+    pub fn finish(mut self) -> Vec<Hash> {
+        if !self.buffer.is_empty() {
+            self.hashes.push(hash_block(&self.buffer));
+        }
+        self.hashes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(data: &[u8]) -> Vec<Hash> {
+        let mut hasher = BlockHasher::new();
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_single_block_root_is_block_hash() {
+        let data = b"hello world";
+        assert_eq!(file_root(&blocks(data)), hash_block(data));
+    }
+
+    #[test]
+    fn test_two_block_root() {
+        let data = vec![7u8; BLOCK_SIZE as usize + 10];
+        let hashes = blocks(&data);
+        assert_eq!(hashes.len(), 2);
+        assert_eq!(
+            file_root(&hashes),
+            hash_pair(
+                &hash_block(&data[..BLOCK_SIZE as usize]),
+                &hash_block(&data[BLOCK_SIZE as usize..])
+            )
+        );
+    }
+
+    #[test]
+    fn test_piece_layer_matches_root() {
+        let data: Vec<u8> = (0..BLOCK_SIZE as usize * 4 + 3).map(|i| i as u8).collect();
+        let hashes = blocks(&data);
+        let piece_length = BLOCK_SIZE * 2;
+
+        let layer = piece_layer(&hashes, piece_length);
+        assert_eq!(layer.len(), 3);
+        assert_eq!(layer[0], hash_pair(&hashes[0], &hashes[1]));
+        assert_eq!(layer[2], hash_pair(&hashes[4], &[0; 32]));
+        assert_eq!(
+            root_from_piece_layer(&layer, piece_length),
+            file_root(&hashes)
+        );
+    }
+
+    #[test]
+    fn test_incremental_hashing() {
+        let data: Vec<u8> = (0..BLOCK_SIZE as usize * 3)
+            .map(|i| (i * 7) as u8)
+            .collect();
+        let mut hasher = BlockHasher::new();
+        for chunk in data.chunks(1000) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finish(), blocks(&data));
+    }
+
+    #[test]
+    fn test_zero_root() {
+        assert_eq!(zero_root(1), [0; 32]);
+        assert_eq!(zero_root(2), hash_pair(&[0; 32], &[0; 32]));
+        assert_eq!(root_with_padding(&[], 4, [0; 32]), zero_root(4));
+    }
+}
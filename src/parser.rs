@@ -9,7 +9,7 @@ use crate::list::list;
 type Res<T, U> = IResult<T, U, VerboseError<T>>;
 
 /// Parse any bencode value (integer, byte string, list, or dictionary)
-pub fn parse_bencode(input: &[u8]) -> Res<&[u8], BencodeValue> {
+pub fn parse_bencode(input: &[u8]) -> Res<&[u8], BencodeValue<'_>> {
     alt((
         map(integer, BencodeValue::Integer),
         map(byte_string, BencodeValue::ByteString),
@@ -0,0 +1,491 @@
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::common::BencodeValue;
+use crate::diff::{Change, ChangeKind};
+use crate::edit::{delete_value, insert_value, set_value};
+use crate::encoder::{encode_to_bytes, EncodingError, ToBencode};
+use crate::fields::{as_list, parse_document, string_value, FieldError, Fields};
+use crate::json::{from_json, to_json, JsonError, JsonMode};
+use crate::path::{ParsePathError, Path, PathSegment};
+use crate::span::raw_value;
+
+/// One edit, modelled on the operations of JSON Patch (RFC 6902)
+///
+/// Values are held as raw bencode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchOp {
+    /// Set a dictionary key, or insert into a list before `path`
+    Add {
+        path: Path,
+        value: Vec<u8>,
+    },
+    Remove {
+        path: Path,
+    },
+    /// Like `Add`, but the target must already exist and is overwritten
+    Replace {
+        path: Path,
+        value: Vec<u8>,
+    },
+    /// Remove the value at `from` and add it at `path`
+    Move {
+        from: Path,
+        path: Path,
+    },
+    /// Fail the patch unless the value at `path` equals `value`
+    Test {
+        path: Path,
+        value: Vec<u8>,
+    },
+}
+
+impl PatchOp {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PatchOp::Add { .. } => "add",
+            PatchOp::Remove { .. } => "remove",
+            PatchOp::Replace { .. } => "replace",
+            PatchOp::Move { .. } => "move",
+            PatchOp::Test { .. } => "test",
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            PatchOp::Add { path, .. }
+            | PatchOp::Remove { path }
+            | PatchOp::Replace { path, .. }
+            | PatchOp::Move { path, .. }
+            | PatchOp::Test { path, .. } => path,
+        }
+    }
+
+    fn value(&self) -> Option<&[u8]> {
+        match self {
+            PatchOp::Add { value, .. }
+            | PatchOp::Replace { value, .. }
+            | PatchOp::Test { value, .. } => Some(value),
+            PatchOp::Remove { .. } | PatchOp::Move { .. } => None,
+        }
+    }
+
+    fn from(&self) -> Option<&Path> {
+        match self {
+            PatchOp::Move { from, .. } => Some(from),
+            _ => None,
+        }
+    }
+}
+
+/// Error type for reading and applying patches
+#[derive(Debug)]
+pub enum PatchError {
+    /// The patch document itself is malformed
+    Malformed(FieldError),
+    /// The document being patched is not valid bencode
+    Document(FieldError),
+    Json(JsonError),
+    Encoding(EncodingError),
+    /// The operation at `index` could not be applied
+    Failed {
+        index: usize,
+        error: FieldError,
+    },
+    /// The `test` operation at `index` did not match
+    TestFailed {
+        index: usize,
+        path: Path,
+    },
+}
+
+impl From<FieldError> for PatchError {
+    fn from(error: FieldError) -> Self {
+        PatchError::Malformed(error)
+    }
+}
+
+impl From<JsonError> for PatchError {
+    fn from(error: JsonError) -> Self {
+        PatchError::Json(error)
+    }
+}
+
+impl From<EncodingError> for PatchError {
+    fn from(error: EncodingError) -> Self {
+        PatchError::Encoding(error)
+    }
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::Malformed(e) => write!(f, "Malformed patch: {}", e),
+            PatchError::Document(e) => write!(f, "Invalid document: {}", e),
+            PatchError::Json(e) => write!(f, "{}", e),
+            PatchError::Encoding(e) => write!(f, "{}", e),
+            PatchError::Failed { index, error } => write!(f, "Operation {}: {}", index, error),
+            PatchError::TestFailed { index, path } => {
+                write!(f, "Operation {}: test failed at {}", index, path)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+/// An ordered list of operations applied all or nothing
+///
+/// The bencode form is a list of dictionaries with `op`, `path`, `from` and
+/// `value` keys, where paths use the syntax of [`Path`]'s `Display` and
+/// `value` holds the bencode value itself. The JSON form has the same shape
+/// with `value` in the lossless JSON mapping.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Patch {
+    pub ops: Vec<PatchOp>,
+}
+
+impl Patch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build a patch that turns the old side of a diff into the new side,
+    /// guarding every removed or overwritten value with a `test`
+    pub fn from_changes(changes: &[Change]) -> Result<Patch, EncodingError> {
+        let mut ops = Vec::new();
+        // Trailing list elements are reported in ascending order, but have to
+        // be removed from the end so the earlier indices stay valid
+        let mut removals: Vec<PatchOp> = Vec::new();
+        for change in changes {
+            let is_list_removal = change.kind == ChangeKind::Removed
+                && matches!(change.path.last(), Some(PathSegment::Index(_)));
+            if !is_list_removal {
+                ops.extend(removals.drain(..).rev());
+            }
+
+            let path = change.path.clone();
+            if let Some(old) = change.old {
+                ops.push(PatchOp::Test {
+                    path: path.clone(),
+                    value: encode_to_bytes(old)?,
+                });
+            }
+            match (change.kind, change.new) {
+                (ChangeKind::Added, Some(new)) => ops.push(PatchOp::Add {
+                    path,
+                    value: encode_to_bytes(new)?,
+                }),
+                (ChangeKind::Changed, Some(new)) => ops.push(PatchOp::Replace {
+                    path,
+                    value: encode_to_bytes(new)?,
+                }),
+                _ if is_list_removal => removals.push(PatchOp::Remove { path }),
+                _ => ops.push(PatchOp::Remove { path }),
+            }
+        }
+        ops.extend(removals.into_iter().rev());
+        Ok(Patch { ops })
+    }
+
+    pub fn from_bytes(input: &[u8]) -> Result<Patch, FieldError> {
+        Patch::from_bencode(&parse_document(input)?)
+    }
+
+    pub fn from_bencode(value: &BencodeValue) -> Result<Patch, FieldError> {
+        let mut ops = Vec::new();
+        for (index, item) in as_list(value, &Path::root())?.iter().enumerate() {
+            let fields = Fields::new(item, Path::root().index(index))?;
+            let path = parse_path(&fields, b"path")?;
+            let value = |fields: &Fields| -> Result<Vec<u8>, FieldError> {
+                let value = fields.require(b"value")?;
+                encode_to_bytes(value)
+                    .map_err(|e| FieldError::invalid(fields.path_of(b"value"), e.to_string()))
+            };
+            ops.push(match fields.require_string(b"op")?.as_str() {
+                "add" => PatchOp::Add {
+                    value: value(&fields)?,
+                    path,
+                },
+                "remove" => PatchOp::Remove { path },
+                "replace" => PatchOp::Replace {
+                    value: value(&fields)?,
+                    path,
+                },
+                "move" => PatchOp::Move {
+                    from: parse_path(&fields, b"from")?,
+                    path,
+                },
+                "test" => PatchOp::Test {
+                    value: value(&fields)?,
+                    path,
+                },
+                other => {
+                    return Err(FieldError::invalid(
+                        fields.path_of(b"op"),
+                        format!("unknown operation {:?}", other),
+                    ))
+                }
+            });
+        }
+        Ok(Patch { ops })
+    }
+
+    /// Read the JSON form, reusing the bencode reader for its structure
+    pub fn from_json(value: &Value) -> Result<Patch, PatchError> {
+        Ok(Patch::from_bytes(&from_json(value)?)?)
+    }
+
+    pub fn from_json_str(input: &str) -> Result<Patch, PatchError> {
+        let value: Value = serde_json::from_str(input).map_err(JsonError::from)?;
+        Patch::from_json(&value)
+    }
+
+    pub fn to_json(&self) -> Result<Value, PatchError> {
+        let mut list = Vec::new();
+        for op in &self.ops {
+            let mut object = Map::new();
+            object.insert("op".to_string(), Value::from(op.name()));
+            object.insert("path".to_string(), Value::from(op.path().to_string()));
+            if let Some(from) = op.from() {
+                object.insert("from".to_string(), Value::from(from.to_string()));
+            }
+            if let Some(value) = op.value() {
+                object.insert(
+                    "value".to_string(),
+                    to_json(&parse_document(value)?, JsonMode::Lossless),
+                );
+            }
+            list.push(Value::Object(object));
+        }
+        Ok(Value::Array(list))
+    }
+
+    /// Apply every operation in order to the bencoded `input`
+    ///
+    /// Nothing is returned unless all operations succeed. Bytes outside the
+    /// edited values are carried over unchanged.
+    pub fn apply(&self, input: &[u8]) -> Result<Vec<u8>, PatchError> {
+        parse_document(input).map_err(PatchError::Document)?;
+        let mut document = input.to_vec();
+        for (index, op) in self.ops.iter().enumerate() {
+            let failed = |error| PatchError::Failed { index, error };
+            document = match op {
+                PatchOp::Add { path, value } => insert_value(&document, path, value),
+                PatchOp::Remove { path } => delete_value(&document, path),
+                PatchOp::Replace { path, value } => match raw_value(&document, path) {
+                    Ok(Some(_)) => set_value(&document, path, value),
+                    Ok(None) => Err(FieldError::missing(path.clone())),
+                    Err(e) => Err(e),
+                },
+                PatchOp::Move { from, path } => move_value(&document, from, path),
+                PatchOp::Test { path, value } => {
+                    let expected = parse_document(value).map_err(failed)?;
+                    match raw_value(&document, path).map_err(failed)? {
+                        Some(raw) if parse_document(raw).map_err(failed)? == expected => continue,
+                        _ => {
+                            return Err(PatchError::TestFailed {
+                                index,
+                                path: path.clone(),
+                            })
+                        }
+                    }
+                }
+            }
+            .map_err(failed)?;
+        }
+        Ok(document)
+    }
+
+    /// Apply the patch to an already parsed value, returning the encoded result
+    pub fn apply_value(&self, value: &BencodeValue) -> Result<Vec<u8>, PatchError> {
+        self.apply(&encode_to_bytes(value)?)
+    }
+}
+
+impl ToBencode for Patch {
+    fn to_bencode(&self) -> Result<Vec<u8>, EncodingError> {
+        let paths: Vec<(String, Option<String>)> = self
+            .ops
+            .iter()
+            .map(|op| (op.path().to_string(), op.from().map(Path::to_string)))
+            .collect();
+
+        let mut list = Vec::new();
+        for (op, (path, from)) in self.ops.iter().zip(&paths) {
+            let mut dict = BTreeMap::new();
+            dict.insert(&b"op"[..], string_value(op.name()));
+            dict.insert(&b"path"[..], string_value(path));
+            if let Some(from) = from {
+                dict.insert(&b"from"[..], string_value(from));
+            }
+            if let Some(value) = op.value() {
+                let value =
+                    parse_document(value).map_err(|e| EncodingError::CustomError(e.to_string()))?;
+                dict.insert(&b"value"[..], value);
+            }
+            list.push(BencodeValue::Dictionary(dict));
+        }
+        encode_to_bytes(&BencodeValue::List(list))
+    }
+}
+
+fn parse_path(fields: &Fields, key: &[u8]) -> Result<Path, FieldError> {
+    fields
+        .require_string(key)?
+        .parse()
+        .map_err(|e: ParsePathError| FieldError::invalid(fields.path_of(key), e.to_string()))
+}
+
+fn move_value(input: &[u8], from: &Path, to: &Path) -> Result<Vec<u8>, FieldError> {
+    let raw = raw_value(input, from)?
+        .ok_or_else(|| FieldError::missing(from.clone()))?
+        .to_vec();
+    if from == to {
+        return Ok(input.to_vec());
+    }
+    if to.starts_with(from) {
+        return Err(FieldError::invalid(
+            to.clone(),
+            "cannot move a value into itself",
+        ));
+    }
+    insert_value(&delete_value(input, from)?, to, &raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::diff;
+
+    fn path(s: &str) -> Path {
+        s.parse().unwrap()
+    }
+
+    fn sample() -> Patch {
+        Patch {
+            ops: vec![
+                PatchOp::Test {
+                    path: path("announce"),
+                    value: b"3:old".to_vec(),
+                },
+                PatchOp::Replace {
+                    path: path("announce"),
+                    value: b"3:new".to_vec(),
+                },
+                PatchOp::Add {
+                    path: path("list[0]"),
+                    value: b"i0e".to_vec(),
+                },
+                PatchOp::Move {
+                    from: path("tmp"),
+                    path: path("info.name"),
+                },
+                PatchOp::Remove {
+                    path: path("list[2]"),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let input = b"d8:announce3:old4:infod6:lengthi1ee4:listli1ei2ee3:tmp1:xe";
+        assert_eq!(
+            sample().apply(input).unwrap(),
+            b"d8:announce3:new4:infod6:lengthi1e4:name1:xe4:listli0ei1eee".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_apply_is_atomic() {
+        let input = b"d8:announce3:bad4:listli1ei2ee3:tmp1:xe";
+        let error = sample().apply(input).unwrap_err();
+        assert_eq!(error.to_string(), "Operation 0: test failed at announce");
+
+        let patch = Patch {
+            ops: vec![
+                PatchOp::Remove { path: path("a") },
+                PatchOp::Replace {
+                    path: path("b"),
+                    value: b"i1e".to_vec(),
+                },
+            ],
+        };
+        let error = patch.apply(b"d1:ai1ee").unwrap_err();
+        assert_eq!(error.to_string(), "Operation 1: b: missing");
+
+        let patch = Patch {
+            ops: vec![PatchOp::Move {
+                from: path("a"),
+                path: path("a.b"),
+            }],
+        };
+        let error = patch.apply(b"d1:adee").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Operation 0: a.b: cannot move a value into itself"
+        );
+
+        let error = Patch::default().apply(b"d1:a").unwrap_err();
+        assert!(matches!(error, PatchError::Document(_)));
+        assert_eq!(
+            error.to_string(),
+            "Invalid document: Parse error: offset 4: unexpected end of input, expected a dictionary value"
+        );
+    }
+
+    #[test]
+    fn test_serialization() {
+        let patch = sample();
+        let bencoded = patch.to_bencode().unwrap();
+        assert!(bencoded.starts_with(b"ld2:op4:test4:path8:announce5:value3:olde"));
+        assert_eq!(Patch::from_bytes(&bencoded).unwrap(), patch);
+
+        let json = patch.to_json().unwrap();
+        assert_eq!(
+            json[3],
+            serde_json::json!({"op": "move", "from": "tmp", "path": "info.name"})
+        );
+        assert_eq!(Patch::from_json(&json).unwrap(), patch);
+
+        let error = Patch::from_json_str(r#"[{"op": "copy", "path": "a"}]"#).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Malformed patch: [0].op: unknown operation \"copy\""
+        );
+        let error = Patch::from_bytes(b"ld2:op6:remove4:path2:a[ee").unwrap_err();
+        assert!(error.to_string().starts_with("[0].path: Invalid path"));
+    }
+
+    #[test]
+    fn test_from_changes() {
+        let old_input = b"d1:ai1e1:bli1ei2ei3ee1:ci0ee";
+        let new_input = b"d1:bli1ee1:ci1e1:d1:xe";
+        let old = parse_document(old_input).unwrap();
+        let new = parse_document(new_input).unwrap();
+        let patch = Patch::from_changes(&diff(&old, &new)).unwrap();
+        let names: Vec<String> = patch
+            .ops
+            .iter()
+            .map(|op| format!("{} {}", op.name(), op.path()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "test a",
+                "remove a",
+                "test b[1]",
+                "test b[2]",
+                "remove b[2]",
+                "remove b[1]",
+                "test c",
+                "replace c",
+                "add d"
+            ]
+        );
+        assert_eq!(patch.apply(old_input).unwrap(), new_input.to_vec());
+        assert!(patch.apply(new_input).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::encoding::hex_byte;
+
+/// One step into a bencode value: a dictionary key or a list index
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PathSegment {
+    Key(Vec<u8>),
+    Index(usize),
+}
+
+/// Location of a value inside a bencode document, starting from the root
+///
+/// Displays as keys joined by `.` with list indices in brackets, for example
+/// `info.files[0].path[1]`. Bytes that would be ambiguous or unprintable are
+/// escaped: `.`, `[`, `]`, `"` and `\` get a leading backslash, control
+/// characters and invalid UTF-8 become `\xHH`, and the empty key is `""`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Path(Vec<PathSegment>);
+
+impl Path {
+    /// The path of the root value
+    pub fn root() -> Self {
+        Path(Vec::new())
+    }
+
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn push(&mut self, segment: PathSegment) {
+        self.0.push(segment);
+    }
+
+    pub fn pop(&mut self) -> Option<PathSegment> {
+        self.0.pop()
+    }
+
+    /// A new path one dictionary key below this one
+    pub fn key(&self, key: &[u8]) -> Self {
+        let mut path = self.clone();
+        path.push(PathSegment::Key(key.to_vec()));
+        path
+    }
+
+    /// A new path one list index below this one
+    pub fn index(&self, index: usize) -> Self {
+        let mut path = self.clone();
+        path.push(PathSegment::Index(index));
+        path
+    }
+
+    /// The path of the enclosing container, or `None` for the root
+    pub fn parent(&self) -> Option<Path> {
+        let (_, parent) = self.0.split_last()?;
+        Some(Path(parent.to_vec()))
+    }
+
+    pub fn last(&self) -> Option<&PathSegment> {
+        self.0.last()
+    }
+
+    /// Whether `self` is `other` or lies somewhere below it
+    pub fn starts_with(&self, other: &Path) -> bool {
+        self.0.starts_with(&other.0)
+    }
+}
+
+impl From<Vec<PathSegment>> for Path {
+    fn from(segments: Vec<PathSegment>) -> Self {
+        Path(segments)
+    }
+}
+
+fn write_key(f: &mut fmt::Formatter<'_>, key: &[u8]) -> fmt::Result {
+    if key.is_empty() {
+        return write!(f, "\"\"");
+    }
+
+    for chunk in key.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '.' | '[' | ']' | '"' | '\\' => write!(f, "\\{}", c)?,
+                c if c.is_control() => {
+                    let mut buf = [0; 4];
+                    for b in c.encode_utf8(&mut buf).bytes() {
+                        write!(f, "\\x{:02x}", b)?;
+                    }
+                }
+                c => write!(f, "{}", c)?,
+            }
+        }
+        for b in chunk.invalid() {
+            write!(f, "\\x{:02x}", b)?;
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.0.iter().enumerate() {
+            match segment {
+                PathSegment::Key(key) => {
+                    if i > 0 {
+                        write!(f, ".")?;
+                    }
+                    write_key(f, key)?;
+                }
+                PathSegment::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Error from parsing the display form of a path back into a `Path`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsePathError {
+    /// Byte offset in the path string
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParsePathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid path at {}: {}", self.position, self.message)
+    }
+}
+
+impl std::error::Error for ParsePathError {}
+
+/// Parses the syntax produced by `Display`, so `info.files[0].path` or `a\.b`
+impl FromStr for Path {
+    type Err = ParsePathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = s.as_bytes();
+        let error = |position: usize, message: &str| ParsePathError {
+            position,
+            message: message.to_string(),
+        };
+
+        let mut path = Path::root();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            if bytes[pos] == b'[' {
+                let close = bytes[pos..]
+                    .iter()
+                    .position(|&b| b == b']')
+                    .ok_or_else(|| error(pos, "unclosed '['"))?;
+                let digits = &s[pos + 1..pos + close];
+                if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(error(pos + 1, "expected a list index"));
+                }
+                let index = digits
+                    .parse()
+                    .map_err(|_| error(pos + 1, "list index out of range"))?;
+                path.push(PathSegment::Index(index));
+                pos += close + 1;
+                continue;
+            }
+
+            if !path.is_root() {
+                if bytes[pos] != b'.' {
+                    return Err(error(pos, "expected '.' or '['"));
+                }
+                pos += 1;
+            }
+
+            if bytes[pos..].starts_with(b"\"\"")
+                && matches!(bytes.get(pos + 2), None | Some(b'.') | Some(b'['))
+            {
+                path.push(PathSegment::Key(Vec::new()));
+                pos += 2;
+                continue;
+            }
+
+            let mut key = Vec::new();
+            while let Some(&b) = bytes.get(pos) {
+                match b {
+                    b'.' | b'[' => break,
+                    b']' | b'"' => return Err(error(pos, "unescaped special character")),
+                    b'\\' => match bytes.get(pos + 1) {
+                        Some(b'x') => {
+                            let hex = bytes
+                                .get(pos + 2..pos + 4)
+                                .and_then(hex_byte)
+                                .ok_or_else(|| error(pos, "expected two hex digits after \\x"))?;
+                            key.push(hex);
+                            pos += 4;
+                        }
+                        Some(&c) if b".[]\"\\".contains(&c) => {
+                            key.push(c);
+                            pos += 2;
+                        }
+                        _ => return Err(error(pos, "invalid escape")),
+                    },
+                    _ => {
+                        key.push(b);
+                        pos += 1;
+                    }
+                }
+            }
+            if key.is_empty() {
+                return Err(error(pos, "empty key; write it as \"\""));
+            }
+            path.push(PathSegment::Key(key));
+        }
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Path::root().to_string(), "");
+        assert_eq!(
+            Path::root().key(b"info").key(b"name").to_string(),
+            "info.name"
+        );
+        assert_eq!(
+            Path::root()
+                .key(b"info")
+                .key(b"files")
+                .index(0)
+                .key(b"path")
+                .index(1)
+                .to_string(),
+            "info.files[0].path[1]"
+        );
+        assert_eq!(Path::root().index(2).index(0).to_string(), "[2][0]");
+        assert_eq!(
+            Path::root().key(b"announce-list").index(0).to_string(),
+            "announce-list[0]"
+        );
+    }
+
+    #[test]
+    fn test_display_escapes() {
+        assert_eq!(Path::root().key(b"a.b").to_string(), "a\\.b");
+        assert_eq!(Path::root().key(b"[x]").to_string(), "\\[x\\]");
+        assert_eq!(Path::root().key(b"\\").to_string(), "\\\\");
+        assert_eq!(Path::root().key(b"").key(b"").to_string(), "\"\".\"\"");
+        assert_eq!(Path::root().key(b"\xff\x00a").to_string(), "\\xff\\x00a");
+        assert_eq!(Path::root().key("ハロー".as_bytes()).to_string(), "ハロー");
+    }
+
+    #[test]
+    fn test_parse() {
+        let paths = [
+            Path::root(),
+            Path::root().key(b"info").key(b"name"),
+            Path::root()
+                .key(b"info")
+                .key(b"files")
+                .index(0)
+                .key(b"path")
+                .index(1),
+            Path::root().index(2).index(0),
+            Path::root().key(b"a.b").key(b"[x]").key(b"\\"),
+            Path::root().key(b"").key(b"").index(3),
+            Path::root().key(b"\xff\x00a"),
+            Path::root().key("ハロー".as_bytes()),
+        ];
+        for path in paths {
+            assert_eq!(path.to_string().parse::<Path>(), Ok(path));
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let position = |s: &str| s.parse::<Path>().unwrap_err().position;
+        assert_eq!(position("a..b"), 2);
+        assert_eq!(position("a[x]"), 2);
+        assert_eq!(position("a[1"), 1);
+        assert_eq!(position("a[0]b"), 4);
+        assert_eq!(position("a\\q"), 1);
+        assert_eq!(position("a]"), 1);
+        assert_eq!(position("a\\x+f"), 1);
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+use crate::common::BencodeValue;
+use crate::compact::{decode_peers_v4, decode_peers_v6, encode_peer, PEER_V4_LEN, PEER_V6_LEN};
+use crate::encoder::{encode_to_bytes, EncodingError, ToBencode};
+use crate::fields::{insert_extra, parse_document, Extra, FieldError, Fields};
+use crate::path::Path;
+
+/// Name of the peer exchange extension in the BEP 10 handshake
+pub const UT_PEX: &str = "ut_pex";
+
+/// Per-peer flags carried in `added.f` and `added6.f`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PexFlags(pub u8);
+
+impl PexFlags {
+    pub const ENCRYPTION: u8 = 0x01;
+    pub const SEED: u8 = 0x02;
+    pub const UTP: u8 = 0x04;
+    pub const HOLEPUNCH: u8 = 0x08;
+    /// The peer accepts incoming connections
+    pub const REACHABLE: u8 = 0x10;
+
+    pub fn contains(self, flag: u8) -> bool {
+        self.0 & flag == flag
+    }
+
+    pub fn set(&mut self, flag: u8, on: bool) {
+        if on {
+            self.0 |= flag;
+        } else {
+            self.0 &= !flag;
+        }
+    }
+
+    pub fn prefers_encryption(self) -> bool {
+        self.contains(PexFlags::ENCRYPTION)
+    }
+
+    pub fn is_seed(self) -> bool {
+        self.contains(PexFlags::SEED)
+    }
+
+    pub fn supports_utp(self) -> bool {
+        self.contains(PexFlags::UTP)
+    }
+
+    pub fn supports_holepunch(self) -> bool {
+        self.contains(PexFlags::HOLEPUNCH)
+    }
+
+    pub fn is_reachable(self) -> bool {
+        self.contains(PexFlags::REACHABLE)
+    }
+}
+
+/// A peer announced in `added` or `added6`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PexPeer {
+    pub addr: SocketAddr,
+    pub flags: PexFlags,
+}
+
+/// A BEP 11 ut_pex message
+///
+/// IPv4 and IPv6 peers are kept together and split by address family when
+/// written. All six peer keys are always written, as common clients do.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PexMessage {
+    pub added: Vec<PexPeer>,
+    pub dropped: Vec<SocketAddr>,
+    pub extra: Extra,
+}
+
+const PEX_KEYS: &[&[u8]] = &[
+    b"added",
+    b"added.f",
+    b"added6",
+    b"added6.f",
+    b"dropped",
+    b"dropped6",
+];
+
+fn added_peers(
+    fields: &Fields,
+    addrs: Vec<SocketAddr>,
+    flags_key: &[u8],
+) -> Result<Vec<PexPeer>, FieldError> {
+    let flags = fields.bytes(flags_key)?.unwrap_or_default();
+    if !flags.is_empty() && flags.len() != addrs.len() {
+        return Err(FieldError::invalid(
+            fields.path_of(flags_key),
+            format!("has {} flags for {} peers", flags.len(), addrs.len()),
+        ));
+    }
+    Ok(addrs
+        .into_iter()
+        .enumerate()
+        .map(|(i, addr)| PexPeer {
+            addr,
+            flags: PexFlags(flags.get(i).copied().unwrap_or(0)),
+        })
+        .collect())
+}
+
+impl PexMessage {
+    pub fn new() -> Self {
+        PexMessage::default()
+    }
+
+    pub fn from_bytes(input: &[u8]) -> Result<PexMessage, FieldError> {
+        PexMessage::from_bencode(&parse_document(input)?)
+    }
+
+    pub fn from_bencode(value: &BencodeValue) -> Result<PexMessage, FieldError> {
+        let fields = Fields::new(value, Path::root())?;
+
+        let added4 = fields
+            .compact(b"added", PEER_V4_LEN, decode_peers_v4)?
+            .unwrap_or_default();
+        let added6 = fields
+            .compact(b"added6", PEER_V6_LEN, decode_peers_v6)?
+            .unwrap_or_default();
+        let mut added = added_peers(&fields, added4, b"added.f")?;
+        added.extend(added_peers(&fields, added6, b"added6.f")?);
+
+        let mut dropped = fields
+            .compact(b"dropped", PEER_V4_LEN, decode_peers_v4)?
+            .unwrap_or_default();
+        dropped.extend(
+            fields
+                .compact(b"dropped6", PEER_V6_LEN, decode_peers_v6)?
+                .unwrap_or_default(),
+        );
+
+        Ok(PexMessage {
+            added,
+            dropped,
+            extra: fields.extra(PEX_KEYS),
+        })
+    }
+}
+
+impl ToBencode for PexMessage {
+    fn to_bencode(&self) -> Result<Vec<u8>, EncodingError> {
+        let mut buffers: BTreeMap<&[u8], Vec<u8>> =
+            PEX_KEYS.iter().map(|key| (*key, Vec::new())).collect();
+        for peer in &self.added {
+            let (key, flags_key): (&[u8], &[u8]) = if peer.addr.is_ipv4() {
+                (b"added", b"added.f")
+            } else {
+                (b"added6", b"added6.f")
+            };
+            buffers
+                .get_mut(key)
+                .unwrap()
+                .extend(encode_peer(&peer.addr));
+            buffers.get_mut(flags_key).unwrap().push(peer.flags.0);
+        }
+        for addr in &self.dropped {
+            let key: &[u8] = if addr.is_ipv4() {
+                b"dropped"
+            } else {
+                b"dropped6"
+            };
+            buffers.get_mut(key).unwrap().extend(encode_peer(addr));
+        }
+
+        let mut dict = BTreeMap::new();
+        insert_extra(&mut dict, &self.extra)?;
+        for (key, buffer) in &buffers {
+            dict.insert(*key, BencodeValue::ByteString(buffer));
+        }
+        encode_to_bytes(&BencodeValue::Dictionary(dict))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Layout of a message as sent by libtorrent: all six keys, empty ones included
+    fn sample() -> Vec<u8> {
+        let mut input = b"d5:added12:".to_vec();
+        input.extend_from_slice(&[81, 2, 3, 4, 0x1a, 0xe1, 10, 0, 0, 9, 0xc8, 0xd5]);
+        input.extend_from_slice(b"7:added.f2:\x12\x01");
+        input.extend_from_slice(b"6:added618:");
+        input.extend_from_slice(&[0x2a, 0x01, 0x04, 0xf8]);
+        input.extend_from_slice(&[0; 11]);
+        input.extend_from_slice(&[2, 0xc8, 0xd5]);
+        input.extend_from_slice(b"8:added6.f1:\x14");
+        input.extend_from_slice(b"7:dropped6:");
+        input.extend_from_slice(&[192, 168, 1, 20, 0x1a, 0xe1]);
+        input.extend_from_slice(b"8:dropped60:e");
+        input
+    }
+
+    #[test]
+    fn test_sample_round_trip() {
+        let input = sample();
+        let message = PexMessage::from_bytes(&input).unwrap();
+
+        let addrs: Vec<String> = message.added.iter().map(|p| p.addr.to_string()).collect();
+        assert_eq!(
+            addrs,
+            vec!["81.2.3.4:6881", "10.0.0.9:51413", "[2a01:4f8::2]:51413"]
+        );
+        assert!(message.added[0].flags.is_seed());
+        assert!(message.added[0].flags.is_reachable());
+        assert!(!message.added[0].flags.supports_utp());
+        assert!(message.added[1].flags.prefers_encryption());
+        assert!(message.added[2].flags.supports_utp());
+        assert_eq!(message.dropped, vec!["192.168.1.20:6881".parse().unwrap()]);
+
+        assert_eq!(message.to_bencode().unwrap(), input);
+    }
+
+    #[test]
+    fn test_missing_keys_and_flags() {
+        let mut input = b"d5:added6:".to_vec();
+        input.extend_from_slice(&[1, 2, 3, 4, 0, 80]);
+        input.push(b'e');
+        let message = PexMessage::from_bytes(&input).unwrap();
+        assert_eq!(message.added[0].flags, PexFlags::default());
+        assert!(message.dropped.is_empty());
+
+        assert_eq!(
+            PexMessage::new().to_bencode().unwrap(),
+            b"d5:added0:7:added.f0:6:added60:8:added6.f0:7:dropped0:8:dropped60:e"
+        );
+    }
+
+    #[test]
+    fn test_flags() {
+        let mut flags = PexFlags::default();
+        flags.set(PexFlags::HOLEPUNCH, true);
+        flags.set(PexFlags::UTP, true);
+        assert!(flags.supports_holepunch() && flags.supports_utp());
+        flags.set(PexFlags::UTP, false);
+        assert_eq!(flags, PexFlags(PexFlags::HOLEPUNCH));
+    }
+
+    #[test]
+    fn test_invalid_messages() {
+        let check = |input: &[u8], expected: &str| {
+            assert_eq!(
+                PexMessage::from_bytes(input).unwrap_err().to_string(),
+                expected
+            );
+        };
+        check(b"d5:added5:abcdee", "added: length must be a multiple of 6");
+        check(
+            b"d5:added6:abcdef7:added.f2:\x00\x00e",
+            "added\\.f: has 2 flags for 1 peers",
+        );
+        check(b"d8:dropped6i1ee", "dropped6: expected byte string");
+    }
+}
@@ -0,0 +1,874 @@
+use std::collections::BTreeMap;
+
+use crate::common::BencodeValue;
+use crate::encoder::{encode_to_bytes, EncodingError, ToBencode};
+use crate::encoding::{percent_decode, percent_encode};
+use crate::fastresume::{FastResume, FILE_FORMAT};
+use crate::fields::{
+    insert_extra, integer_value, parse_document, string_list_value, string_value, Extra,
+    FieldError, Fields,
+};
+use crate::path::Path;
+use crate::torrent::Info;
+
+/// Which pieces a Transmission resume file marks as downloaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PieceProgress {
+    All,
+    Empty,
+    /// One bit per piece, most significant bit first
+    Bits(Vec<u8>),
+}
+
+/// The `progress` dictionary of a Transmission resume file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransmissionProgress {
+    pub pieces: Option<PieceProgress>,
+    /// Block bitfields, mtimes and the like, kept verbatim
+    pub extra: Extra,
+}
+
+/// A Transmission `.resume` file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransmissionResume {
+    pub destination: Option<String>,
+    pub incomplete_dir: Option<String>,
+    pub name: Option<String>,
+    pub added_date: Option<i64>,
+    /// Zero while the torrent is incomplete
+    pub done_date: Option<i64>,
+    pub activity_date: Option<i64>,
+    pub uploaded: Option<i64>,
+    pub downloaded: Option<i64>,
+    pub corrupt: Option<i64>,
+    pub paused: Option<bool>,
+    /// Per-file priority: -1 low, 0 normal, 1 high
+    pub priority: Option<Vec<i64>>,
+    /// Per-file "do not download" flags
+    pub dnd: Option<Vec<bool>>,
+    pub labels: Option<Vec<String>>,
+    pub progress: Option<TransmissionProgress>,
+    pub extra: Extra,
+}
+
+const TRANSMISSION_KEYS: &[&[u8]] = &[
+    b"activity-date",
+    b"added-date",
+    b"corrupt",
+    b"destination",
+    b"dnd",
+    b"done-date",
+    b"downloaded",
+    b"incomplete-dir",
+    b"labels",
+    b"name",
+    b"paused",
+    b"priority",
+    b"progress",
+    b"uploaded",
+];
+
+/// An rTorrent `.rtorrent` session file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RtorrentSession {
+    /// Data directory; for a multi-file torrent this is the torrent's own folder
+    pub directory: Option<String>,
+    /// The .torrent file the download was loaded from
+    pub tied_to_file: Option<String>,
+    /// ruTorrent stores its percent-encoded label here
+    pub custom1: Option<String>,
+    /// Started (`true`) or stopped
+    pub state: Option<bool>,
+    pub complete: Option<bool>,
+    /// Download priority: 0 off, 1 low, 2 normal, 3 high
+    pub priority: Option<i64>,
+    pub total_uploaded: Option<i64>,
+    pub total_downloaded: Option<i64>,
+    pub timestamp_started: Option<i64>,
+    pub timestamp_finished: Option<i64>,
+    pub views: Option<Vec<String>>,
+    pub extra: Extra,
+}
+
+const SESSION_KEYS: &[&[u8]] = &[
+    b"complete",
+    b"custom1",
+    b"directory",
+    b"priority",
+    b"state",
+    b"tied_to_file",
+    b"timestamp.finished",
+    b"timestamp.started",
+    b"total_downloaded",
+    b"total_uploaded",
+    b"views",
+];
+
+/// The `bitfield` of an rTorrent resume file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RtorrentBitfield {
+    /// Every one of this many pieces is downloaded
+    Complete(u64),
+    /// One bit per piece, most significant bit first
+    Bits(Vec<u8>),
+}
+
+/// A file entry of an rTorrent resume file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RtorrentFile {
+    /// Number of completed chunks touching the file
+    pub completed: Option<i64>,
+    pub mtime: Option<i64>,
+    /// 0 off, 1 normal, 2 high
+    pub priority: Option<i64>,
+    pub extra: Extra,
+}
+
+/// A tracker entry of an rTorrent resume file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RtorrentTracker {
+    pub enabled: Option<bool>,
+    pub extra: Extra,
+}
+
+/// An rTorrent `.libtorrent_resume` file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RtorrentResume {
+    pub bitfield: Option<RtorrentBitfield>,
+    pub files: Option<Vec<RtorrentFile>>,
+    /// Keyed by announce URL
+    pub trackers: Option<BTreeMap<String, RtorrentTracker>>,
+    pub extra: Extra,
+}
+
+// libtorrent keeps one byte per piece where the other clients pack eight
+fn pack_pieces(pieces: &[u8]) -> Vec<u8> {
+    let mut bits = vec![0; pieces.len().div_ceil(8)];
+    for (i, piece) in pieces.iter().enumerate() {
+        if piece & 1 == 1 {
+            bits[i / 8] |= 0x80 >> (i % 8);
+        }
+    }
+    bits
+}
+
+fn unpack_bits(bits: &[u8], count: usize) -> Vec<u8> {
+    (0..count)
+        .map(|i| bits.get(i / 8).map_or(0, |b| (b >> (7 - i % 8)) & 1))
+        .collect()
+}
+
+fn all_pieces(pieces: &[u8]) -> bool {
+    pieces.iter().all(|piece| piece & 1 == 1)
+}
+
+// libtorrent file priorities run from 0 (skip) through 4 (default) to 7 (top)
+fn from_transmission_priority(priority: i64, dnd: bool) -> i64 {
+    match priority {
+        _ if dnd => 0,
+        p if p < 0 => 1,
+        0 => 4,
+        _ => 7,
+    }
+}
+
+fn to_transmission_priority(priority: i64) -> (i64, bool) {
+    match priority {
+        p if p <= 0 => (0, true),
+        1..=3 => (-1, false),
+        4 | 5 => (0, false),
+        _ => (1, false),
+    }
+}
+
+fn from_rtorrent_priority(priority: i64) -> i64 {
+    match priority {
+        p if p <= 0 => 0,
+        1 => 4,
+        _ => 7,
+    }
+}
+
+fn to_rtorrent_priority(priority: i64) -> i64 {
+    match priority {
+        p if p <= 0 => 0,
+        1..=5 => 1,
+        _ => 2,
+    }
+}
+
+fn parent_directory(dir: &str) -> String {
+    match dir.trim_end_matches('/').rsplit_once('/') {
+        Some(("", _)) => "/".to_string(),
+        Some((parent, _)) => parent.to_string(),
+        None => ".".to_string(),
+    }
+}
+
+// A libtorrent path as text for clients that only store UTF-8 paths
+fn lossy(path: &[u8]) -> String {
+    String::from_utf8_lossy(path).into_owned()
+}
+
+// Insert each present value under its key, for the many optional fields
+fn insert_integers<'a>(
+    dict: &mut BTreeMap<&'a [u8], BencodeValue<'a>>,
+    entries: &[(&'a [u8], Option<i64>)],
+) -> Result<(), EncodingError> {
+    for (key, value) in entries {
+        if let Some(value) = value {
+            dict.insert(*key, integer_value(*value)?);
+        }
+    }
+    Ok(())
+}
+
+fn insert_strings<'a>(
+    dict: &mut BTreeMap<&'a [u8], BencodeValue<'a>>,
+    entries: &[(&'a [u8], &'a Option<String>)],
+) {
+    for (key, value) in entries {
+        if let Some(value) = value {
+            dict.insert(*key, string_value(value));
+        }
+    }
+}
+
+fn flag_value<'a>(flag: bool) -> BencodeValue<'a> {
+    BencodeValue::Integer(flag as isize)
+}
+
+impl TransmissionProgress {
+    fn from_bencode(fields: &Fields) -> Result<TransmissionProgress, FieldError> {
+        let pieces = fields.bytes(b"pieces")?.map(|pieces| match pieces {
+            b"all" => PieceProgress::All,
+            b"none" => PieceProgress::Empty,
+            bits => PieceProgress::Bits(bits.to_vec()),
+        });
+        Ok(TransmissionProgress {
+            pieces,
+            extra: fields.extra(&[b"pieces"]),
+        })
+    }
+
+    fn to_value(&self) -> Result<BencodeValue<'_>, EncodingError> {
+        let mut dict = BTreeMap::new();
+        insert_extra(&mut dict, &self.extra)?;
+        let pieces = match &self.pieces {
+            Some(PieceProgress::All) => Some(&b"all"[..]),
+            Some(PieceProgress::Empty) => Some(&b"none"[..]),
+            Some(PieceProgress::Bits(bits)) => Some(&bits[..]),
+            None => None,
+        };
+        if let Some(pieces) = pieces {
+            dict.insert(&b"pieces"[..], BencodeValue::ByteString(pieces));
+        }
+        Ok(BencodeValue::Dictionary(dict))
+    }
+
+    /// One byte per piece in libtorrent's form, if the piece state is known
+    fn piece_states(&self, count: usize) -> Option<Vec<u8>> {
+        match self.pieces.as_ref()? {
+            PieceProgress::All => Some(vec![1; count]),
+            PieceProgress::Empty => Some(vec![0; count]),
+            PieceProgress::Bits(bits) => Some(unpack_bits(bits, count)),
+        }
+    }
+}
+
+impl TransmissionResume {
+    pub fn from_bytes(input: &[u8]) -> Result<TransmissionResume, FieldError> {
+        TransmissionResume::from_bencode(&parse_document(input)?)
+    }
+
+    pub fn from_bencode(value: &BencodeValue) -> Result<TransmissionResume, FieldError> {
+        let fields = Fields::new(value, Path::root())?;
+
+        let dnd = fields
+            .integer_list(b"dnd")?
+            .map(|list| list.into_iter().map(|dnd| dnd != 0).collect());
+        let progress = match fields.dictionary(b"progress")? {
+            Some(progress) => Some(TransmissionProgress::from_bencode(&progress)?),
+            None => None,
+        };
+
+        Ok(TransmissionResume {
+            destination: fields.string(b"destination")?,
+            incomplete_dir: fields.string(b"incomplete-dir")?,
+            name: fields.string(b"name")?,
+            added_date: fields.integer(b"added-date")?,
+            done_date: fields.integer(b"done-date")?,
+            activity_date: fields.integer(b"activity-date")?,
+            uploaded: fields.integer(b"uploaded")?,
+            downloaded: fields.integer(b"downloaded")?,
+            corrupt: fields.integer(b"corrupt")?,
+            paused: fields.flag(b"paused")?,
+            priority: fields.integer_list(b"priority")?,
+            dnd,
+            labels: fields.string_list(b"labels")?,
+            progress,
+            extra: fields.extra(TRANSMISSION_KEYS),
+        })
+    }
+
+    pub fn is_complete(&self) -> bool {
+        matches!(
+            self.progress.as_ref().and_then(|p| p.pieces.as_ref()),
+            Some(PieceProgress::All)
+        )
+    }
+
+    /// Convert to a libtorrent resume file for the torrent described by `info`
+    ///
+    /// Labels become qBittorrent tags and Transmission's three file
+    /// priorities map onto libtorrent's low, default and top priorities.
+    pub fn to_fastresume(&self, info: &Info) -> FastResume {
+        let mut resume = FastResume::new();
+        resume.file_format = Some(FILE_FORMAT.to_string());
+        resume.file_version = Some(1);
+        resume.name = self.name.clone().map(String::into_bytes);
+        resume.save_path = self.destination.clone().map(String::into_bytes);
+        resume.added_time = self.added_date;
+        resume.completed_time = self.done_date;
+        resume.total_uploaded = self.uploaded;
+        resume.total_downloaded = self.downloaded;
+        resume.paused = self.paused;
+        resume.qbt_tags = self.labels.clone();
+
+        if self.priority.is_some() || self.dnd.is_some() {
+            let priority = self.priority.as_deref().unwrap_or_default();
+            let dnd = self.dnd.as_deref().unwrap_or_default();
+            let files = priority.len().max(dnd.len());
+            resume.file_priority = Some(
+                (0..files)
+                    .map(|i| {
+                        let priority = priority.get(i).copied().unwrap_or(0);
+                        from_transmission_priority(priority, dnd.get(i).copied().unwrap_or(false))
+                    })
+                    .collect(),
+            );
+        }
+        if info.piece_count() > 0 {
+            resume.pieces = self
+                .progress
+                .as_ref()
+                .and_then(|progress| progress.piece_states(info.piece_count()));
+        }
+        resume
+    }
+
+    pub fn from_fastresume(resume: &FastResume) -> TransmissionResume {
+        let (priority, dnd) = match &resume.file_priority {
+            Some(priorities) => {
+                let (priority, dnd) = priorities
+                    .iter()
+                    .map(|&p| to_transmission_priority(p))
+                    .unzip();
+                (Some(priority), Some(dnd))
+            }
+            None => (None, None),
+        };
+        let progress = resume.pieces.as_ref().map(|pieces| TransmissionProgress {
+            pieces: Some(if all_pieces(pieces) {
+                PieceProgress::All
+            } else if pieces.iter().all(|piece| piece & 1 == 0) {
+                PieceProgress::Empty
+            } else {
+                PieceProgress::Bits(pack_pieces(pieces))
+            }),
+            extra: Extra::new(),
+        });
+
+        TransmissionResume {
+            destination: resume.effective_save_path().map(lossy),
+            name: resume
+                .qbt_name
+                .clone()
+                .or_else(|| resume.name.as_deref().map(lossy)),
+            added_date: resume.added_time,
+            done_date: resume.completed_time,
+            uploaded: resume.total_uploaded,
+            downloaded: resume.total_downloaded,
+            paused: resume.paused,
+            priority,
+            dnd,
+            labels: resume.qbt_tags.clone(),
+            progress,
+            ..TransmissionResume::default()
+        }
+    }
+}
+
+impl ToBencode for TransmissionResume {
+    fn to_bencode(&self) -> Result<Vec<u8>, EncodingError> {
+        let mut dict = BTreeMap::new();
+        insert_extra(&mut dict, &self.extra)?;
+        insert_strings(
+            &mut dict,
+            &[
+                (b"destination", &self.destination),
+                (b"incomplete-dir", &self.incomplete_dir),
+                (b"name", &self.name),
+            ],
+        );
+        insert_integers(
+            &mut dict,
+            &[
+                (b"added-date", self.added_date),
+                (b"done-date", self.done_date),
+                (b"activity-date", self.activity_date),
+                (b"uploaded", self.uploaded),
+                (b"downloaded", self.downloaded),
+                (b"corrupt", self.corrupt),
+            ],
+        )?;
+        if let Some(paused) = self.paused {
+            dict.insert(&b"paused"[..], flag_value(paused));
+        }
+        if let Some(priority) = &self.priority {
+            let list = priority
+                .iter()
+                .map(|&p| integer_value(p))
+                .collect::<Result<_, _>>()?;
+            dict.insert(&b"priority"[..], BencodeValue::List(list));
+        }
+        if let Some(dnd) = &self.dnd {
+            let list = dnd.iter().map(|&dnd| flag_value(dnd)).collect();
+            dict.insert(&b"dnd"[..], BencodeValue::List(list));
+        }
+        if let Some(labels) = &self.labels {
+            dict.insert(&b"labels"[..], string_list_value(labels));
+        }
+        if let Some(progress) = &self.progress {
+            dict.insert(&b"progress"[..], progress.to_value()?);
+        }
+        encode_to_bytes(&BencodeValue::Dictionary(dict))
+    }
+}
+
+impl RtorrentSession {
+    pub fn from_bytes(input: &[u8]) -> Result<RtorrentSession, FieldError> {
+        RtorrentSession::from_bencode(&parse_document(input)?)
+    }
+
+    pub fn from_bencode(value: &BencodeValue) -> Result<RtorrentSession, FieldError> {
+        let fields = Fields::new(value, Path::root())?;
+        Ok(RtorrentSession {
+            directory: fields.string(b"directory")?,
+            tied_to_file: fields.string(b"tied_to_file")?,
+            custom1: fields.string(b"custom1")?,
+            state: fields.flag(b"state")?,
+            complete: fields.flag(b"complete")?,
+            priority: fields.integer(b"priority")?,
+            total_uploaded: fields.integer(b"total_uploaded")?,
+            total_downloaded: fields.integer(b"total_downloaded")?,
+            timestamp_started: fields.integer(b"timestamp.started")?,
+            timestamp_finished: fields.integer(b"timestamp.finished")?,
+            views: fields.string_list(b"views")?,
+            extra: fields.extra(SESSION_KEYS),
+        })
+    }
+
+    /// Convert a session file and its optional resume file to a libtorrent resume file
+    ///
+    /// The ruTorrent label in `custom1` becomes the qBittorrent category.
+    /// Enabled trackers are listed one per tier, since rTorrent does not keep tiers.
+    /// A complete-bitfield count that is not the torrent's piece count is
+    /// dropped, leaving the pieces to be rechecked.
+    pub fn to_fastresume(&self, resume: Option<&RtorrentResume>, info: &Info) -> FastResume {
+        let mut fastresume = FastResume::new();
+        fastresume.file_format = Some(FILE_FORMAT.to_string());
+        fastresume.file_version = Some(1);
+        fastresume.name = Some(info.name.clone());
+        fastresume.save_path = self.directory.as_ref().map(|dir| {
+            if info.is_multi_file() {
+                parent_directory(dir).into_bytes()
+            } else {
+                dir.clone().into_bytes()
+            }
+        });
+        fastresume.paused = self.state.map(|started| !started);
+        fastresume.completed_time = self.timestamp_finished;
+        fastresume.total_uploaded = self.total_uploaded;
+        fastresume.total_downloaded = self.total_downloaded;
+        fastresume.qbt_category = self
+            .custom1
+            .as_ref()
+            .filter(|label| !label.is_empty())
+            .map(|label| percent_decode(label).unwrap_or_else(|| label.clone()));
+
+        let resume = match resume {
+            Some(resume) => resume,
+            None => return fastresume,
+        };
+        fastresume.pieces = match &resume.bitfield {
+            Some(RtorrentBitfield::Complete(count)) => {
+                let pieces = info.piece_count();
+                if *count == pieces as u64 {
+                    Some(vec![1; pieces])
+                } else {
+                    None
+                }
+            }
+            Some(RtorrentBitfield::Bits(bits)) => Some(unpack_bits(bits, info.piece_count())),
+            None => None,
+        };
+        fastresume.file_priority = resume.files.as_ref().map(|files| {
+            files
+                .iter()
+                .map(|file| from_rtorrent_priority(file.priority.unwrap_or(1)))
+                .collect()
+        });
+        fastresume.trackers = resume.trackers.as_ref().map(|trackers| {
+            trackers
+                .iter()
+                .filter(|(_, tracker)| tracker.enabled != Some(false))
+                .map(|(url, _)| vec![url.clone()])
+                .collect()
+        });
+        fastresume
+    }
+
+    /// Build a session file and resume file from a libtorrent resume file
+    pub fn from_fastresume(
+        fastresume: &FastResume,
+        info: &Info,
+    ) -> (RtorrentSession, RtorrentResume) {
+        let directory = fastresume.effective_save_path().map(|dir| {
+            let dir = lossy(dir);
+            if info.is_multi_file() {
+                format!("{}/{}", dir.trim_end_matches('/'), info.name_lossy())
+            } else {
+                dir
+            }
+        });
+        let session = RtorrentSession {
+            directory,
+            custom1: fastresume
+                .qbt_category
+                .as_deref()
+                .map(|category| percent_encode(category.as_bytes())),
+            state: fastresume.paused.map(|paused| !paused),
+            complete: fastresume.pieces.as_deref().map(all_pieces),
+            total_uploaded: fastresume.total_uploaded,
+            total_downloaded: fastresume.total_downloaded,
+            timestamp_finished: fastresume.completed_time,
+            ..RtorrentSession::default()
+        };
+
+        let bitfield = fastresume.pieces.as_ref().map(|pieces| {
+            if all_pieces(pieces) {
+                RtorrentBitfield::Complete(pieces.len() as u64)
+            } else {
+                RtorrentBitfield::Bits(pack_pieces(pieces))
+            }
+        });
+        let files = fastresume.file_priority.as_ref().map(|priorities| {
+            priorities
+                .iter()
+                .map(|&priority| RtorrentFile {
+                    priority: Some(to_rtorrent_priority(priority)),
+                    ..RtorrentFile::default()
+                })
+                .collect()
+        });
+        let trackers = fastresume.trackers.as_ref().map(|tiers| {
+            tiers
+                .iter()
+                .flatten()
+                .map(|url| {
+                    let tracker = RtorrentTracker {
+                        enabled: Some(true),
+                        extra: Extra::new(),
+                    };
+                    (url.clone(), tracker)
+                })
+                .collect()
+        });
+        let resume = RtorrentResume {
+            bitfield,
+            files,
+            trackers,
+            extra: Extra::new(),
+        };
+        (session, resume)
+    }
+}
+
+impl ToBencode for RtorrentSession {
+    fn to_bencode(&self) -> Result<Vec<u8>, EncodingError> {
+        let mut dict = BTreeMap::new();
+        insert_extra(&mut dict, &self.extra)?;
+        insert_strings(
+            &mut dict,
+            &[
+                (b"directory", &self.directory),
+                (b"tied_to_file", &self.tied_to_file),
+                (b"custom1", &self.custom1),
+            ],
+        );
+        insert_integers(
+            &mut dict,
+            &[
+                (b"priority", self.priority),
+                (b"total_uploaded", self.total_uploaded),
+                (b"total_downloaded", self.total_downloaded),
+                (b"timestamp.started", self.timestamp_started),
+                (b"timestamp.finished", self.timestamp_finished),
+            ],
+        )?;
+        if let Some(state) = self.state {
+            dict.insert(&b"state"[..], flag_value(state));
+        }
+        if let Some(complete) = self.complete {
+            dict.insert(&b"complete"[..], flag_value(complete));
+        }
+        if let Some(views) = &self.views {
+            dict.insert(&b"views"[..], string_list_value(views));
+        }
+        encode_to_bytes(&BencodeValue::Dictionary(dict))
+    }
+}
+
+impl RtorrentFile {
+    fn from_bencode(value: &BencodeValue, path: Path) -> Result<RtorrentFile, FieldError> {
+        let fields = Fields::new(value, path)?;
+        Ok(RtorrentFile {
+            completed: fields.integer(b"completed")?,
+            mtime: fields.integer(b"mtime")?,
+            priority: fields.integer(b"priority")?,
+            extra: fields.extra(&[b"completed", b"mtime", b"priority"]),
+        })
+    }
+
+    fn to_value(&self) -> Result<BencodeValue<'_>, EncodingError> {
+        let mut dict = BTreeMap::new();
+        insert_extra(&mut dict, &self.extra)?;
+        insert_integers(
+            &mut dict,
+            &[
+                (b"completed", self.completed),
+                (b"mtime", self.mtime),
+                (b"priority", self.priority),
+            ],
+        )?;
+        Ok(BencodeValue::Dictionary(dict))
+    }
+}
+
+impl RtorrentResume {
+    pub fn from_bytes(input: &[u8]) -> Result<RtorrentResume, FieldError> {
+        RtorrentResume::from_bencode(&parse_document(input)?)
+    }
+
+    pub fn from_bencode(value: &BencodeValue) -> Result<RtorrentResume, FieldError> {
+        let fields = Fields::new(value, Path::root())?;
+
+        let bitfield = match fields.get(b"bitfield") {
+            Some(BencodeValue::ByteString(bits)) => Some(RtorrentBitfield::Bits(bits.to_vec())),
+            Some(_) => fields
+                .unsigned(b"bitfield")?
+                .map(RtorrentBitfield::Complete),
+            None => None,
+        };
+
+        let files = match fields.list(b"files")? {
+            Some(list) => {
+                let path = fields.path_of(b"files");
+                Some(
+                    list.iter()
+                        .enumerate()
+                        .map(|(i, file)| RtorrentFile::from_bencode(file, path.index(i)))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+            None => None,
+        };
+
+        let trackers = match fields.dictionary(b"trackers")? {
+            Some(entries) => {
+                let mut trackers = BTreeMap::new();
+                for (url, tracker) in entries.iter() {
+                    let path = entries.path_of(url);
+                    let url = String::from_utf8(url.to_vec())
+                        .map_err(|_| FieldError::invalid(path.clone(), "URL is not UTF-8"))?;
+                    let tracker = Fields::new(tracker, path)?;
+                    trackers.insert(
+                        url,
+                        RtorrentTracker {
+                            enabled: tracker.flag(b"enabled")?,
+                            extra: tracker.extra(&[b"enabled"]),
+                        },
+                    );
+                }
+                Some(trackers)
+            }
+            None => None,
+        };
+
+        Ok(RtorrentResume {
+            bitfield,
+            files,
+            trackers,
+            extra: fields.extra(&[b"bitfield", b"files", b"trackers"]),
+        })
+    }
+}
+
+impl ToBencode for RtorrentResume {
+    fn to_bencode(&self) -> Result<Vec<u8>, EncodingError> {
+        let mut dict = BTreeMap::new();
+        insert_extra(&mut dict, &self.extra)?;
+        match &self.bitfield {
+            Some(RtorrentBitfield::Complete(count)) => {
+                dict.insert(&b"bitfield"[..], integer_value(*count)?);
+            }
+            Some(RtorrentBitfield::Bits(bits)) => {
+                dict.insert(&b"bitfield"[..], BencodeValue::ByteString(bits));
+            }
+            None => {}
+        }
+        if let Some(files) = &self.files {
+            let list = files
+                .iter()
+                .map(RtorrentFile::to_value)
+                .collect::<Result<_, _>>()?;
+            dict.insert(&b"files"[..], BencodeValue::List(list));
+        }
+        if let Some(trackers) = &self.trackers {
+            let mut entries = BTreeMap::new();
+            for (url, tracker) in trackers {
+                let mut entry = BTreeMap::new();
+                insert_extra(&mut entry, &tracker.extra)?;
+                if let Some(enabled) = tracker.enabled {
+                    entry.insert(&b"enabled"[..], flag_value(enabled));
+                }
+                entries.insert(url.as_bytes(), BencodeValue::Dictionary(entry));
+            }
+            dict.insert(&b"trackers"[..], BencodeValue::Dictionary(entries));
+        }
+        encode_to_bytes(&BencodeValue::Dictionary(dict))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ten pieces of 16 KiB, in a folder of two files or as one file
+    fn info(multi_file: bool) -> Info {
+        let mut input = b"d".to_vec();
+        if multi_file {
+            input.extend_from_slice(
+                b"5:filesld6:lengthi100000e4:pathl1:aeed6:lengthi63840e4:pathl1:beee",
+            );
+        } else {
+            input.extend_from_slice(b"6:lengthi163840e");
+        }
+        input.extend_from_slice(b"4:name4:data12:piece lengthi16384e6:pieces200:");
+        input.extend_from_slice(&[0; 200]);
+        input.push(b'e');
+        Info::from_bencode(&parse_document(&input).unwrap(), Path::root()).unwrap()
+    }
+
+    #[test]
+    fn test_transmission_round_trip() {
+        let input = b"d13:activity-datei1700000500e10:added-datei1700000000e7:corrupti0e11:destination10:/home/data3:dndli0ei1ee9:done-datei0e10:downloadedi4096e6:labelsl5:linuxe8:maxPeersi50e4:name4:data6:pausedi1e8:priorityli1ei0ee8:progressd6:blocks4:none6:pieces2:\xa0\x00e8:uploadedi0ee";
+        let resume = TransmissionResume::from_bytes(input).unwrap();
+
+        assert_eq!(resume.destination.as_deref(), Some("/home/data"));
+        assert_eq!(resume.dnd, Some(vec![false, true]));
+        assert_eq!(resume.priority, Some(vec![1, 0]));
+        assert_eq!(resume.paused, Some(true));
+        let progress = resume.progress.as_ref().unwrap();
+        assert_eq!(progress.pieces, Some(PieceProgress::Bits(vec![0xa0, 0])));
+        assert!(progress.extra.contains_key(b"blocks".as_slice()));
+        assert!(resume.extra.contains_key(b"maxPeers".as_slice()));
+        assert!(!resume.is_complete());
+
+        assert_eq!(resume.to_bencode().unwrap(), input.to_vec());
+    }
+
+    #[test]
+    fn test_transmission_conversion() {
+        let input = b"d11:destination10:/home/data3:dndli0ei1ee6:labelsl5:linuxe4:name4:data6:pausedi1e8:priorityli1ei0ee8:progressd6:pieces2:\xa0\x00ee";
+        let transmission = TransmissionResume::from_bytes(input).unwrap();
+        let resume = transmission.to_fastresume(&info(true));
+
+        assert_eq!(resume.save_path.as_deref(), Some(&b"/home/data"[..]));
+        assert_eq!(resume.file_priority, Some(vec![7, 0]));
+        assert_eq!(resume.qbt_tags, Some(vec!["linux".to_string()]));
+        assert_eq!(resume.pieces, Some(vec![1, 0, 1, 0, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(resume.paused, Some(true));
+
+        let back = TransmissionResume::from_fastresume(&resume);
+        assert_eq!(back.priority, transmission.priority);
+        assert_eq!(back.dnd, transmission.dnd);
+        assert_eq!(back.progress, transmission.progress);
+        assert_eq!(back.labels, transmission.labels);
+        assert_eq!(back.destination, transmission.destination);
+    }
+
+    #[test]
+    fn test_rtorrent_round_trip() {
+        let session = b"d8:completei1e7:custom17:my%20tv9:directory14:/srv/data/data15:ignore_commandsi0e8:priorityi2e5:statei1e12:tied_to_file15:/w/data.torrent18:timestamp.finishedi1700003600e17:timestamp.startedi1700000000e16:total_downloadedi163840e14:total_uploadedi5e5:viewsl4:mainee";
+        let parsed = RtorrentSession::from_bytes(session).unwrap();
+        assert_eq!(parsed.state, Some(true));
+        assert_eq!(parsed.custom1.as_deref(), Some("my%20tv"));
+        assert_eq!(parsed.to_bencode().unwrap(), session.to_vec());
+
+        let resume = b"d8:bitfieldi10e5:filesld9:completedi7e5:mtimei1700003600e8:priorityi1eed9:completedi4e5:mtimei1700003600e8:priorityi0eee8:trackersd26:udp://tracker.example:1337d7:enabledi1e13:extra_trackeri0eeee";
+        let parsed = RtorrentResume::from_bytes(resume).unwrap();
+        assert_eq!(parsed.bitfield, Some(RtorrentBitfield::Complete(10)));
+        assert_eq!(parsed.files.as_ref().unwrap()[1].priority, Some(0));
+        assert_eq!(parsed.to_bencode().unwrap(), resume.to_vec());
+    }
+
+    #[test]
+    fn test_rtorrent_conversion() {
+        let session = RtorrentSession::from_bytes(
+            b"d7:custom17:my%20tv9:directory14:/srv/data/data5:statei0ee",
+        )
+        .unwrap();
+        let resume = RtorrentResume::from_bytes(
+            b"d8:bitfield2:\xff\x405:filesld8:priorityi2eed8:priorityi0eee8:trackersd5:udp:ad7:enabledi1ee5:udp:bd7:enabledi0eeee",
+        )
+        .unwrap();
+        let info = info(true);
+
+        let fastresume = session.to_fastresume(Some(&resume), &info);
+        assert_eq!(fastresume.save_path.as_deref(), Some(&b"/srv/data"[..]));
+        assert_eq!(fastresume.name.as_deref(), Some(&b"data"[..]));
+        assert_eq!(fastresume.paused, Some(true));
+        assert_eq!(fastresume.qbt_category.as_deref(), Some("my tv"));
+        assert_eq!(fastresume.pieces, Some(vec![1, 1, 1, 1, 1, 1, 1, 1, 0, 1]));
+        assert_eq!(fastresume.file_priority, Some(vec![7, 0]));
+        assert_eq!(fastresume.trackers, Some(vec![vec!["udp:a".to_string()]]));
+
+        let (session_back, resume_back) = RtorrentSession::from_fastresume(&fastresume, &info);
+        assert_eq!(session_back.directory, session.directory);
+        assert_eq!(session_back.custom1, session.custom1);
+        assert_eq!(session_back.state, Some(false));
+        assert_eq!(session_back.complete, Some(false));
+        assert_eq!(resume_back.bitfield, resume.bitfield);
+
+        let complete = RtorrentResume::from_bytes(b"d8:bitfieldi10ee").unwrap();
+        let fastresume = session.to_fastresume(Some(&complete), &info);
+        assert_eq!(fastresume.pieces, Some(vec![1; 10]));
+        let huge = RtorrentResume::from_bytes(b"d8:bitfieldi9000000000000000000ee").unwrap();
+        assert_eq!(session.to_fastresume(Some(&huge), &info).pieces, None);
+
+        let single = session.to_fastresume(None, &self::info(false));
+        assert_eq!(single.save_path.as_deref(), Some(&b"/srv/data/data"[..]));
+        assert_eq!(single.pieces, None);
+    }
+
+    #[test]
+    fn test_bit_packing() {
+        let pieces = [1, 0, 0, 0, 0, 0, 0, 1, 1];
+        assert_eq!(pack_pieces(&pieces), vec![0x81, 0x80]);
+        assert_eq!(unpack_bits(&[0x81, 0x80], 9), pieces.to_vec());
+        assert_eq!(parent_directory("/srv/data/"), "/srv");
+        assert_eq!(parent_directory("/data"), "/");
+    }
+}
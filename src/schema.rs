@@ -0,0 +1,480 @@
+use std::str;
+
+use crate::common::BencodeValue;
+use crate::fields::FieldError;
+use crate::path::Path;
+
+/// Expected shape of a bencode value
+///
+/// Schemas are built from the constructors on this type and the builder
+/// methods of the per-type structs, which convert into `Schema` wherever one
+/// is expected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Schema {
+    Any,
+    Integer(IntegerSchema),
+    Bytes(BytesSchema),
+    List(ListSchema),
+    Dictionary(DictionarySchema),
+    /// Matches when at least one alternative does
+    OneOf(Vec<Schema>),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntegerSchema {
+    min: Option<i64>,
+    max: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BytesSchema {
+    min_len: Option<usize>,
+    max_len: Option<usize>,
+    multiple_of: Option<usize>,
+    utf8: bool,
+    allowed: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListSchema {
+    items: Box<Schema>,
+    min_len: Option<usize>,
+    max_len: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DictionarySchema {
+    fields: Vec<FieldSchema>,
+    /// Schema for keys not listed in `fields`; `None` accepts anything
+    others: Option<Box<Schema>>,
+    deny_others: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct FieldSchema {
+    key: Vec<u8>,
+    required: bool,
+    schema: Schema,
+}
+
+impl Schema {
+    pub fn integer() -> IntegerSchema {
+        IntegerSchema::default()
+    }
+
+    pub fn bytes() -> BytesSchema {
+        BytesSchema::default()
+    }
+
+    /// A byte string that must be valid UTF-8
+    pub fn string() -> BytesSchema {
+        BytesSchema {
+            utf8: true,
+            ..BytesSchema::default()
+        }
+    }
+
+    pub fn list(items: impl Into<Schema>) -> ListSchema {
+        ListSchema {
+            items: Box::new(items.into()),
+            min_len: None,
+            max_len: None,
+        }
+    }
+
+    pub fn dictionary() -> DictionarySchema {
+        DictionarySchema::default()
+    }
+
+    pub fn one_of(alternatives: Vec<Schema>) -> Schema {
+        Schema::OneOf(alternatives)
+    }
+
+    /// Check `value`, collecting every violation rather than stopping at the first
+    pub fn validate(&self, value: &BencodeValue) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        self.check(value, &Path::root(), &mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn check(&self, value: &BencodeValue, path: &Path, errors: &mut Vec<FieldError>) {
+        match (self, value) {
+            (Schema::Any, _) => {}
+            (Schema::Integer(schema), BencodeValue::Integer(i)) => {
+                schema.check(*i as i64, path, errors)
+            }
+            (Schema::Bytes(schema), BencodeValue::ByteString(bytes)) => {
+                schema.check(bytes, path, errors)
+            }
+            (Schema::List(schema), BencodeValue::List(list)) => schema.check(list, path, errors),
+            (Schema::Dictionary(schema), BencodeValue::Dictionary(_)) => {
+                schema.check(value, path, errors)
+            }
+            // The closest alternative explains the failure best
+            (Schema::OneOf(alternatives), _) => {
+                let mut best: Option<Vec<FieldError>> = None;
+                for alternative in alternatives {
+                    let mut attempt = Vec::new();
+                    alternative.check(value, path, &mut attempt);
+                    if attempt.is_empty() {
+                        return;
+                    }
+                    if best
+                        .as_ref()
+                        .map_or(true, |best| attempt.len() < best.len())
+                    {
+                        best = Some(attempt);
+                    }
+                }
+                errors.extend(best.unwrap_or_default());
+            }
+            (Schema::Integer(_), _) => errors.push(FieldError::wrong_type(path.clone(), "integer")),
+            (Schema::Bytes(_), _) => {
+                errors.push(FieldError::wrong_type(path.clone(), "byte string"))
+            }
+            (Schema::List(_), _) => errors.push(FieldError::wrong_type(path.clone(), "list")),
+            (Schema::Dictionary(_), _) => {
+                errors.push(FieldError::wrong_type(path.clone(), "dictionary"))
+            }
+        }
+    }
+
+    /// BEP 3 metainfo, with the BEP 52 keys allowed but not checked in depth
+    pub fn metainfo() -> Schema {
+        let string_list = || Schema::list(Schema::string());
+        let file = Schema::dictionary()
+            .required("length", Schema::integer().min(0))
+            .required("path", string_list().min_len(1));
+        let info = Schema::dictionary()
+            .required("name", Schema::string())
+            .required("piece length", Schema::integer().min(1))
+            .optional("pieces", Schema::bytes().multiple_of(20))
+            .optional("length", Schema::integer().min(0))
+            .optional("files", Schema::list(file).min_len(1))
+            .optional("private", Schema::integer().range(0, 1))
+            .optional("meta version", Schema::integer().min(2))
+            .optional("file tree", Schema::dictionary());
+        Schema::dictionary()
+            .required("info", info)
+            .optional("announce", Schema::string())
+            .optional("announce-list", Schema::list(string_list()))
+            .optional("creation date", Schema::integer())
+            .optional("comment", Schema::string())
+            .optional("created by", Schema::string())
+            .optional(
+                "url-list",
+                Schema::one_of(vec![Schema::string().into(), string_list().into()]),
+            )
+            .optional(
+                "piece layers",
+                Schema::dictionary().others(Schema::bytes().multiple_of(32)),
+            )
+            .into()
+    }
+
+    /// BEP 5 KRPC messages of all three kinds
+    pub fn krpc() -> Schema {
+        let node_id = || Schema::bytes().len(20);
+        let message = |kind: &str| {
+            Schema::dictionary()
+                .required("t", Schema::bytes())
+                .required("y", Schema::bytes().allowed(&[kind]))
+        };
+        let query = message("q")
+            .required("q", Schema::string().min_len(1))
+            .required("a", Schema::dictionary().required("id", node_id()));
+        let response = message("r").required("r", Schema::dictionary().required("id", node_id()));
+        let error = message("e").required(
+            "e",
+            Schema::list(Schema::one_of(vec![
+                Schema::integer().into(),
+                Schema::bytes().into(),
+            ]))
+            .len(2),
+        );
+        Schema::one_of(vec![query.into(), response.into(), error.into()])
+    }
+}
+
+impl IntegerSchema {
+    pub fn min(mut self, min: i64) -> Self {
+        self.min = Some(min);
+        self
+    }
+
+    pub fn max(mut self, max: i64) -> Self {
+        self.max = Some(max);
+        self
+    }
+
+    /// Both bounds, inclusive
+    pub fn range(self, min: i64, max: i64) -> Self {
+        self.min(min).max(max)
+    }
+
+    fn check(&self, value: i64, path: &Path, errors: &mut Vec<FieldError>) {
+        if let Some(min) = self.min.filter(|&min| value < min) {
+            errors.push(FieldError::invalid(
+                path.clone(),
+                format!("must be at least {}", min),
+            ));
+        }
+        if let Some(max) = self.max.filter(|&max| value > max) {
+            errors.push(FieldError::invalid(
+                path.clone(),
+                format!("must be at most {}", max),
+            ));
+        }
+    }
+}
+
+impl BytesSchema {
+    pub fn min_len(mut self, min_len: usize) -> Self {
+        self.min_len = Some(min_len);
+        self
+    }
+
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    /// Exactly `len` bytes long
+    pub fn len(self, len: usize) -> Self {
+        self.min_len(len).max_len(len)
+    }
+
+    /// Length must be a multiple of `n`, such as 20 for concatenated SHA-1 hashes
+    pub fn multiple_of(mut self, n: usize) -> Self {
+        self.multiple_of = Some(n);
+        self
+    }
+
+    /// Restrict the value to one of `values`
+    pub fn allowed(mut self, values: &[&str]) -> Self {
+        self.allowed = values.iter().map(|v| v.as_bytes().to_vec()).collect();
+        self
+    }
+
+    fn check(&self, bytes: &[u8], path: &Path, errors: &mut Vec<FieldError>) {
+        let mut invalid = |reason: String| errors.push(FieldError::invalid(path.clone(), reason));
+        match (self.min_len, self.max_len) {
+            (Some(min), Some(max)) if min == max && bytes.len() != min => {
+                invalid(format!("must be {} bytes long", min))
+            }
+            (Some(min), _) if bytes.len() < min => {
+                invalid(format!("must be at least {} bytes long", min))
+            }
+            (_, Some(max)) if bytes.len() > max => {
+                invalid(format!("must be at most {} bytes long", max))
+            }
+            _ => {}
+        }
+        if let Some(n) = self.multiple_of.filter(|&n| n > 0 && bytes.len() % n != 0) {
+            invalid(format!("length {} is not a multiple of {}", bytes.len(), n));
+        }
+        if self.utf8 && str::from_utf8(bytes).is_err() {
+            invalid("must be valid UTF-8".to_string());
+        }
+        if !self.allowed.is_empty() && !self.allowed.iter().any(|v| v == bytes) {
+            let allowed: Vec<String> = self
+                .allowed
+                .iter()
+                .map(|v| format!("{:?}", String::from_utf8_lossy(v)))
+                .collect();
+            invalid(format!("must be one of {}", allowed.join(", ")));
+        }
+    }
+}
+
+impl ListSchema {
+    pub fn min_len(mut self, min_len: usize) -> Self {
+        self.min_len = Some(min_len);
+        self
+    }
+
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    /// Exactly `len` elements
+    pub fn len(self, len: usize) -> Self {
+        self.min_len(len).max_len(len)
+    }
+
+    fn check(&self, list: &[BencodeValue], path: &Path, errors: &mut Vec<FieldError>) {
+        if let Some(min) = self.min_len.filter(|&min| list.len() < min) {
+            errors.push(FieldError::invalid(
+                path.clone(),
+                format!("must have at least {} elements", min),
+            ));
+        }
+        if let Some(max) = self.max_len.filter(|&max| list.len() > max) {
+            errors.push(FieldError::invalid(
+                path.clone(),
+                format!("must have at most {} elements", max),
+            ));
+        }
+        for (index, item) in list.iter().enumerate() {
+            self.items.check(item, &path.index(index), errors);
+        }
+    }
+}
+
+impl DictionarySchema {
+    pub fn required(mut self, key: &str, schema: impl Into<Schema>) -> Self {
+        self.fields.push(FieldSchema {
+            key: key.as_bytes().to_vec(),
+            required: true,
+            schema: schema.into(),
+        });
+        self
+    }
+
+    pub fn optional(mut self, key: &str, schema: impl Into<Schema>) -> Self {
+        self.fields.push(FieldSchema {
+            key: key.as_bytes().to_vec(),
+            required: false,
+            schema: schema.into(),
+        });
+        self
+    }
+
+    /// Schema for every key not declared with `required` or `optional`
+    pub fn others(mut self, schema: impl Into<Schema>) -> Self {
+        self.others = Some(Box::new(schema.into()));
+        self
+    }
+
+    /// Reject keys that were not declared
+    pub fn deny_others(mut self) -> Self {
+        self.deny_others = true;
+        self
+    }
+
+    fn check(&self, value: &BencodeValue, path: &Path, errors: &mut Vec<FieldError>) {
+        let dict = match value {
+            BencodeValue::Dictionary(dict) => dict,
+            _ => return,
+        };
+        for field in &self.fields {
+            match dict.get(field.key.as_slice()) {
+                Some(value) => field.schema.check(value, &path.key(&field.key), errors),
+                None if field.required => errors.push(FieldError::missing(path.key(&field.key))),
+                None => {}
+            }
+        }
+        for (key, value) in dict {
+            if self.fields.iter().any(|field| field.key == *key) {
+                continue;
+            }
+            if self.deny_others {
+                errors.push(FieldError::invalid(path.key(key), "unexpected key"));
+            } else if let Some(schema) = &self.others {
+                schema.check(value, &path.key(key), errors);
+            }
+        }
+    }
+}
+
+impl From<IntegerSchema> for Schema {
+    fn from(schema: IntegerSchema) -> Self {
+        Schema::Integer(schema)
+    }
+}
+
+impl From<BytesSchema> for Schema {
+    fn from(schema: BytesSchema) -> Self {
+        Schema::Bytes(schema)
+    }
+}
+
+impl From<ListSchema> for Schema {
+    fn from(schema: ListSchema) -> Self {
+        Schema::List(schema)
+    }
+}
+
+impl From<DictionarySchema> for Schema {
+    fn from(schema: DictionarySchema) -> Self {
+        Schema::Dictionary(schema)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::parse_document;
+
+    fn violations(schema: &Schema, input: &[u8]) -> Vec<String> {
+        match schema.validate(&parse_document(input).unwrap()) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_collects_all_violations() {
+        let schema: Schema = Schema::dictionary()
+            .required("id", Schema::bytes().len(4))
+            .required("port", Schema::integer().range(1, 65535))
+            .optional("tags", Schema::list(Schema::string()).max_len(2))
+            .deny_others()
+            .into();
+        assert!(violations(&schema, b"d2:id4:abcd4:porti80ee").is_empty());
+        assert_eq!(
+            violations(&schema, b"d2:id3:abc4:tagsl1:a1:\xffi1ee1:xi0ee"),
+            [
+                "id: must be 4 bytes long",
+                "port: missing",
+                "tags: must have at most 2 elements",
+                "tags[1]: must be valid UTF-8",
+                "tags[2]: expected byte string",
+                "x: unexpected key",
+            ]
+        );
+        assert_eq!(violations(&schema, b"le"), ["<root>: expected dictionary"]);
+    }
+
+    #[test]
+    fn test_metainfo() {
+        let schema = Schema::metainfo();
+        let valid = b"d8:announce3:url4:infod6:lengthi5e4:name1:a12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaaee";
+        assert!(violations(&schema, valid).is_empty());
+        assert_eq!(
+            violations(
+                &schema,
+                b"d4:infod6:lengthi-1e12:piece lengthi0e6:pieces3:abce8:url-listi1ee"
+            ),
+            [
+                "info.name: missing",
+                "info.piece length: must be at least 1",
+                "info.pieces: length 3 is not a multiple of 20",
+                "info.length: must be at least 0",
+                "url-list: expected byte string",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_krpc() {
+        let schema = Schema::krpc();
+        let id = "20:abcdefghij0123456789";
+        let query = format!("d1:ad2:id{}e1:q4:ping1:t2:aa1:y1:qe", id);
+        assert!(violations(&schema, query.as_bytes()).is_empty());
+        let response = format!("d1:rd2:id{}e1:t2:aa1:y1:re", id);
+        assert!(violations(&schema, response.as_bytes()).is_empty());
+        assert!(violations(&schema, b"d1:eli201e7:Generice1:t2:aa1:y1:ee").is_empty());
+
+        assert_eq!(
+            violations(&schema, b"d1:rd2:id3:abce1:t2:aa1:y1:re"),
+            ["r.id: must be 20 bytes long"]
+        );
+        assert_eq!(violations(&schema, b"d1:t2:aa1:y1:ee"), ["e: missing"]);
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::common::BencodeValue;
+use crate::edit::set_value;
+use crate::encoder::{encode_to_bytes, EncodingError};
+use crate::fields::{insert_extra, parse_document, parse_strict, Extra, FieldError, Fields};
+use crate::path::Path;
+use crate::span::raw_value;
+
+/// Produces signatures for [`sign_torrent`]
+///
+/// BEP 35 leaves the algorithm to the certificate; implementations for
+/// ed25519 keys are provided with the `sign` feature.
+pub trait Signer {
+    fn sign(&self, message: &[u8]) -> Vec<u8>;
+
+    /// DER certificate to embed next to the signature, if any
+    fn certificate(&self) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Checks signatures for [`verify_torrent`]
+pub trait Verifier {
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool;
+}
+
+/// One entry of the `signatures` dictionary, keyed by the signer's identity
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SignatureEntry {
+    pub certificate: Option<Vec<u8>>,
+    /// Raw bencoded dictionary of additional signed data
+    pub info: Option<Vec<u8>>,
+    pub signature: Vec<u8>,
+    pub extra: Extra,
+}
+
+/// Error type for signing and verifying torrents
+#[derive(Debug)]
+pub enum SignatureError {
+    Field(FieldError),
+    Encoding(EncodingError),
+    /// There is no entry for this signer
+    NotSigned(String),
+    InvalidSignature(String),
+}
+
+impl From<FieldError> for SignatureError {
+    fn from(error: FieldError) -> Self {
+        SignatureError::Field(error)
+    }
+}
+
+impl From<EncodingError> for SignatureError {
+    fn from(error: EncodingError) -> Self {
+        SignatureError::Encoding(error)
+    }
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Field(e) => write!(f, "{}", e),
+            SignatureError::Encoding(e) => write!(f, "{}", e),
+            SignatureError::NotSigned(signer) => write!(f, "Not signed by {}", signer),
+            SignatureError::InvalidSignature(signer) => {
+                write!(f, "Invalid signature from {}", signer)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+const ENTRY_KEYS: &[&[u8]] = &[b"certificate", b"info", b"signature"];
+
+impl SignatureEntry {
+    pub fn from_bencode(value: &BencodeValue, path: Path) -> Result<SignatureEntry, FieldError> {
+        let fields = Fields::new(value, path)?;
+        let info = match fields.get(b"info") {
+            Some(info) => {
+                fields.require_dictionary(b"info")?;
+                Some(
+                    encode_to_bytes(info)
+                        .map_err(|e| FieldError::invalid(fields.path_of(b"info"), e.to_string()))?,
+                )
+            }
+            None => None,
+        };
+        Ok(SignatureEntry {
+            certificate: fields.bytes(b"certificate")?.map(<[u8]>::to_vec),
+            info,
+            signature: fields.require_bytes(b"signature")?.to_vec(),
+            extra: fields.extra(ENTRY_KEYS),
+        })
+    }
+
+    pub fn to_bencode(&self) -> Result<Vec<u8>, EncodingError> {
+        let mut dict = BTreeMap::new();
+        insert_extra(&mut dict, &self.extra)?;
+        if let Some(certificate) = &self.certificate {
+            dict.insert(&b"certificate"[..], BencodeValue::ByteString(certificate));
+        }
+        if let Some(info) = &self.info {
+            let info =
+                parse_document(info).map_err(|e| EncodingError::CustomError(e.to_string()))?;
+            dict.insert(&b"info"[..], info);
+        }
+        dict.insert(&b"signature"[..], BencodeValue::ByteString(&self.signature));
+        encode_to_bytes(&BencodeValue::Dictionary(dict))
+    }
+}
+
+/// The bytes a BEP 35 signature covers: the torrent's `info` dictionary
+/// followed by the entry's own `info` dictionary when it has one
+pub fn signed_message(info: &[u8], entry_info: Option<&[u8]>) -> Vec<u8> {
+    let mut message = info.to_vec();
+    message.extend_from_slice(entry_info.unwrap_or_default());
+    message
+}
+
+/// Every entry of a torrent's `signatures` dictionary
+pub fn read_signatures(input: &[u8]) -> Result<BTreeMap<String, SignatureEntry>, FieldError> {
+    let value = parse_document(input)?;
+    let fields = Fields::new(&value, Path::root())?;
+    let mut entries = BTreeMap::new();
+    if let Some(signatures) = fields.dictionary(b"signatures")? {
+        for (signer, entry) in signatures.iter() {
+            let path = signatures.path_of(signer);
+            let name = String::from_utf8(signer.to_vec())
+                .map_err(|_| FieldError::invalid(path.clone(), "signer must be UTF-8"))?;
+            entries.insert(name, SignatureEntry::from_bencode(entry, path)?);
+        }
+    }
+    Ok(entries)
+}
+
+/// Sign the `info` dictionary of a .torrent file as `signer_name` and add the
+/// entry to `signatures`
+///
+/// The input must be canonical bencode, as [`verify_torrent`] requires. The
+/// entry is spliced in without touching the `info` bytes, so the info-hash is
+/// unchanged. `entry_info` is optional extra data covered by the signature.
+pub fn sign_torrent(
+    input: &[u8],
+    signer_name: &str,
+    signer: &dyn Signer,
+    entry_info: Option<&BencodeValue>,
+) -> Result<Vec<u8>, SignatureError> {
+    parse_strict(input)?;
+    let info_path = Path::root().key(b"info");
+    let info = raw_value(input, &info_path)?.ok_or_else(|| FieldError::missing(info_path))?;
+    let entry_info = entry_info.map(encode_to_bytes).transpose()?;
+
+    let entry = SignatureEntry {
+        certificate: signer.certificate(),
+        signature: signer.sign(&signed_message(info, entry_info.as_deref())),
+        info: entry_info,
+        extra: Extra::new(),
+    };
+
+    let signatures = Path::root().key(b"signatures");
+    let mut output = input.to_vec();
+    if raw_value(&output, &signatures)?.is_none() {
+        output = set_value(&output, &signatures, b"de")?;
+    }
+    Ok(set_value(
+        &output,
+        &signatures.key(signer_name.as_bytes()),
+        &entry.to_bencode()?,
+    )?)
+}
+
+/// Check the signature of `signer_name` on a .torrent file
+///
+/// Input with unsorted or repeated keys is rejected: the signed bytes and the
+/// values a parser reads must be the same, and a second `info` key could
+/// otherwise carry unsigned content.
+pub fn verify_torrent(
+    input: &[u8],
+    signer_name: &str,
+    verifier: &dyn Verifier,
+) -> Result<(), SignatureError> {
+    parse_strict(input)?;
+    let info_path = Path::root().key(b"info");
+    let info = raw_value(input, &info_path)?.ok_or_else(|| FieldError::missing(info_path))?;
+    let entry = read_signatures(input)?
+        .remove(signer_name)
+        .ok_or_else(|| SignatureError::NotSigned(signer_name.to_string()))?;
+    let entry_path = Path::root().key(b"signatures").key(signer_name.as_bytes());
+    let entry_info = raw_value(input, &entry_path.key(b"info"))?;
+
+    if verifier.verify(&signed_message(info, entry_info), &entry.signature) {
+        Ok(())
+    } else {
+        Err(SignatureError::InvalidSignature(signer_name.to_string()))
+    }
+}
+
+#[cfg(feature = "sign")]
+impl Signer for ed25519_dalek::SigningKey {
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        ed25519_dalek::Signer::sign(self, message)
+            .to_bytes()
+            .to_vec()
+    }
+}
+
+#[cfg(feature = "sign")]
+impl Verifier for ed25519_dalek::VerifyingKey {
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match ed25519_dalek::Signature::from_slice(signature) {
+            Ok(signature) => self.verify_strict(message, &signature).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TORRENT: &[u8] = b"d8:announce8:http://a4:infod6:lengthi1e4:name1:xee";
+
+    /// Stand-in algorithm: the "signature" is the message reversed
+    struct Reverse;
+
+    impl Signer for Reverse {
+        fn sign(&self, message: &[u8]) -> Vec<u8> {
+            message.iter().rev().copied().collect()
+        }
+
+        fn certificate(&self) -> Option<Vec<u8>> {
+            Some(b"cert".to_vec())
+        }
+    }
+
+    impl Verifier for Reverse {
+        fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+            self.sign(message) == signature
+        }
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signed = sign_torrent(TORRENT, "org.example", &Reverse, None).unwrap();
+        let info = Path::root().key(b"info");
+        assert_eq!(raw_value(&signed, &info), raw_value(TORRENT, &info));
+        assert!(signed.starts_with(&TORRENT[..TORRENT.len() - 1]));
+        verify_torrent(&signed, "org.example", &Reverse).unwrap();
+
+        let entries = read_signatures(&signed).unwrap();
+        assert_eq!(
+            entries["org.example"].certificate.as_deref(),
+            Some(&b"cert"[..])
+        );
+
+        let error = verify_torrent(&signed, "org.other", &Reverse).unwrap_err();
+        assert_eq!(error.to_string(), "Not signed by org.other");
+        let tampered = set_value(&signed, &"info.length".parse().unwrap(), b"i2e").unwrap();
+        let error = verify_torrent(&tampered, "org.example", &Reverse).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid signature from org.example");
+    }
+
+    #[test]
+    fn test_non_canonical_input_is_rejected() {
+        let signed = sign_torrent(TORRENT, "org.example", &Reverse, None).unwrap();
+
+        // A second, unsigned info after the signed one is what a parser would read
+        let info = b"4:infod6:lengthi1e4:name1:xe";
+        let at = signed.windows(info.len()).position(|w| w == info).unwrap() + info.len();
+        let mut forged = signed.clone();
+        forged.splice(at..at, b"4:infod6:lengthi1e4:name4:evile".iter().copied());
+        assert_eq!(
+            raw_value(&forged, &Path::root().key(b"info")).unwrap(),
+            Some(&b"d6:lengthi1e4:name4:evile"[..])
+        );
+        let error = verify_torrent(&forged, "org.example", &Reverse).unwrap_err();
+        assert_eq!(error.to_string(), "info: duplicate key");
+
+        let mut appended = signed[..signed.len() - 1].to_vec();
+        appended.extend_from_slice(b"4:infod6:lengthi1e4:name4:evilee");
+        assert!(verify_torrent(&appended, "org.example", &Reverse).is_err());
+
+        let unsorted = b"d4:infod4:name1:x6:lengthi1eee";
+        let error = sign_torrent(unsorted, "org.example", &Reverse, None).unwrap_err();
+        assert_eq!(error.to_string(), "info.length: key is not in sorted order");
+    }
+
+    #[test]
+    fn test_entry_info_is_signed() {
+        let extra = parse_document(b"d7:expiresi100ee").unwrap();
+        let signed = sign_torrent(TORRENT, "a", &Reverse, Some(&extra)).unwrap();
+        let signed = sign_torrent(&signed, "b", &Reverse, None).unwrap();
+        let entries = read_signatures(&signed).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries["a"].info.as_deref(), Some(&b"d7:expiresi100ee"[..]));
+        verify_torrent(&signed, "a", &Reverse).unwrap();
+        verify_torrent(&signed, "b", &Reverse).unwrap();
+
+        let tampered = set_value(
+            &signed,
+            &"signatures.a.info.expires".parse().unwrap(),
+            b"i999e",
+        )
+        .unwrap();
+        assert!(verify_torrent(&tampered, "a", &Reverse).is_err());
+        verify_torrent(&tampered, "b", &Reverse).unwrap();
+    }
+
+    #[cfg(feature = "sign")]
+    #[test]
+    fn test_ed25519() {
+        let key = ed25519_dalek::SigningKey::from_bytes(&[3; 32]);
+        let signed = sign_torrent(TORRENT, "org.example", &key, None).unwrap();
+        let entries = read_signatures(&signed).unwrap();
+        assert_eq!(entries["org.example"].signature.len(), 64);
+        assert_eq!(entries["org.example"].certificate, None);
+        verify_torrent(&signed, "org.example", &key.verifying_key()).unwrap();
+
+        let other = ed25519_dalek::SigningKey::from_bytes(&[4; 32]);
+        assert!(verify_torrent(&signed, "org.example", &other.verifying_key()).is_err());
+    }
+}
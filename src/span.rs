@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::explain::{explain, TokenKind};
+use crate::fields::{FieldError, FieldErrorKind};
+use crate::path::Path;
+
+/// Where a value sits in the input it was parsed from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueSpan {
+    pub path: Path,
+    pub range: Range<usize>,
+}
+
+/// Locate every value of the root document, in the order they end in the input
+///
+/// Trailing bytes after the root value are ignored. When a dictionary repeats
+/// a key, only the last value is located, as the parser keeps only that one.
+pub fn value_spans(input: &[u8]) -> Result<Vec<ValueSpan>, FieldError> {
+    let explanation = explain(input);
+    let mut spans = Vec::new();
+    let mut open = Vec::new();
+
+    for annotation in explanation.annotations {
+        let end = annotation.offset + annotation.raw.len();
+        match annotation.kind {
+            TokenKind::Integer(_) | TokenKind::ByteString(_) => spans.push(ValueSpan {
+                path: annotation.path,
+                range: annotation.offset..end,
+            }),
+            TokenKind::ListStart | TokenKind::DictionaryStart => open.push(annotation.offset),
+            TokenKind::End => {
+                let start = open.pop().unwrap_or(annotation.offset);
+                spans.push(ValueSpan {
+                    path: annotation.path,
+                    range: start..end,
+                });
+            }
+            TokenKind::Key(_) | TokenKind::Trailing => {}
+            TokenKind::Error { position, message } => {
+                return Err(FieldError {
+                    path: annotation.path,
+                    kind: FieldErrorKind::Parse(format!("offset {}: {}", position, message)),
+                })
+            }
+        }
+    }
+
+    Ok(without_shadowed(spans))
+}
+
+// Drop values under a repeated key that a later occurrence replaces, and
+// everything nested in them
+fn without_shadowed(spans: Vec<ValueSpan>) -> Vec<ValueSpan> {
+    let mut last: HashMap<&Path, &Range<usize>> = HashMap::new();
+    for span in &spans {
+        last.insert(&span.path, &span.range);
+    }
+
+    let mut by_depth: Vec<&ValueSpan> = spans.iter().collect();
+    by_depth.sort_by_key(|span| span.path.segments().len());
+    let mut kept: HashMap<Path, Range<usize>> = HashMap::new();
+    for span in by_depth {
+        if last[&span.path] != &span.range {
+            continue;
+        }
+        let inside_parent = match span.path.segments().split_last() {
+            Some((_, parent)) => kept.get(&Path::from(parent.to_vec())).is_some_and(|outer| {
+                outer.start <= span.range.start && span.range.end <= outer.end
+            }),
+            None => true,
+        };
+        if inside_parent {
+            kept.insert(span.path.clone(), span.range.clone());
+        }
+    }
+
+    spans
+        .into_iter()
+        .filter(|span| kept.get(&span.path) == Some(&span.range))
+        .collect()
+}
+
+/// Byte range of the value at `path`, if there is one
+pub fn find_span(input: &[u8], path: &Path) -> Result<Option<Range<usize>>, FieldError> {
+    Ok(value_spans(input)?
+        .into_iter()
+        .find(|span| &span.path == path)
+        .map(|span| span.range))
+}
+
+/// The exact original bytes of the value at `path`
+pub fn raw_value<'a>(input: &'a [u8], path: &Path) -> Result<Option<&'a [u8]>, FieldError> {
+    Ok(find_span(input, path)?.map(|range| &input[range]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::BencodeValue;
+    use crate::fields::parse_document;
+
+    #[test]
+    fn test_value_spans() {
+        let input = b"d4:infod4:name4:spame4:listli1ei2eee";
+        let spans = value_spans(input).unwrap();
+        let lookup = |path: &str| {
+            spans
+                .iter()
+                .find(|s| s.path.to_string() == path)
+                .map(|s| &input[s.range.clone()])
+        };
+
+        assert_eq!(lookup(""), Some(&input[..]));
+        assert_eq!(lookup("info"), Some(&b"d4:name4:spame"[..]));
+        assert_eq!(lookup("info.name"), Some(&b"4:spam"[..]));
+        assert_eq!(lookup("list"), Some(&b"li1ei2ee"[..]));
+        assert_eq!(lookup("list[1]"), Some(&b"i2e"[..]));
+        assert_eq!(spans.len(), 6);
+    }
+
+    #[test]
+    fn test_raw_value_keeps_original_bytes() {
+        // Keys out of order are accepted by the parser but would be reordered on re-encoding
+        let input = b"d4:infod4:name1:a6:lengthi1eee";
+        let info = raw_value(input, &Path::root().key(b"info")).unwrap();
+        assert_eq!(info, Some(&b"d4:name1:a6:lengthi1ee"[..]));
+
+        let missing = raw_value(input, &Path::root().key(b"nope")).unwrap();
+        assert_eq!(missing, None);
+    }
+
+    #[test]
+    fn test_duplicate_keys_keep_last() {
+        // The parser keeps the second `info`, which has no `length`
+        let input = b"d4:infod6:lengthi1e4:name1:ae4:infod4:name1:bee";
+        let info = raw_value(input, &Path::root().key(b"info")).unwrap();
+        assert_eq!(info, Some(&b"d4:name1:be"[..]));
+        match parse_document(input).unwrap() {
+            BencodeValue::Dictionary(dict) => {
+                assert_eq!(dict[&b"info"[..]], parse_document(info.unwrap()).unwrap())
+            }
+            _ => panic!("Expected a dictionary"),
+        }
+        assert_eq!(
+            raw_value(input, &Path::root().key(b"info").key(b"name")).unwrap(),
+            Some(&b"1:b"[..])
+        );
+        assert_eq!(
+            raw_value(input, &Path::root().key(b"info").key(b"length")).unwrap(),
+            None
+        );
+        assert_eq!(value_spans(input).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        let error = value_spans(b"d4:infoi01ee").unwrap_err();
+        assert_eq!(error.path.to_string(), "info");
+        assert!(find_span(b"l", &Path::root()).is_err());
+    }
+}
@@ -0,0 +1,391 @@
+use crate::common::BencodeValue;
+use crate::edit::{delete_value, set_value};
+use crate::encoder::{encode_to_bytes, EncodingError};
+use crate::fields::{as_list, as_string, parse_document, string_list_value, FieldError, Fields};
+use crate::path::Path;
+use crate::torrent::Metainfo;
+
+/// The trackers of a torrent grouped into BEP 12 tiers
+///
+/// Clients try the tiers in order, shuffling each tier once and moving a
+/// tracker that answers to the front of its tier. URLs are compared after
+/// [`normalize_url`], so `HTTP://Tracker:80/announce` and
+/// `http://tracker/announce` count as the same tracker.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackerTiers {
+    pub tiers: Vec<Vec<String>>,
+}
+
+impl TrackerTiers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tiers from `announce-list`, or a single tier holding `announce` when
+    /// there is no list, as BEP 12 prescribes
+    pub fn from_metainfo(metainfo: &Metainfo) -> Self {
+        if !metainfo.announce_list.is_empty() {
+            return TrackerTiers {
+                tiers: metainfo.announce_list.clone(),
+            };
+        }
+        TrackerTiers {
+            tiers: metainfo
+                .announce
+                .iter()
+                .map(|url| vec![url.clone()])
+                .collect(),
+        }
+    }
+
+    /// Read only the tracker keys of a metainfo dictionary
+    pub fn from_bytes(input: &[u8]) -> Result<Self, FieldError> {
+        Self::from_bencode(&parse_document(input)?)
+    }
+
+    pub fn from_bencode(value: &BencodeValue) -> Result<Self, FieldError> {
+        let fields = Fields::new(value, Path::root())?;
+        let mut tiers = Vec::new();
+        if let Some(list) = fields.list(b"announce-list")? {
+            for (i, tier) in list.iter().enumerate() {
+                let tier_path = fields.path_of(b"announce-list").index(i);
+                let urls = as_list(tier, &tier_path)?
+                    .iter()
+                    .enumerate()
+                    .map(|(j, url)| as_string(url, &tier_path.index(j)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                tiers.push(urls);
+            }
+        }
+        if tiers.is_empty() {
+            tiers.extend(fields.string(b"announce")?.map(|url| vec![url]));
+        }
+        Ok(TrackerTiers { tiers })
+    }
+
+    /// Store the tiers in `metainfo`, with `announce` set to the first tracker
+    /// for clients that do not support BEP 12
+    pub fn apply_to(&self, metainfo: &mut Metainfo) {
+        metainfo.announce = self.urls().next().cloned();
+        metainfo.announce_list = self.tiers.clone();
+    }
+
+    /// Like [`apply_to`](Self::apply_to), but splicing `announce` and
+    /// `announce-list` into a raw metainfo file so the `info` bytes, and with
+    /// them the info-hash, stay exactly as they were
+    pub fn write_to(&self, input: &[u8]) -> Result<Vec<u8>, FieldError> {
+        let encoding_error = |e: EncodingError| FieldError::invalid(Path::root(), e.to_string());
+        let announce = Path::root().key(b"announce");
+        let announce_list = Path::root().key(b"announce-list");
+        parse_document(input)?;
+        let mut output = input.to_vec();
+
+        match self.urls().next() {
+            Some(first) => {
+                let tiers = BencodeValue::List(
+                    self.tiers
+                        .iter()
+                        .map(|tier| string_list_value(tier))
+                        .collect(),
+                );
+                let value = encode_to_bytes(&tiers).map_err(encoding_error)?;
+                output = set_value(&output, &announce_list, &value)?;
+                let value = encode_to_bytes(&BencodeValue::ByteString(first.as_bytes()))
+                    .map_err(encoding_error)?;
+                output = set_value(&output, &announce, &value)?;
+            }
+            // Removing the last tracker leaves a trackerless torrent
+            None => {
+                for path in [&announce_list, &announce] {
+                    if let Ok(edited) = delete_value(&output, path) {
+                        output = edited;
+                    }
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    /// Every tracker URL, tier by tier
+    pub fn urls(&self) -> impl Iterator<Item = &String> {
+        self.tiers.iter().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.urls().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.urls().next().is_none()
+    }
+
+    /// Index of the tier holding `url`
+    pub fn tier_of(&self, url: &str) -> Option<usize> {
+        let url = normalize_url(url);
+        self.tiers
+            .iter()
+            .position(|tier| tier.iter().any(|u| normalize_url(u) == url))
+    }
+
+    pub fn contains(&self, url: &str) -> bool {
+        self.tier_of(url).is_some()
+    }
+
+    /// Add `url` to the end of tier `tier`, creating a new last tier when
+    /// `tier` is past the end
+    ///
+    /// Returns false without changing anything if the tracker is already
+    /// listed in any tier.
+    pub fn add(&mut self, url: &str, tier: usize) -> bool {
+        if self.contains(url) {
+            return false;
+        }
+        match self.tiers.get_mut(tier) {
+            Some(tier) => tier.push(url.to_string()),
+            None => self.tiers.push(vec![url.to_string()]),
+        }
+        true
+    }
+
+    /// Append a tier of backup trackers, skipping those already listed
+    pub fn add_tier(&mut self, urls: &[String]) {
+        let mut tier: Vec<String> = Vec::new();
+        for url in urls {
+            let normalized = normalize_url(url);
+            if !self.contains(url) && !tier.iter().any(|u| normalize_url(u) == normalized) {
+                tier.push(url.clone());
+            }
+        }
+        if !tier.is_empty() {
+            self.tiers.push(tier);
+        }
+    }
+
+    /// Remove `url` from whichever tier holds it, dropping the tier if it
+    /// becomes empty
+    pub fn remove(&mut self, url: &str) -> bool {
+        let url = normalize_url(url);
+        let before = self.len();
+        for tier in &mut self.tiers {
+            tier.retain(|u| normalize_url(u) != url);
+        }
+        self.tiers.retain(|tier| !tier.is_empty());
+        self.len() != before
+    }
+
+    /// Keep only the first occurrence of each tracker and drop empty tiers
+    pub fn dedupe(&mut self) {
+        let mut seen = Vec::new();
+        for tier in &mut self.tiers {
+            tier.retain(|url| {
+                let url = normalize_url(url);
+                if seen.contains(&url) {
+                    false
+                } else {
+                    seen.push(url);
+                    true
+                }
+            });
+        }
+        self.tiers.retain(|tier| !tier.is_empty());
+    }
+
+    /// Rewrite every URL with [`normalize_url`], then dedupe
+    pub fn normalize(&mut self) {
+        for url in self.tiers.iter_mut().flatten() {
+            *url = normalize_url(url);
+        }
+        self.dedupe();
+    }
+
+    /// Merge tier `i` of `other` into tier `i` of `self`, so primary trackers
+    /// stay primary
+    ///
+    /// Trackers already listed keep their place; only new ones are added.
+    pub fn merge(&mut self, other: &TrackerTiers) {
+        for (i, tier) in other.tiers.iter().enumerate() {
+            if i >= self.tiers.len() {
+                self.tiers.push(Vec::new());
+            }
+            for url in tier {
+                if !self.contains(url) {
+                    self.tiers[i].push(url.clone());
+                }
+            }
+        }
+        self.tiers.retain(|tier| !tier.is_empty());
+    }
+
+    /// Shuffle each tier in place, as a client does once before announcing
+    ///
+    /// `random(n)` must return a value below `n`; it is taken as a parameter
+    /// so callers can bring their own source of randomness.
+    pub fn shuffle(&mut self, mut random: impl FnMut(usize) -> usize) {
+        for tier in &mut self.tiers {
+            for i in (1..tier.len()).rev() {
+                tier.swap(i, random(i + 1));
+            }
+        }
+    }
+
+    /// Move `url` to the front of its tier after a successful announce
+    pub fn promote(&mut self, url: &str) -> bool {
+        let normalized = normalize_url(url);
+        for tier in &mut self.tiers {
+            if let Some(index) = tier.iter().position(|u| normalize_url(u) == normalized) {
+                let url = tier.remove(index);
+                tier.insert(0, url);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Canonical form of a tracker URL
+///
+/// Surrounding whitespace is trimmed, the scheme and host are lowercased and
+/// the default port of `http` (80) or `https` (443) is dropped. The path and
+/// query are left alone, since trackers often embed passkeys there.
+pub fn normalize_url(url: &str) -> String {
+    let url = url.trim();
+    let (scheme, rest) = match url.split_once("://") {
+        Some(parts) => parts,
+        None => return url.to_string(),
+    };
+    let scheme = scheme.to_ascii_lowercase();
+    let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (authority, tail) = rest.split_at(authority_end);
+
+    let (userinfo, host_port) = match authority.rsplit_once('@') {
+        Some((userinfo, host_port)) => (Some(userinfo), host_port),
+        None => (None, authority),
+    };
+    let (host, port) = match host_port.rsplit_once(':') {
+        Some((host, port))
+            if !port.is_empty()
+                && port.bytes().all(|b| b.is_ascii_digit())
+                && (!host.starts_with('[') || host.ends_with(']')) =>
+        {
+            (host, Some(port))
+        }
+        _ => (host_port, None),
+    };
+    let default_port = match scheme.as_str() {
+        "http" => Some("80"),
+        "https" => Some("443"),
+        _ => None,
+    };
+
+    let mut normalized = format!("{}://", scheme);
+    if let Some(userinfo) = userinfo {
+        normalized.push_str(userinfo);
+        normalized.push('@');
+    }
+    normalized.push_str(&host.to_ascii_lowercase());
+    if let Some(port) = port.filter(|&port| Some(port) != default_port) {
+        normalized.push(':');
+        normalized.push_str(port);
+    }
+    normalized.push_str(tail);
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiers(tiers: &[&[&str]]) -> TrackerTiers {
+        TrackerTiers {
+            tiers: tiers
+                .iter()
+                .map(|tier| tier.iter().map(|url| url.to_string()).collect())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        assert_eq!(
+            normalize_url(" HTTP://Tracker.Example:80/Announce?K=V "),
+            "http://tracker.example/Announce?K=V"
+        );
+        assert_eq!(normalize_url("https://t.example:443"), "https://t.example");
+        assert_eq!(
+            normalize_url("udp://T.example:6969/announce"),
+            "udp://t.example:6969/announce"
+        );
+        assert_eq!(normalize_url("http://[::1]:80/a"), "http://[::1]/a");
+        assert_eq!(normalize_url("http://[::1]/a"), "http://[::1]/a");
+        assert_eq!(normalize_url("not a url"), "not a url");
+    }
+
+    #[test]
+    fn test_edit_operations() {
+        let mut list = tiers(&[&["http://a/", "http://b/"], &["udp://c:1"]]);
+        assert!(!list.add("HTTP://A:80/", 1));
+        assert!(list.add("http://d/", 1));
+        assert!(list.add("http://e/", 9));
+        assert_eq!(list.tier_of("http://d/"), Some(1));
+        assert_eq!(list.tier_of("http://e/"), Some(2));
+
+        assert!(list.remove("udp://c:1"));
+        assert!(list.remove("http://d/"));
+        assert!(!list.remove("http://d/"));
+        assert_eq!(list, tiers(&[&["http://a/", "http://b/"], &["http://e/"]]));
+
+        assert!(list.promote("http://b/"));
+        assert_eq!(list.tiers[0], ["http://b/", "http://a/"]);
+
+        let mut list = tiers(&[
+            &["http://A/", "http://a:80/"],
+            &["http://a/"],
+            &["udp://x:1"],
+        ]);
+        list.dedupe();
+        assert_eq!(list, tiers(&[&["http://A/"], &["udp://x:1"]]));
+        list.normalize();
+        assert_eq!(list, tiers(&[&["http://a/"], &["udp://x:1"]]));
+    }
+
+    #[test]
+    fn test_merge_and_shuffle() {
+        let mut list = tiers(&[&["http://a/"], &["http://b/"]]);
+        list.merge(&tiers(&[&["http://c/", "http://b/"], &[], &["http://d/"]]));
+        assert_eq!(
+            list,
+            tiers(&[&["http://a/", "http://c/"], &["http://b/"], &["http://d/"]])
+        );
+
+        let mut list = tiers(&[&["1", "2", "3"]]);
+        list.shuffle(|_| 0);
+        assert_eq!(list, tiers(&[&["2", "3", "1"]]));
+    }
+
+    #[test]
+    fn test_read_and_write() {
+        let input = b"d8:announce8:http://a4:infod4:name1:xee";
+        let mut list = TrackerTiers::from_bytes(input).unwrap();
+        assert_eq!(list, tiers(&[&["http://a"]]));
+
+        list.add_tier(&["http://b".to_string(), "http://a".to_string()]);
+        let output = list.write_to(input).unwrap();
+        assert_eq!(
+            output,
+            b"d8:announce8:http://a13:announce-listll8:http://ael8:http://bee4:infod4:name1:xee"
+                .to_vec()
+        );
+        assert_eq!(TrackerTiers::from_bytes(&output).unwrap(), list);
+
+        let mut metainfo = Metainfo::from_bytes(
+            b"d4:infod4:name1:x12:piece lengthi16384e6:lengthi1e6:pieces20:aaaaaaaaaaaaaaaaaaaaee",
+        )
+        .unwrap();
+        list.apply_to(&mut metainfo);
+        assert_eq!(metainfo.announce.as_deref(), Some("http://a"));
+        assert_eq!(TrackerTiers::from_metainfo(&metainfo), list);
+
+        let emptied = TrackerTiers::new().write_to(&output).unwrap();
+        assert_eq!(emptied, b"d4:infod4:name1:xee".to_vec());
+    }
+}
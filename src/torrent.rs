@@ -0,0 +1,878 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+#[cfg(feature = "hash")]
+use std::collections::BTreeSet;
+
+use crate::common::BencodeValue;
+use crate::encoder::{encode_to_bytes, EncodingError, ToBencode};
+use crate::fields::{
+    as_bytes, as_list, as_string, insert_extra, integer_value, parse_document, string_list_value,
+    string_value, Extra, FieldError, Fields,
+};
+use crate::file_tree::{is_safe_component, FileTree, PIECES_ROOT_LEN};
+pub use crate::file_tree::{FileEntry, FileLayout};
+#[cfg(feature = "hash")]
+use crate::merkle::root_from_piece_layer;
+use crate::path::Path;
+
+/// Length of a SHA-1 piece hash in the `pieces` string
+pub const PIECE_HASH_LEN: usize = 20;
+
+/// Smallest piece length a v2 torrent may use (BEP 52)
+pub const MIN_V2_PIECE_LENGTH: u64 = 16 * 1024;
+
+/// The `info` dictionary of a torrent
+///
+/// v1 torrents have `pieces` and `layout`, v2 torrents (BEP 52) have
+/// `meta_version` 2 and `file_tree`, and hybrid torrents have both.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Info {
+    /// Raw bytes, which torrents with a legacy `encoding` do not write as UTF-8
+    pub name: Vec<u8>,
+    pub piece_length: u64,
+    pub pieces: Option<Vec<u8>>,
+    pub layout: Option<FileLayout>,
+    pub meta_version: Option<i64>,
+    pub file_tree: Option<FileTree>,
+    pub private: Option<bool>,
+    pub source: Option<String>,
+    pub extra: Extra,
+}
+
+/// A parsed .torrent file (BEP 3, with the common BEP 12, BEP 27 and BEP 52 keys)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metainfo {
+    pub announce: Option<String>,
+    pub announce_list: Vec<Vec<String>>,
+    /// Raw bytes in the torrent's `encoding`, which need not be UTF-8
+    pub comment: Option<Vec<u8>>,
+    pub created_by: Option<Vec<u8>>,
+    pub creation_date: Option<i64>,
+    /// Name of the legacy character set of the text fields, such as `GBK`
+    pub encoding: Option<Vec<u8>>,
+    pub info: Info,
+    /// BEP 52 piece layers, keyed by the `pieces root` of each file
+    pub piece_layers: BTreeMap<Vec<u8>, Vec<u8>>,
+    pub extra: Extra,
+}
+
+const INFO_KEYS: &[&[u8]] = &[
+    b"name",
+    b"piece length",
+    b"pieces",
+    b"length",
+    b"files",
+    b"meta version",
+    b"file tree",
+    b"private",
+    b"source",
+];
+
+const METAINFO_KEYS: &[&[u8]] = &[
+    b"announce",
+    b"announce-list",
+    b"comment",
+    b"created by",
+    b"creation date",
+    b"encoding",
+    b"info",
+    b"piece layers",
+];
+
+fn parse_file_entry(value: &BencodeValue, path: Path) -> Result<FileEntry, FieldError> {
+    let fields = Fields::new(value, path)?;
+    let length = fields.require_unsigned(b"length")?;
+
+    let path_list = fields
+        .list(b"path")?
+        .ok_or_else(|| FieldError::missing(fields.path_of(b"path")))?;
+    if path_list.is_empty() {
+        return Err(FieldError::invalid(
+            fields.path_of(b"path"),
+            "must not be empty",
+        ));
+    }
+
+    let mut path = Vec::with_capacity(path_list.len());
+    for (i, component) in path_list.iter().enumerate() {
+        let component_path = fields.path_of(b"path").index(i);
+        let component = as_string(component, &component_path)?;
+        if !is_safe_component(component.as_bytes()) {
+            return Err(FieldError::invalid(component_path, "unsafe path component"));
+        }
+        path.push(component);
+    }
+
+    Ok(FileEntry {
+        length,
+        path,
+        attr: fields.string(b"attr")?,
+        extra: fields.extra(&[b"length", b"path", b"attr"]),
+    })
+}
+
+fn parse_layout(fields: &Fields) -> Result<Option<FileLayout>, FieldError> {
+    match (fields.get(b"length"), fields.list(b"files")?) {
+        (Some(_), Some(_)) => Err(FieldError::invalid(
+            fields.path().clone(),
+            "must not contain both length and files",
+        )),
+        (Some(_), None) => Ok(Some(FileLayout::Single {
+            length: fields.require_unsigned(b"length")?,
+        })),
+        (None, Some(list)) => {
+            let layout = FileLayout::Multiple {
+                files: list
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| parse_file_entry(v, fields.path_of(b"files").index(i)))
+                    .collect::<Result<_, _>>()?,
+            };
+            if layout.checked_total_length().is_none() {
+                return Err(FieldError::invalid(
+                    fields.path_of(b"files"),
+                    "total length overflows",
+                ));
+            }
+            Ok(Some(layout))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+// Whether a v2 file path is the single file `name`
+fn is_single(path: &[String], name: &[u8]) -> bool {
+    matches!(path, [only] if only.as_bytes() == name)
+}
+
+// The v1 files of a hybrid torrent must be the v2 files, in order and piece aligned
+fn check_hybrid(
+    fields: &Fields,
+    name: &[u8],
+    piece_length: u64,
+    layout: &FileLayout,
+    tree: &FileTree,
+) -> Result<(), FieldError> {
+    let v2_files = tree.files();
+    match layout {
+        FileLayout::Single { length } => {
+            let matches = match &v2_files[..] {
+                [(path, file)] => is_single(path, name) && file.length == *length,
+                _ => false,
+            };
+            if !matches {
+                return Err(FieldError::invalid(
+                    fields.path_of(b"length"),
+                    "does not match file tree",
+                ));
+            }
+        }
+        FileLayout::Multiple { files } => {
+            let mut v2_files = v2_files.iter();
+            let mut offset = 0;
+            for (i, entry) in files.iter().enumerate() {
+                let entry_path = fields.path_of(b"files").index(i);
+                if !entry.is_padding() {
+                    match v2_files.next() {
+                        Some((path, file))
+                            if *path == entry.path && file.length == entry.length => {}
+                        _ => {
+                            return Err(FieldError::invalid(entry_path, "does not match file tree"))
+                        }
+                    }
+                    if entry.length > 0 && offset % piece_length != 0 {
+                        return Err(FieldError::invalid(
+                            entry_path,
+                            "does not start on a piece boundary",
+                        ));
+                    }
+                }
+                offset += entry.length;
+            }
+            if v2_files.next().is_some() {
+                return Err(FieldError::invalid(
+                    fields.path_of(b"files"),
+                    "does not match file tree",
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Info {
+    pub fn from_bencode(value: &BencodeValue, path: Path) -> Result<Info, FieldError> {
+        let fields = Fields::new(value, path)?;
+
+        // The name is the file or directory created in the download directory
+        let name = fields.require_bytes(b"name")?.to_vec();
+        if !is_safe_component(&name) {
+            return Err(FieldError::invalid(
+                fields.path_of(b"name"),
+                "unsafe path component",
+            ));
+        }
+        let piece_length = fields.require_unsigned(b"piece length")?;
+        if piece_length == 0 {
+            return Err(FieldError::invalid(
+                fields.path_of(b"piece length"),
+                "must be greater than zero",
+            ));
+        }
+
+        let meta_version = fields.integer(b"meta version")?;
+        let file_tree = match meta_version {
+            None => None,
+            Some(2) => {
+                if !piece_length.is_power_of_two() || piece_length < MIN_V2_PIECE_LENGTH {
+                    return Err(FieldError::invalid(
+                        fields.path_of(b"piece length"),
+                        "must be a power of two of at least 16 KiB",
+                    ));
+                }
+                Some(FileTree::from_bencode(
+                    fields.require(b"file tree")?,
+                    fields.path_of(b"file tree"),
+                )?)
+            }
+            Some(_) => {
+                return Err(FieldError::invalid(
+                    fields.path_of(b"meta version"),
+                    "unsupported meta version",
+                ))
+            }
+        };
+
+        let pieces = fields.bytes(b"pieces")?.map(<[u8]>::to_vec);
+        let layout = parse_layout(&fields)?;
+        match (&pieces, &layout) {
+            (Some(pieces), Some(layout)) => {
+                if pieces.len() % PIECE_HASH_LEN != 0 {
+                    return Err(FieldError::invalid(
+                        fields.path_of(b"pieces"),
+                        "length must be a multiple of 20",
+                    ));
+                }
+
+                let expected_pieces = layout.total_length().div_ceil(piece_length);
+                let actual_pieces = (pieces.len() / PIECE_HASH_LEN) as u64;
+                if expected_pieces != actual_pieces {
+                    return Err(FieldError::invalid(
+                        fields.path_of(b"pieces"),
+                        format!(
+                            "expected {} piece hashes for {} bytes, found {}",
+                            expected_pieces,
+                            layout.total_length(),
+                            actual_pieces
+                        ),
+                    ));
+                }
+            }
+            (None, None) if file_tree.is_some() => {}
+            (Some(_), None) => {
+                return Err(FieldError::invalid(
+                    fields.path().clone(),
+                    "must contain either length or files",
+                ))
+            }
+            (None, _) => return Err(FieldError::missing(fields.path_of(b"pieces"))),
+        }
+        if let (Some(layout), Some(tree)) = (&layout, &file_tree) {
+            check_hybrid(&fields, &name, piece_length, layout, tree)?;
+        }
+
+        Ok(Info {
+            name,
+            piece_length,
+            pieces,
+            layout,
+            meta_version,
+            file_tree,
+            private: fields.flag(b"private")?,
+            source: fields.string(b"source")?,
+            extra: fields.extra(INFO_KEYS),
+        })
+    }
+
+    /// Whether the torrent has v1 piece hashes
+    pub fn has_v1(&self) -> bool {
+        self.pieces.is_some() && self.layout.is_some()
+    }
+
+    /// Whether the torrent has a v2 file tree
+    pub fn has_v2(&self) -> bool {
+        self.meta_version == Some(2) && self.file_tree.is_some()
+    }
+
+    /// Whether the content is a directory called `name` rather than a single file
+    pub fn is_multi_file(&self) -> bool {
+        match (&self.layout, &self.file_tree) {
+            (Some(layout), _) => matches!(layout, FileLayout::Multiple { .. }),
+            (None, Some(tree)) => {
+                let files = tree.files();
+                !(files.len() == 1 && is_single(&files[0].0, &self.name))
+            }
+            (None, None) => false,
+        }
+    }
+
+    /// `name` as text, with bytes that are not UTF-8 replaced
+    pub fn name_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.name)
+    }
+
+    /// Total length of the content, including any v1 padding files
+    pub fn total_length(&self) -> u64 {
+        match (&self.layout, &self.file_tree) {
+            (Some(layout), _) => layout.total_length(),
+            (None, Some(tree)) => tree.total_length(),
+            (None, None) => 0,
+        }
+    }
+
+    /// Number of v1 pieces, or zero for a v2-only torrent
+    pub fn piece_count(&self) -> usize {
+        self.pieces.as_ref().map_or(0, |p| p.len() / PIECE_HASH_LEN)
+    }
+
+    /// The SHA-1 hash of a piece, if the index is in range
+    pub fn piece_hash(&self, index: usize) -> Option<&[u8]> {
+        self.pieces
+            .as_ref()?
+            .chunks_exact(PIECE_HASH_LEN)
+            .nth(index)
+    }
+
+    /// Length of a piece, which is shorter than `piece_length` for the last one
+    pub fn piece_size(&self, index: usize) -> Option<u64> {
+        if index >= self.piece_count() {
+            return None;
+        }
+        let start = index as u64 * self.piece_length;
+        Some(self.piece_length.min(self.total_length() - start))
+    }
+
+    pub fn to_value(&self) -> Result<BencodeValue<'_>, EncodingError> {
+        let mut dict = BTreeMap::new();
+        insert_extra(&mut dict, &self.extra)?;
+
+        dict.insert(&b"name"[..], BencodeValue::ByteString(&self.name));
+        dict.insert(&b"piece length"[..], integer_value(self.piece_length)?);
+        if let Some(pieces) = &self.pieces {
+            dict.insert(&b"pieces"[..], BencodeValue::ByteString(pieces));
+        }
+
+        match &self.layout {
+            Some(FileLayout::Single { length }) => {
+                dict.insert(&b"length"[..], integer_value(*length)?);
+            }
+            Some(FileLayout::Multiple { files }) => {
+                let mut list = Vec::with_capacity(files.len());
+                for file in files {
+                    let mut entry = BTreeMap::new();
+                    insert_extra(&mut entry, &file.extra)?;
+                    entry.insert(&b"length"[..], integer_value(file.length)?);
+                    entry.insert(&b"path"[..], string_list_value(&file.path));
+                    if let Some(attr) = &file.attr {
+                        entry.insert(&b"attr"[..], string_value(attr));
+                    }
+                    list.push(BencodeValue::Dictionary(entry));
+                }
+                dict.insert(&b"files"[..], BencodeValue::List(list));
+            }
+            None => {}
+        }
+
+        if let Some(meta_version) = self.meta_version {
+            dict.insert(&b"meta version"[..], integer_value(meta_version)?);
+        }
+        if let Some(file_tree) = &self.file_tree {
+            dict.insert(&b"file tree"[..], file_tree.to_value()?);
+        }
+        if let Some(private) = self.private {
+            dict.insert(&b"private"[..], BencodeValue::Integer(private as isize));
+        }
+        if let Some(source) = &self.source {
+            dict.insert(&b"source"[..], string_value(source));
+        }
+
+        Ok(BencodeValue::Dictionary(dict))
+    }
+}
+
+impl Metainfo {
+    /// Parse a complete .torrent file
+    pub fn from_bytes(input: &[u8]) -> Result<Metainfo, FieldError> {
+        Metainfo::from_bencode(&parse_document(input)?)
+    }
+
+    pub fn from_bencode(value: &BencodeValue) -> Result<Metainfo, FieldError> {
+        let fields = Fields::new(value, Path::root())?;
+
+        let mut announce_list = Vec::new();
+        if let Some(tiers) = fields.list(b"announce-list")? {
+            for (i, tier) in tiers.iter().enumerate() {
+                let tier_path = fields.path_of(b"announce-list").index(i);
+                let urls = as_list(tier, &tier_path)?
+                    .iter()
+                    .enumerate()
+                    .map(|(j, url)| as_string(url, &tier_path.index(j)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                announce_list.push(urls);
+            }
+        }
+
+        let mut piece_layers = BTreeMap::new();
+        if let Some(layers) = fields.dictionary(b"piece layers")? {
+            for (root, layer) in layers.iter() {
+                let layer_path = layers.path_of(root);
+                let layer = as_bytes(layer, &layer_path)?;
+                if root.len() != PIECES_ROOT_LEN {
+                    return Err(FieldError::invalid(layer_path, "key must be 32 bytes long"));
+                }
+                if layer.len() % PIECES_ROOT_LEN != 0 {
+                    return Err(FieldError::invalid(
+                        layer_path,
+                        "length must be a multiple of 32",
+                    ));
+                }
+                piece_layers.insert(root.to_vec(), layer.to_vec());
+            }
+        }
+
+        Ok(Metainfo {
+            announce: fields.string(b"announce")?,
+            announce_list,
+            comment: fields.bytes(b"comment")?.map(<[u8]>::to_vec),
+            created_by: fields.bytes(b"created by")?.map(<[u8]>::to_vec),
+            creation_date: fields.integer(b"creation date")?,
+            encoding: fields.bytes(b"encoding")?.map(<[u8]>::to_vec),
+            info: Info::from_bencode(fields.require(b"info")?, fields.path_of(b"info"))?,
+            piece_layers,
+            extra: fields.extra(METAINFO_KEYS),
+        })
+    }
+
+    /// The piece layer of the file with the given `pieces root`, split into hashes
+    pub fn piece_layer(&self, pieces_root: &[u8]) -> Option<Vec<[u8; PIECES_ROOT_LEN]>> {
+        let layer = self.piece_layers.get(pieces_root)?;
+        Some(
+            layer
+                .chunks_exact(PIECES_ROOT_LEN)
+                .map(|hash| {
+                    let mut array = [0; PIECES_ROOT_LEN];
+                    array.copy_from_slice(hash);
+                    array
+                })
+                .collect(),
+        )
+    }
+
+    /// Check the `piece layers` of a v2 torrent against its file tree
+    ///
+    /// Every file longer than one piece needs a layer with one hash per piece
+    /// whose merkle root is the file's `pieces root`. Layers belonging to no file
+    /// are rejected too.
+    #[cfg(feature = "hash")]
+    pub fn verify_piece_layers(&self) -> Result<(), FieldError> {
+        let info = &self.info;
+        let tree = match &info.file_tree {
+            Some(tree) => tree,
+            None => return Ok(()),
+        };
+        let tree_path = Path::root().key(b"info").key(b"file tree");
+        let mut roots = BTreeSet::new();
+
+        for (path, file) in tree.files() {
+            let root = match &file.pieces_root {
+                Some(root) => root,
+                None => continue,
+            };
+            roots.insert(&root[..]);
+            if file.length <= info.piece_length {
+                continue;
+            }
+
+            let root_path = path
+                .iter()
+                .fold(tree_path.clone(), |p, name| p.key(name.as_bytes()))
+                .key(b"")
+                .key(b"pieces root");
+            let layer = match self.piece_layer(root) {
+                Some(layer) => layer,
+                None => return Err(FieldError::invalid(root_path, "piece layer missing")),
+            };
+
+            let expected = file.length.div_ceil(info.piece_length);
+            if layer.len() as u64 != expected {
+                return Err(FieldError::invalid(
+                    root_path,
+                    format!(
+                        "piece layer has {} hashes, expected {}",
+                        layer.len(),
+                        expected
+                    ),
+                ));
+            }
+            if root_from_piece_layer(&layer, info.piece_length) != *root {
+                return Err(FieldError::invalid(
+                    root_path,
+                    "piece layer does not hash to pieces root",
+                ));
+            }
+        }
+
+        match self
+            .piece_layers
+            .keys()
+            .find(|key| !roots.contains(&key[..]))
+        {
+            Some(unused) => Err(FieldError::invalid(
+                Path::root().key(b"piece layers").key(unused),
+                "not the pieces root of any file",
+            )),
+            None => Ok(()),
+        }
+    }
+
+    pub fn to_value(&self) -> Result<BencodeValue<'_>, EncodingError> {
+        let mut dict = BTreeMap::new();
+        insert_extra(&mut dict, &self.extra)?;
+
+        if let Some(announce) = &self.announce {
+            dict.insert(&b"announce"[..], string_value(announce));
+        }
+        if !self.announce_list.is_empty() {
+            let tiers = self
+                .announce_list
+                .iter()
+                .map(|tier| string_list_value(tier))
+                .collect();
+            dict.insert(&b"announce-list"[..], BencodeValue::List(tiers));
+        }
+        if let Some(comment) = &self.comment {
+            dict.insert(&b"comment"[..], BencodeValue::ByteString(comment));
+        }
+        if let Some(created_by) = &self.created_by {
+            dict.insert(&b"created by"[..], BencodeValue::ByteString(created_by));
+        }
+        if let Some(creation_date) = self.creation_date {
+            dict.insert(&b"creation date"[..], integer_value(creation_date)?);
+        }
+        if let Some(encoding) = &self.encoding {
+            dict.insert(&b"encoding"[..], BencodeValue::ByteString(encoding));
+        }
+        dict.insert(&b"info"[..], self.info.to_value()?);
+        if !self.piece_layers.is_empty() {
+            let layers = self
+                .piece_layers
+                .iter()
+                .map(|(root, layer)| (&root[..], BencodeValue::ByteString(layer)))
+                .collect();
+            dict.insert(&b"piece layers"[..], BencodeValue::Dictionary(layers));
+        }
+
+        Ok(BencodeValue::Dictionary(dict))
+    }
+}
+
+impl ToBencode for Info {
+    fn to_bencode(&self) -> Result<Vec<u8>, EncodingError> {
+        encode_to_bytes(&self.to_value()?)
+    }
+}
+
+impl ToBencode for Metainfo {
+    fn to_bencode(&self) -> Result<Vec<u8>, EncodingError> {
+        encode_to_bytes(&self.to_value()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn single_file_torrent() -> Vec<u8> {
+        let mut input = Vec::new();
+        input.extend_from_slice(b"d8:announce31:http://tracker.example/announce");
+        input.extend_from_slice(
+            b"13:announce-listll31:http://tracker.example/announceel9:udp://a:1ee",
+        );
+        input
+            .extend_from_slice(b"7:comment4:test10:created by6:tester13:creation datei1700000000e");
+        input.extend_from_slice(b"4:infod6:lengthi40000e4:name8:file.bin12:piece lengthi16384e");
+        input.extend_from_slice(b"6:pieces60:");
+        input.extend_from_slice(&[0xAB; 60]);
+        input.extend_from_slice(b"7:privatei1e6:source3:abce8:url-list0:e");
+        input
+    }
+
+    #[test]
+    fn test_parse_single_file() {
+        let metainfo = Metainfo::from_bytes(&single_file_torrent()).unwrap();
+
+        assert_eq!(
+            metainfo.announce.as_deref(),
+            Some("http://tracker.example/announce")
+        );
+        assert_eq!(metainfo.announce_list.len(), 2);
+        assert_eq!(metainfo.announce_list[1], vec!["udp://a:1".to_string()]);
+        assert_eq!(metainfo.creation_date, Some(1700000000));
+        assert_eq!(metainfo.info.name, b"file.bin");
+        assert_eq!(
+            metainfo.info.layout,
+            Some(FileLayout::Single { length: 40000 })
+        );
+        assert_eq!(metainfo.info.piece_count(), 3);
+        assert_eq!(metainfo.info.piece_size(2), Some(40000 - 2 * 16384));
+        assert_eq!(metainfo.info.piece_hash(1), Some(&[0xAB; 20][..]));
+        assert!(metainfo.info.has_v1() && !metainfo.info.has_v2());
+        assert_eq!(metainfo.info.private, Some(true));
+        assert_eq!(metainfo.info.source.as_deref(), Some("abc"));
+        assert!(metainfo.extra.contains_key(&b"url-list"[..]));
+    }
+
+    #[test]
+    fn test_round_trip_is_canonical() {
+        let input = single_file_torrent();
+        let metainfo = Metainfo::from_bytes(&input).unwrap();
+        assert_eq!(metainfo.to_bencode().unwrap(), input);
+    }
+
+    #[test]
+    fn test_legacy_encoding_round_trip() {
+        // "种子" and a comment in GBK, which are not UTF-8
+        let mut input = b"d7:comment2:\xb2\xe28:encoding3:GBK4:infod6:lengthi0e".to_vec();
+        input.extend_from_slice(b"4:name4:\xd6\xd6\xd7\xd312:piece lengthi1e6:pieces0:ee");
+        let metainfo = Metainfo::from_bytes(&input).unwrap();
+        assert_eq!(metainfo.info.name, b"\xd6\xd6\xd7\xd3");
+        assert_eq!(
+            metainfo.info.name_lossy(),
+            "\u{fffd}\u{fffd}\u{fffd}\u{fffd}"
+        );
+        assert_eq!(metainfo.comment.as_deref(), Some(&b"\xb2\xe2"[..]));
+        assert_eq!(metainfo.encoding.as_deref(), Some(&b"GBK"[..]));
+        assert_eq!(metainfo.to_bencode().unwrap(), input);
+    }
+
+    #[test]
+    fn test_parse_multi_file() {
+        let mut input = Vec::new();
+        input.extend_from_slice(b"d4:infod5:filesld6:lengthi10e4:pathl3:dir5:a.txteed6:lengthi5e");
+        input.extend_from_slice(b"6:md5sum32:0123456789abcdef0123456789abcdef4:pathl5:b.txteee");
+        input.extend_from_slice(b"4:name4:root12:piece lengthi16e6:pieces20:");
+        input.extend_from_slice(&[0; 20]);
+        input.extend_from_slice(b"ee");
+
+        let metainfo = Metainfo::from_bytes(&input).unwrap();
+        match &metainfo.info.layout {
+            Some(FileLayout::Multiple { files }) => {
+                assert_eq!(files.len(), 2);
+                assert_eq!(files[0].path, vec!["dir", "a.txt"]);
+                assert_eq!(files[1].length, 5);
+                assert!(files[1].extra.contains_key(&b"md5sum"[..]));
+            }
+            _ => panic!("Expected multiple files"),
+        }
+        assert_eq!(metainfo.info.total_length(), 15);
+        assert_eq!(metainfo.to_bencode().unwrap(), input);
+    }
+
+    #[test]
+    fn test_parse_v2_only() {
+        let mut input = Vec::new();
+        input.extend_from_slice(b"d4:infod9:file treed1:ad0:d6:lengthi1e11:pieces root32:");
+        input.extend_from_slice(&[1; 32]);
+        input.extend_from_slice(b"eee12:meta versioni2e4:name1:a12:piece lengthi16384ee");
+        input.extend_from_slice(b"12:piece layersd32:");
+        input.extend_from_slice(&[1; 32]);
+        input.extend_from_slice(b"0:ee");
+
+        let metainfo = Metainfo::from_bytes(&input).unwrap();
+        assert!(metainfo.info.has_v2() && !metainfo.info.has_v1());
+        assert_eq!(metainfo.info.total_length(), 1);
+        assert_eq!(metainfo.info.piece_count(), 0);
+        assert_eq!(metainfo.piece_layers.len(), 1);
+        assert_eq!(metainfo.to_bencode().unwrap(), input);
+
+        let error = Metainfo::from_bytes(
+            b"d4:infod9:file treede12:meta versioni2e4:name1:a12:piece lengthi1000eee",
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "info.piece length: must be a power of two of at least 16 KiB"
+        );
+
+        let error =
+            Metainfo::from_bytes(b"d4:infod12:meta versioni2e4:name1:a12:piece lengthi16384eee")
+                .unwrap_err();
+        assert_eq!(error.to_string(), "info.file tree: missing");
+
+        let mut input = b"d4:infod9:file treede12:meta versioni2e4:name1:a12:piece lengthi16384ee12:piece layersd32:".to_vec();
+        input.extend_from_slice(&[1; 32]);
+        input.extend_from_slice(b"3:abcee");
+        let error = Metainfo::from_bytes(&input).unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with(": length must be a multiple of 32"));
+    }
+
+    #[test]
+    fn test_hybrid_must_match_file_tree() {
+        let hybrid = |files: &str, pieces: usize| {
+            let mut input = b"d4:infod9:file treed1:ad0:d6:lengthi1e11:pieces root32:".to_vec();
+            input.extend_from_slice(&[1; 32]);
+            input.extend_from_slice(b"ee1:bd0:d6:lengthi2e11:pieces root32:");
+            input.extend_from_slice(&[2; 32]);
+            input.extend_from_slice(b"eee5:files");
+            input.extend_from_slice(files.as_bytes());
+            input.extend_from_slice(b"12:meta versioni2e4:name1:x12:piece lengthi16384e6:pieces");
+            input.extend_from_slice(format!("{}:", pieces * 20).as_bytes());
+            input.extend_from_slice(&vec![0; pieces * 20]);
+            input.extend_from_slice(b"ee");
+            Metainfo::from_bytes(&input)
+        };
+
+        let aligned = "ld6:lengthi1e4:pathl1:aeed4:attr1:p6:lengthi16383e4:pathl4:.pad5:16383eed6:lengthi2e4:pathl1:beee";
+        let metainfo = hybrid(aligned, 2).unwrap();
+        assert!(metainfo.info.has_v1() && metainfo.info.has_v2());
+        assert_eq!(
+            metainfo
+                .info
+                .file_tree
+                .as_ref()
+                .unwrap()
+                .to_v1_files(Some(16384)),
+            match metainfo.info.layout.unwrap() {
+                FileLayout::Multiple { files } => files,
+                _ => panic!("Expected multiple files"),
+            }
+        );
+
+        let unaligned = "ld6:lengthi1e4:pathl1:aeed6:lengthi2e4:pathl1:beee";
+        assert_eq!(
+            hybrid(unaligned, 1).unwrap_err().to_string(),
+            "info.files[1]: does not start on a piece boundary"
+        );
+        let renamed = "ld6:lengthi1e4:pathl1:aeed4:attr1:p6:lengthi16383e4:pathl4:.pad5:16383eed6:lengthi2e4:pathl1:ceee";
+        assert_eq!(
+            hybrid(renamed, 2).unwrap_err().to_string(),
+            "info.files[2]: does not match file tree"
+        );
+        assert_eq!(
+            hybrid("ld6:lengthi1e4:pathl1:aeee", 1)
+                .unwrap_err()
+                .to_string(),
+            "info.files: does not match file tree"
+        );
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let check = |input: &[u8], expected: &str| {
+            assert_eq!(
+                Metainfo::from_bytes(input).unwrap_err().to_string(),
+                expected
+            );
+        };
+
+        check(b"le", "<root>: expected dictionary");
+        check(b"de", "info: missing");
+        check(
+            b"d4:infod4:name1:a12:piece lengthi0e6:pieces0:6:lengthi0eee",
+            "info.piece length: must be greater than zero",
+        );
+        check(
+            b"d4:infod4:name1:a12:piece lengthi1e6:pieces3:abc6:lengthi0eee",
+            "info.pieces: length must be a multiple of 20",
+        );
+        check(
+            b"d4:infod4:name1:a12:piece lengthi1e6:pieces0:6:lengthi2eee",
+            "info.pieces: expected 2 piece hashes for 2 bytes, found 0",
+        );
+        check(
+            b"d4:infod4:name1:a12:piece lengthi1e6:pieces0:ee",
+            "info: must contain either length or files",
+        );
+        check(
+            b"d4:infod4:name1:a12:piece lengthi1e6:lengthi0eee",
+            "info.pieces: missing",
+        );
+        check(
+            b"d4:infod6:lengthi0e4:name4:/etc12:piece lengthi1e6:pieces0:ee",
+            "info.name: unsafe path component",
+        );
+        check(
+            b"d4:infod6:lengthi0e4:name2:..12:piece lengthi1e6:pieces0:ee",
+            "info.name: unsafe path component",
+        );
+        check(
+            b"d4:infod5:filesld6:lengthi9223372036854775807e4:pathl1:aeed6:lengthi9223372036854775807e4:pathl1:beed6:lengthi2e4:pathl1:ceee4:name1:a12:piece lengthi1e6:pieces0:ee",
+            "info.files: total length overflows",
+        );
+        check(
+            b"d4:infod5:filesld6:lengthi0e4:pathl2:..eee4:name1:a12:piece lengthi1e6:pieces0:ee",
+            "info.files[0].path[0]: unsafe path component",
+        );
+        check(
+            b"d4:infod5:filesld4:pathl1:aeee4:name1:a12:piece lengthi1e6:pieces0:ee",
+            "info.files[0].length: missing",
+        );
+        check(
+            b"d4:infod4:name1:a12:piece lengthi1e6:pieces0:6:lengthi0e7:privatei2eee",
+            "info.private: must be 0 or 1",
+        );
+        check(
+            b"d13:announce-listli1ee4:infod4:name1:a12:piece lengthi1e6:pieces0:6:lengthi0eee",
+            "announce-list[0]: expected list",
+        );
+    }
+
+    #[cfg(feature = "hash")]
+    #[test]
+    fn test_verify_piece_layers() {
+        use crate::create::{TorrentBuilder, TorrentVersion};
+        use crate::merkle::BLOCK_SIZE;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("big"), vec![5u8; BLOCK_SIZE as usize * 3]).unwrap();
+        std::fs::write(dir.path().join("small"), b"small").unwrap();
+        let metainfo = TorrentBuilder::new(dir.path())
+            .name("data")
+            .version(TorrentVersion::V2)
+            .build()
+            .unwrap();
+        metainfo.verify_piece_layers().unwrap();
+
+        let root = metainfo.piece_layers.keys().next().unwrap().clone();
+
+        let mut tampered = metainfo.clone();
+        tampered.piece_layers.get_mut(&root).unwrap()[0] ^= 1;
+        assert_eq!(
+            tampered.verify_piece_layers().unwrap_err().to_string(),
+            "info.file tree.big.\"\".pieces root: piece layer does not hash to pieces root"
+        );
+
+        let mut short = metainfo.clone();
+        short.piece_layers.get_mut(&root).unwrap().truncate(32);
+        assert_eq!(
+            short.verify_piece_layers().unwrap_err().to_string(),
+            "info.file tree.big.\"\".pieces root: piece layer has 1 hashes, expected 3"
+        );
+
+        let mut missing = metainfo.clone();
+        missing.piece_layers.clear();
+        assert_eq!(
+            missing.verify_piece_layers().unwrap_err().to_string(),
+            "info.file tree.big.\"\".pieces root: piece layer missing"
+        );
+
+        let mut unused = metainfo.clone();
+        unused.piece_layers.insert(b"x".repeat(32), Vec::new());
+        assert_eq!(
+            unused.verify_piece_layers().unwrap_err().to_string(),
+            "piece layers.xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx: not the pieces root of any file"
+        );
+    }
+}
@@ -0,0 +1,531 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::common::BencodeValue;
+use crate::compact::{
+    decode_peers_v4, decode_peers_v6, encode_peer, NODE_ID_LEN, PEER_V4_LEN, PEER_V6_LEN,
+};
+use crate::encoder::{encode_to_bytes, EncodingError, ToBencode};
+use crate::fields::{
+    insert_extra, integer_value, parse_document, string_value, Extra, FieldError, Fields,
+};
+use crate::path::Path;
+
+/// Length of a peer ID and of the info-hash keys of a scrape
+pub const PEER_ID_LEN: usize = NODE_ID_LEN;
+
+/// Where to reach a peer; the dictionary model allows a DNS name (BEP 3)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerHost {
+    Ip(IpAddr),
+    Name(String),
+}
+
+impl fmt::Display for PeerHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerHost::Ip(ip) => write!(f, "{}", ip),
+            PeerHost::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// A peer returned by a tracker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    pub host: PeerHost,
+    pub port: u16,
+    /// Only present in the dictionary model, and not with `no_peer_id`
+    pub peer_id: Option<[u8; PEER_ID_LEN]>,
+}
+
+impl Peer {
+    /// The socket address, or `None` when the host is a DNS name
+    pub fn addr(&self) -> Option<SocketAddr> {
+        match self.host {
+            PeerHost::Ip(ip) => Some(SocketAddr::new(ip, self.port)),
+            PeerHost::Name(_) => None,
+        }
+    }
+}
+
+impl From<SocketAddr> for Peer {
+    fn from(addr: SocketAddr) -> Self {
+        Peer {
+            host: PeerHost::Ip(addr.ip()),
+            port: addr.port(),
+            peer_id: None,
+        }
+    }
+}
+
+/// Reply to an HTTP announce (BEP 3, with BEP 23 compact peers and BEP 7 `peers6`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnounceResponse {
+    /// When set, the announce failed and the other fields may be absent
+    pub failure_reason: Option<String>,
+    pub warning_message: Option<String>,
+    pub interval: Option<u64>,
+    pub min_interval: Option<u64>,
+    pub tracker_id: Option<Vec<u8>>,
+    /// Seeders
+    pub complete: Option<u64>,
+    /// Leechers
+    pub incomplete: Option<u64>,
+    /// Peers from `peers`; only IPv4 addresses when `compact` is set
+    pub peers: Vec<Peer>,
+    /// IPv6 peers from `peers6`, which is compact in both models
+    pub peers6: Vec<Peer>,
+    /// Write `peers` as a compact string rather than a list of dictionaries
+    pub compact: bool,
+    pub extra: Extra,
+}
+
+/// Statistics for one torrent in a scrape reply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScrapeFile {
+    pub complete: u64,
+    pub downloaded: u64,
+    pub incomplete: u64,
+    pub name: Option<String>,
+    pub extra: Extra,
+}
+
+/// Reply to an HTTP scrape, keyed by info-hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScrapeResponse {
+    pub failure_reason: Option<String>,
+    pub files: BTreeMap<[u8; PEER_ID_LEN], ScrapeFile>,
+    /// Tracker `flags` such as `min_request_interval`, kept raw
+    pub flags: Extra,
+    pub extra: Extra,
+}
+
+const ANNOUNCE_KEYS: &[&[u8]] = &[
+    b"failure reason",
+    b"warning message",
+    b"interval",
+    b"min interval",
+    b"tracker id",
+    b"complete",
+    b"incomplete",
+    b"peers",
+    b"peers6",
+];
+
+fn parse_peer(value: &BencodeValue, path: Path) -> Result<Peer, FieldError> {
+    let fields = Fields::new(value, path)?;
+    let ip = fields.require_string(b"ip")?;
+    let host = match ip.parse() {
+        Ok(ip) => PeerHost::Ip(ip),
+        Err(_) if !ip.is_empty() => PeerHost::Name(ip),
+        Err(_) => return Err(FieldError::invalid(fields.path_of(b"ip"), "empty host")),
+    };
+    let port = fields.require_unsigned(b"port")?;
+    if port > u64::from(u16::MAX) {
+        return Err(FieldError::invalid(
+            fields.path_of(b"port"),
+            "must be a valid port",
+        ));
+    }
+
+    Ok(Peer {
+        host,
+        port: port as u16,
+        peer_id: fields.fixed_bytes(b"peer id")?,
+    })
+}
+
+// Peers from `peers` in either model and from the compact `peers6`;
+// a response without peers is taken as compact
+fn parse_peers(fields: &Fields) -> Result<(Vec<Peer>, Vec<Peer>, bool), FieldError> {
+    let mut compact = true;
+    let mut peers = Vec::new();
+    let mut peers6 = Vec::new();
+
+    match fields.get(b"peers") {
+        Some(BencodeValue::ByteString(_)) => {
+            let addrs = fields.compact(b"peers", PEER_V4_LEN, decode_peers_v4)?;
+            peers.extend(addrs.unwrap_or_default().into_iter().map(Peer::from));
+        }
+        Some(_) => {
+            compact = false;
+            let list = fields.list(b"peers")?.unwrap_or_default();
+            for (i, peer) in list.iter().enumerate() {
+                peers.push(parse_peer(peer, fields.path_of(b"peers").index(i))?);
+            }
+        }
+        None => {}
+    }
+
+    if let Some(addrs) = fields.compact(b"peers6", PEER_V6_LEN, decode_peers_v6)? {
+        peers6.extend(addrs.into_iter().map(Peer::from));
+    }
+    Ok((peers, peers6, compact))
+}
+
+// Compact string of peers that must all be IPv4 or all be IPv6
+fn encode_compact(peers: &[Peer], key: &str, v6: bool) -> Result<Vec<u8>, EncodingError> {
+    let mut out = Vec::new();
+    for peer in peers {
+        match peer.addr() {
+            Some(addr) if addr.is_ipv6() == v6 => out.extend(encode_peer(&addr)),
+            _ => {
+                return Err(EncodingError::CustomError(format!(
+                    "peer {} cannot be written to compact {}",
+                    peer.host, key
+                )))
+            }
+        }
+    }
+    Ok(out)
+}
+
+impl AnnounceResponse {
+    /// An empty successful response
+    pub fn new(interval: u64) -> Self {
+        AnnounceResponse {
+            failure_reason: None,
+            warning_message: None,
+            interval: Some(interval),
+            min_interval: None,
+            tracker_id: None,
+            complete: None,
+            incomplete: None,
+            peers: Vec::new(),
+            peers6: Vec::new(),
+            compact: true,
+            extra: Extra::new(),
+        }
+    }
+
+    pub fn failure(reason: impl Into<String>) -> Self {
+        AnnounceResponse {
+            failure_reason: Some(reason.into()),
+            interval: None,
+            ..AnnounceResponse::new(0)
+        }
+    }
+
+    pub fn from_bytes(input: &[u8]) -> Result<AnnounceResponse, FieldError> {
+        AnnounceResponse::from_bencode(&parse_document(input)?)
+    }
+
+    pub fn from_bencode(value: &BencodeValue) -> Result<AnnounceResponse, FieldError> {
+        let fields = Fields::new(value, Path::root())?;
+        let failure_reason = fields.string(b"failure reason")?;
+
+        let interval = fields.unsigned(b"interval")?;
+        if interval.is_none() && failure_reason.is_none() {
+            return Err(FieldError::missing(fields.path_of(b"interval")));
+        }
+        let (peers, peers6, compact) = parse_peers(&fields)?;
+
+        Ok(AnnounceResponse {
+            failure_reason,
+            warning_message: fields.string(b"warning message")?,
+            interval,
+            min_interval: fields.unsigned(b"min interval")?,
+            tracker_id: fields.bytes(b"tracker id")?.map(<[u8]>::to_vec),
+            complete: fields.unsigned(b"complete")?,
+            incomplete: fields.unsigned(b"incomplete")?,
+            peers,
+            peers6,
+            compact,
+            extra: fields.extra(ANNOUNCE_KEYS),
+        })
+    }
+
+    pub fn is_failure(&self) -> bool {
+        self.failure_reason.is_some()
+    }
+}
+
+impl ToBencode for AnnounceResponse {
+    fn to_bencode(&self) -> Result<Vec<u8>, EncodingError> {
+        let compact_v4 = match self.compact {
+            true => encode_compact(&self.peers, "peers", false)?,
+            false => Vec::new(),
+        };
+        let compact_v6 = encode_compact(&self.peers6, "peers6", true)?;
+        let hosts: Vec<String> = self.peers.iter().map(|p| p.host.to_string()).collect();
+
+        let mut dict = BTreeMap::new();
+        insert_extra(&mut dict, &self.extra)?;
+        if let Some(reason) = &self.failure_reason {
+            dict.insert(&b"failure reason"[..], string_value(reason));
+        }
+        if let Some(warning) = &self.warning_message {
+            dict.insert(&b"warning message"[..], string_value(warning));
+        }
+        let counters = [
+            (&b"interval"[..], self.interval),
+            (&b"min interval"[..], self.min_interval),
+            (&b"complete"[..], self.complete),
+            (&b"incomplete"[..], self.incomplete),
+        ];
+        for (key, counter) in counters {
+            if let Some(counter) = counter {
+                dict.insert(key, integer_value(counter)?);
+            }
+        }
+        if let Some(tracker_id) = &self.tracker_id {
+            dict.insert(&b"tracker id"[..], BencodeValue::ByteString(tracker_id));
+        }
+
+        if self.compact {
+            if self.failure_reason.is_none() || !self.peers.is_empty() {
+                dict.insert(&b"peers"[..], BencodeValue::ByteString(&compact_v4));
+            }
+        } else if self.failure_reason.is_none() || !self.peers.is_empty() {
+            let mut list = Vec::new();
+            for (peer, host) in self.peers.iter().zip(&hosts) {
+                let mut entry = BTreeMap::new();
+                entry.insert(&b"ip"[..], string_value(host));
+                entry.insert(&b"port"[..], integer_value(peer.port)?);
+                if let Some(peer_id) = &peer.peer_id {
+                    entry.insert(&b"peer id"[..], BencodeValue::ByteString(peer_id));
+                }
+                list.push(BencodeValue::Dictionary(entry));
+            }
+            dict.insert(&b"peers"[..], BencodeValue::List(list));
+        }
+        if !compact_v6.is_empty() {
+            dict.insert(&b"peers6"[..], BencodeValue::ByteString(&compact_v6));
+        }
+
+        encode_to_bytes(&BencodeValue::Dictionary(dict))
+    }
+}
+
+impl ScrapeFile {
+    fn from_bencode(value: &BencodeValue, path: Path) -> Result<ScrapeFile, FieldError> {
+        let fields = Fields::new(value, path)?;
+        Ok(ScrapeFile {
+            complete: fields.require_unsigned(b"complete")?,
+            downloaded: fields.require_unsigned(b"downloaded")?,
+            incomplete: fields.require_unsigned(b"incomplete")?,
+            name: fields.string(b"name")?,
+            extra: fields.extra(&[b"complete", b"downloaded", b"incomplete", b"name"]),
+        })
+    }
+
+    fn to_value(&self) -> Result<BencodeValue<'_>, EncodingError> {
+        let mut dict = BTreeMap::new();
+        insert_extra(&mut dict, &self.extra)?;
+        dict.insert(&b"complete"[..], integer_value(self.complete)?);
+        dict.insert(&b"downloaded"[..], integer_value(self.downloaded)?);
+        dict.insert(&b"incomplete"[..], integer_value(self.incomplete)?);
+        if let Some(name) = &self.name {
+            dict.insert(&b"name"[..], string_value(name));
+        }
+        Ok(BencodeValue::Dictionary(dict))
+    }
+}
+
+impl ScrapeResponse {
+    pub fn from_bytes(input: &[u8]) -> Result<ScrapeResponse, FieldError> {
+        ScrapeResponse::from_bencode(&parse_document(input)?)
+    }
+
+    pub fn from_bencode(value: &BencodeValue) -> Result<ScrapeResponse, FieldError> {
+        let fields = Fields::new(value, Path::root())?;
+        let failure_reason = fields.string(b"failure reason")?;
+
+        let mut files = BTreeMap::new();
+        match fields.dictionary(b"files")? {
+            Some(entries) => {
+                for (hash, file) in entries.iter() {
+                    let path = entries.path_of(hash);
+                    let hash = hash.try_into().map_err(|_| {
+                        FieldError::invalid(path.clone(), "key must be 20 bytes long")
+                    })?;
+                    files.insert(hash, ScrapeFile::from_bencode(file, path)?);
+                }
+            }
+            None if failure_reason.is_none() => {
+                return Err(FieldError::missing(fields.path_of(b"files")))
+            }
+            None => {}
+        }
+
+        let flags = match fields.dictionary(b"flags")? {
+            Some(flags) => flags.extra(&[]),
+            None => Extra::new(),
+        };
+
+        Ok(ScrapeResponse {
+            failure_reason,
+            files,
+            flags,
+            extra: fields.extra(&[b"failure reason", b"files", b"flags"]),
+        })
+    }
+}
+
+impl ToBencode for ScrapeResponse {
+    fn to_bencode(&self) -> Result<Vec<u8>, EncodingError> {
+        let mut dict = BTreeMap::new();
+        insert_extra(&mut dict, &self.extra)?;
+        if let Some(reason) = &self.failure_reason {
+            dict.insert(&b"failure reason"[..], string_value(reason));
+        }
+        if self.failure_reason.is_none() || !self.files.is_empty() {
+            let mut files = BTreeMap::new();
+            for (hash, file) in &self.files {
+                files.insert(&hash[..], file.to_value()?);
+            }
+            dict.insert(&b"files"[..], BencodeValue::Dictionary(files));
+        }
+        if !self.flags.is_empty() {
+            let mut flags = BTreeMap::new();
+            insert_extra(&mut flags, &self.flags)?;
+            dict.insert(&b"flags"[..], BencodeValue::Dictionary(flags));
+        }
+        encode_to_bytes(&BencodeValue::Dictionary(dict))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip_announce(input: &[u8]) -> AnnounceResponse {
+        let response = AnnounceResponse::from_bytes(input).unwrap();
+        assert_eq!(response.to_bencode().unwrap(), input);
+        response
+    }
+
+    #[test]
+    fn test_compact_announce() {
+        let mut input =
+            b"d8:completei5e10:incompletei3e8:intervali1800e12:min intervali900e5:peers12:"
+                .to_vec();
+        input.extend_from_slice(&[10, 0, 0, 1, 0x1a, 0xe1, 192, 168, 0, 2, 0, 80]);
+        input.extend_from_slice(b"6:peers618:");
+        input.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+        input.extend_from_slice(&[0; 11]);
+        input.extend_from_slice(&[1, 0x1a, 0xe1]);
+        input.push(b'e');
+
+        let response = round_trip_announce(&input);
+        assert!(response.compact && !response.is_failure());
+        assert_eq!(response.interval, Some(1800));
+        assert_eq!(response.min_interval, Some(900));
+        assert_eq!((response.complete, response.incomplete), (Some(5), Some(3)));
+        let addrs = |peers: &[Peer]| -> Vec<String> {
+            peers
+                .iter()
+                .map(|p| p.addr().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(
+            addrs(&response.peers),
+            vec!["10.0.0.1:6881", "192.168.0.2:80"]
+        );
+        assert_eq!(addrs(&response.peers6), vec!["[2001:db8::1]:6881"]);
+    }
+
+    #[test]
+    fn test_dictionary_announce() {
+        let input = b"d8:intervali60e5:peersld2:ip8:10.0.0.17:peer id20:-XX0001-0123456789ab4:porti6881eed2:ip3:::14:porti1eee15:warning message4:slowe";
+        let response = round_trip_announce(input);
+        assert!(!response.compact);
+        assert_eq!(response.warning_message.as_deref(), Some("slow"));
+        assert_eq!(response.peers.len(), 2);
+        assert_eq!(response.peers[0].peer_id, Some(*b"-XX0001-0123456789ab"));
+        assert_eq!(response.peers[1].addr(), Some("[::1]:1".parse().unwrap()));
+        assert_eq!(response.peers[1].peer_id, None);
+
+        let mut input =
+            b"d8:intervali60e5:peersld2:ip11:example.org4:porti80eee6:peers618:".to_vec();
+        input.extend_from_slice(&[0; 15]);
+        input.extend_from_slice(&[1, 0x1a, 0xe1]);
+        input.push(b'e');
+        let response = round_trip_announce(&input);
+        assert_eq!(response.peers[0].host, PeerHost::Name("example.org".into()));
+        assert_eq!(response.peers[0].addr(), None);
+        assert_eq!(
+            response.peers6[0].addr(),
+            Some("[::1]:6881".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_failure_and_building() {
+        let failure = round_trip_announce(b"d14:failure reason9:not founde");
+        assert_eq!(failure, AnnounceResponse::failure("not found"));
+
+        let mut response = AnnounceResponse::new(120);
+        response
+            .peers
+            .push(Peer::from("1.2.3.4:5".parse::<SocketAddr>().unwrap()));
+        assert_eq!(
+            response.to_bencode().unwrap(),
+            b"d8:intervali120e5:peers6:\x01\x02\x03\x04\x00\x05e"
+        );
+
+        response
+            .peers
+            .push(Peer::from("[::1]:5".parse::<SocketAddr>().unwrap()));
+        assert_eq!(
+            response.to_bencode().unwrap_err().to_string(),
+            "Error: peer ::1 cannot be written to compact peers"
+        );
+    }
+
+    #[test]
+    fn test_invalid_announce() {
+        let check = |input: &[u8], expected: &str| {
+            assert_eq!(
+                AnnounceResponse::from_bytes(input).unwrap_err().to_string(),
+                expected
+            );
+        };
+        check(b"de", "interval: missing");
+        check(
+            b"d8:intervali1e5:peers5:abcdee",
+            "peers: length must be a multiple of 6",
+        );
+        check(
+            b"d8:intervali1e5:peersld2:ip0:4:porti1eeee",
+            "peers[0].ip: empty host",
+        );
+        check(
+            b"d8:intervali1e5:peersld2:ip3:::14:porti70000eeee",
+            "peers[0].port: must be a valid port",
+        );
+    }
+
+    #[test]
+    fn test_scrape() {
+        let mut input = b"d5:filesd20:".to_vec();
+        input.extend_from_slice(&[0xaa; 20]);
+        input.extend_from_slice(b"d8:completei5e10:downloadedi50e10:incompletei10e4:name3:fooee5:flagsd20:min_request_intervali600eee");
+
+        let response = ScrapeResponse::from_bytes(&input).unwrap();
+        let file = &response.files[&[0xaa; 20]];
+        assert_eq!(
+            (file.complete, file.downloaded, file.incomplete),
+            (5, 50, 10)
+        );
+        assert_eq!(file.name.as_deref(), Some("foo"));
+        assert_eq!(response.to_bencode().unwrap(), input);
+
+        assert_eq!(
+            ScrapeResponse::from_bytes(b"d5:filesd3:abcdeee")
+                .unwrap_err()
+                .to_string(),
+            "files.abc: key must be 20 bytes long"
+        );
+        assert_eq!(
+            ScrapeResponse::from_bytes(b"de").unwrap_err().to_string(),
+            "files: missing"
+        );
+        let failure = ScrapeResponse::from_bytes(b"d14:failure reason4:nopee").unwrap();
+        assert!(failure.files.is_empty());
+        assert_eq!(failure.to_bencode().unwrap(), b"d14:failure reason4:nopee");
+    }
+}
@@ -0,0 +1,359 @@
+use std::convert::TryInto;
+use std::fmt;
+
+use crate::common::BencodeValue;
+#[cfg(feature = "hash")]
+use crate::dht_item::mutable_target;
+use crate::dht_item::{ItemError, MutableItem, MAX_SALT_LEN, PUBLIC_KEY_LEN};
+use crate::encoder::{encode_to_bytes, EncodingError, ToBencode};
+use crate::encoding::{decode_hex, strip_prefix_ignore_case, to_hex};
+use crate::fields::{as_bytes, as_string, insert_extra, parse_document, Extra, FieldError, Fields};
+use crate::magnet::{decode_component, MagnetError};
+use crate::path::Path;
+use crate::torrent::Info;
+
+/// Value of a BEP 46 mutable item: the info-hash of the current version of
+/// a torrent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TorrentPointer {
+    /// `ih`
+    pub info_hash: [u8; 20],
+    pub extra: Extra,
+}
+
+impl TorrentPointer {
+    pub fn new(info_hash: [u8; 20]) -> Self {
+        TorrentPointer {
+            info_hash,
+            extra: Extra::new(),
+        }
+    }
+
+    pub fn from_bencode(value: &BencodeValue, path: Path) -> Result<TorrentPointer, FieldError> {
+        let fields = Fields::new(value, path)?;
+        let info_hash = fields.require_bytes(b"ih")?.try_into().map_err(|_| {
+            FieldError::invalid(fields.path_of(b"ih"), "info-hash must be 20 bytes")
+        })?;
+        Ok(TorrentPointer {
+            info_hash,
+            extra: fields.extra(&[b"ih"]),
+        })
+    }
+
+    /// Read the pointer carried by a mutable item, without checking its signature
+    pub fn from_item(item: &MutableItem) -> Result<TorrentPointer, FieldError> {
+        TorrentPointer::from_bencode(&item.value(), Path::root())
+    }
+
+    pub fn to_value(&self) -> Result<BencodeValue<'_>, EncodingError> {
+        let mut dict = std::collections::BTreeMap::new();
+        insert_extra(&mut dict, &self.extra)?;
+        dict.insert(&b"ih"[..], BencodeValue::ByteString(&self.info_hash));
+        Ok(BencodeValue::Dictionary(dict))
+    }
+
+    /// Sign the pointer as version `seq` of the item at `key` and `salt`
+    #[cfg(feature = "sign")]
+    pub fn sign(
+        &self,
+        key: &ed25519_dalek::SigningKey,
+        salt: &[u8],
+        seq: i64,
+    ) -> Result<MutableItem, ItemError> {
+        MutableItem::sign(key, salt, seq, &self.to_value()?)
+    }
+}
+
+impl ToBencode for TorrentPointer {
+    fn to_bencode(&self) -> Result<Vec<u8>, EncodingError> {
+        encode_to_bytes(&self.to_value()?)
+    }
+}
+
+/// Sequence number to publish after `current`, starting at 1
+pub fn next_seq(current: Option<i64>) -> Result<i64, ItemError> {
+    match current {
+        None => Ok(1),
+        Some(seq) => seq
+            .checked_add(1)
+            .ok_or_else(|| ItemError::InvalidValue("sequence number overflow".to_string())),
+    }
+}
+
+/// Check that `update` may replace `current`
+///
+/// The update must be for the same public key and salt and have a higher
+/// sequence number; storing the identical item again is also accepted.
+/// Signatures are not checked here.
+pub fn check_update(current: &MutableItem, update: &MutableItem) -> Result<(), ItemError> {
+    if update.public_key != current.public_key || update.salt != current.salt {
+        return Err(ItemError::InvalidValue(
+            "update is for a different public key or salt".to_string(),
+        ));
+    }
+    if update.seq > current.seq || update == current {
+        Ok(())
+    } else {
+        Err(ItemError::StaleSequence {
+            current: current.seq,
+            seq: update.seq,
+        })
+    }
+}
+
+/// A BEP 46 magnet link, `magnet:?xs=urn:btpk:<key>&s=<salt>`, naming the
+/// mutable item to follow rather than a fixed info-hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MutableLink {
+    pub public_key: [u8; PUBLIC_KEY_LEN],
+    /// Empty when the item has no salt
+    pub salt: Vec<u8>,
+}
+
+impl MutableLink {
+    pub fn from_item(item: &MutableItem) -> MutableLink {
+        MutableLink {
+            public_key: item.public_key,
+            salt: item.salt.clone(),
+        }
+    }
+
+    /// Parse the link, ignoring parameters other than `xs` and `s`
+    pub fn parse(uri: &str) -> Result<MutableLink, MagnetError> {
+        let query = strip_prefix_ignore_case(uri, "magnet:?").ok_or(MagnetError::NotMagnet)?;
+
+        let mut public_key = None;
+        let mut salt = Vec::new();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (raw_name, raw_value) = pair.split_once('=').unwrap_or((pair, ""));
+            let name = decode_component(raw_name)?;
+            let value = decode_component(raw_value)?;
+            let invalid = || MagnetError::InvalidParameter {
+                name: name.clone(),
+                value: value.clone(),
+            };
+            match name.as_str() {
+                "xs" => {
+                    let key = strip_prefix_ignore_case(&value, "urn:btpk:").ok_or_else(invalid)?;
+                    let key = decode_hex(key).and_then(|key| key.try_into().ok());
+                    public_key = Some(key.ok_or_else(invalid)?);
+                }
+                "s" => {
+                    salt = decode_hex(&value)
+                        .filter(|salt| salt.len() <= MAX_SALT_LEN)
+                        .ok_or_else(invalid)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(MutableLink {
+            public_key: public_key.ok_or(MagnetError::MissingPublicKey)?,
+            salt,
+        })
+    }
+
+    /// DHT target to `get` the item from
+    #[cfg(feature = "hash")]
+    pub fn target(&self) -> [u8; 20] {
+        mutable_target(&self.public_key, &self.salt)
+    }
+}
+
+impl fmt::Display for MutableLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "magnet:?xs=urn:btpk:{}", to_hex(&self.public_key))?;
+        if !self.salt.is_empty() {
+            write!(f, "&s={}", to_hex(&self.salt))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for MutableLink {
+    type Err = MagnetError;
+
+    fn from_str(uri: &str) -> Result<MutableLink, MagnetError> {
+        MutableLink::parse(uri)
+    }
+}
+
+/// The BEP 39 keys of an info dictionary, which tie a torrent to the feed it
+/// is updated from
+///
+/// They live in `info`, so changing them changes the info-hash.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeedInfo {
+    /// `update-url`, polled for newer versions of the torrent
+    pub update_url: Option<String>,
+    /// `originator`, identifying the publisher of every version
+    pub originator: Option<Vec<u8>>,
+}
+
+impl FeedInfo {
+    /// Read both keys from the unmodelled entries of an info dictionary
+    pub fn from_info(info: &Info) -> Result<FeedInfo, FieldError> {
+        let path = Path::root().key(b"info");
+        let update_url = match info.extra.get(&b"update-url"[..]) {
+            Some(raw) => Some(as_string(&parse_document(raw)?, &path.key(b"update-url"))?),
+            None => None,
+        };
+        let originator = match info.extra.get(&b"originator"[..]) {
+            Some(raw) => Some(as_bytes(&parse_document(raw)?, &path.key(b"originator"))?.to_vec()),
+            None => None,
+        };
+        Ok(FeedInfo {
+            update_url,
+            originator,
+        })
+    }
+
+    /// Store both keys in `info.extra`, removing the ones that are unset
+    pub fn apply_to(&self, info: &mut Info) -> Result<(), EncodingError> {
+        let update_url = self.update_url.as_ref().map(String::as_bytes);
+        for (key, value) in [
+            (&b"update-url"[..], update_url),
+            (b"originator", self.originator.as_deref()),
+        ] {
+            match value {
+                Some(value) => {
+                    let encoded = encode_to_bytes(&BencodeValue::ByteString(value))?;
+                    info.extra.insert(key.to_vec(), encoded);
+                }
+                None => {
+                    info.extra.remove(key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a torrent with the fields `update` may supersede this one
+    ///
+    /// Both must name the same originator; the feed itself is free to move.
+    pub fn accepts(&self, update: &FeedInfo) -> bool {
+        self.originator.is_some() && self.originator == update.originator
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::Metainfo;
+
+    const INFO_HASH: [u8; 20] = [0xab; 20];
+
+    #[test]
+    fn test_pointer() {
+        let pointer = TorrentPointer::new(INFO_HASH);
+        let mut expected = b"d2:ih20:".to_vec();
+        expected.extend_from_slice(&INFO_HASH);
+        expected.push(b'e');
+        assert_eq!(pointer.to_bencode().unwrap(), expected);
+
+        let value = parse_document(&expected).unwrap();
+        assert_eq!(
+            TorrentPointer::from_bencode(&value, Path::root()).unwrap(),
+            pointer
+        );
+
+        let short = parse_document(b"d2:ih3:abc1:xi1ee").unwrap();
+        let error = TorrentPointer::from_bencode(&short, Path::root()).unwrap_err();
+        assert_eq!(error.to_string(), "ih: info-hash must be 20 bytes");
+    }
+
+    #[test]
+    fn test_sequence_numbers() {
+        assert_eq!(next_seq(None).unwrap(), 1);
+        assert_eq!(next_seq(Some(41)).unwrap(), 42);
+        assert!(next_seq(Some(i64::MAX)).is_err());
+
+        let item = |seq, encoded: &[u8]| {
+            MutableItem::from_parts([1; 32], b"s".to_vec(), seq, encoded.to_vec(), [0; 64]).unwrap()
+        };
+        let current = item(5, b"i1e");
+        check_update(&current, &item(6, b"i2e")).unwrap();
+        check_update(&current, &current.clone()).unwrap();
+
+        let error = check_update(&current, &item(5, b"i2e")).unwrap_err();
+        assert_eq!(error.to_string(), "Sequence number 5 is not newer than 5");
+        assert_eq!(error.krpc_code(), 302);
+        assert!(check_update(&current, &item(4, b"i2e")).is_err());
+
+        let mut other_salt = item(6, b"i2e");
+        other_salt.salt = b"t".to_vec();
+        assert!(matches!(
+            check_update(&current, &other_salt),
+            Err(ItemError::InvalidValue(_))
+        ));
+    }
+
+    #[test]
+    fn test_mutable_link() {
+        let link = MutableLink {
+            public_key: [0x0f; 32],
+            salt: b"v1".to_vec(),
+        };
+        let uri = link.to_string();
+        assert_eq!(
+            uri,
+            format!("magnet:?xs=urn:btpk:{}&s=7631", "0f".repeat(32))
+        );
+        assert_eq!(uri.parse::<MutableLink>().unwrap(), link);
+
+        let unsalted = format!("MAGNET:?dn=x&xs=urn:btpk:{}", "0F".repeat(32));
+        assert!(MutableLink::parse(&unsalted).unwrap().salt.is_empty());
+
+        assert_eq!(
+            MutableLink::parse("magnet:?dn=x"),
+            Err(MagnetError::MissingPublicKey)
+        );
+        assert!(matches!(
+            MutableLink::parse("magnet:?xs=urn:btpk:0f0f"),
+            Err(MagnetError::InvalidParameter { .. })
+        ));
+    }
+
+    #[test]
+    fn test_feed_info() {
+        let input = b"d4:infod6:lengthi1e4:name1:x10:originator3:pub12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaa10:update-url17:http://f.example/ee";
+        let mut metainfo = Metainfo::from_bytes(input).unwrap();
+        let feed = FeedInfo::from_info(&metainfo.info).unwrap();
+        assert_eq!(feed.update_url.as_deref(), Some("http://f.example/"));
+        assert_eq!(feed.originator.as_deref(), Some(&b"pub"[..]));
+
+        let moved = FeedInfo {
+            update_url: None,
+            ..feed.clone()
+        };
+        assert!(feed.accepts(&moved));
+        assert!(!feed.accepts(&FeedInfo::default()));
+        assert!(!FeedInfo::default().accepts(&FeedInfo::default()));
+
+        moved.apply_to(&mut metainfo.info).unwrap();
+        assert_eq!(FeedInfo::from_info(&metainfo.info).unwrap(), moved);
+        assert!(!metainfo.info.extra.contains_key(&b"update-url"[..]));
+    }
+
+    #[cfg(feature = "sign")]
+    #[test]
+    fn test_publish_and_follow() {
+        let key = ed25519_dalek::SigningKey::from_bytes(&[5; 32]);
+        let first = TorrentPointer::new(INFO_HASH)
+            .sign(&key, b"", next_seq(None).unwrap())
+            .unwrap();
+        first.verify().unwrap();
+        assert_eq!(
+            TorrentPointer::from_item(&first).unwrap().info_hash,
+            INFO_HASH
+        );
+
+        let second = TorrentPointer::new([0xcd; 20])
+            .sign(&key, b"", next_seq(Some(first.seq)).unwrap())
+            .unwrap();
+        check_update(&first, &second).unwrap();
+        assert!(check_update(&second, &first).is_err());
+
+        let link = MutableLink::from_item(&second);
+        assert_eq!(link.target(), second.target());
+    }
+}
@@ -0,0 +1,421 @@
+#[cfg(unix)]
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path as FsPath, PathBuf};
+
+use crate::infohash::sha1;
+use crate::torrent::{FileLayout, Info};
+
+/// Error type for checking local data against a torrent
+#[derive(Debug)]
+pub enum VerifyError {
+    IoError(io::Error),
+    /// The torrent has no v1 piece hashes to check against
+    NoPieceHashes,
+}
+
+impl From<io::Error> for VerifyError {
+    fn from(error: io::Error) -> Self {
+        VerifyError::IoError(error)
+    }
+}
+
+impl std::fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyError::IoError(e) => write!(f, "IO error: {}", e),
+            VerifyError::NoPieceHashes => write!(f, "Torrent has no v1 piece hashes"),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// One bit per piece, most significant bit first, as in the BEP 3 bitfield message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bitfield {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl Bitfield {
+    pub fn new(len: usize) -> Self {
+        Bitfield {
+            bytes: vec![0; len.div_ceil(8)],
+            len,
+        }
+    }
+
+    /// Wrap a bitfield message payload, ignoring the spare bits of the last byte
+    pub fn from_bytes(bytes: &[u8], len: usize) -> Option<Self> {
+        if bytes.len() != len.div_ceil(8) {
+            return None;
+        }
+        let mut bitfield = Bitfield {
+            bytes: bytes.to_vec(),
+            len,
+        };
+        if len % 8 != 0 {
+            *bitfield.bytes.last_mut()? &= 0xff << (8 - len % 8);
+        }
+        Some(bitfield)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> bool {
+        index < self.len && self.bytes[index / 8] & (0x80 >> (index % 8)) != 0
+    }
+
+    pub fn set(&mut self, index: usize, value: bool) {
+        if index >= self.len {
+            return;
+        }
+        if value {
+            self.bytes[index / 8] |= 0x80 >> (index % 8);
+        } else {
+            self.bytes[index / 8] &= !(0x80 >> (index % 8));
+        }
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.bytes.iter().map(|b| b.count_ones() as usize).sum()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.count_ones() == self.len
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// How much of one file is backed by verified pieces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStatus {
+    pub path: Vec<String>,
+    pub length: u64,
+    /// Whether the file exists on disk
+    pub present: bool,
+    /// Pieces overlapping the file, and how many of them passed
+    pub pieces: usize,
+    pub pieces_complete: usize,
+    /// Bytes of the file inside passing pieces
+    pub bytes_complete: u64,
+}
+
+impl FileStatus {
+    pub fn is_complete(&self) -> bool {
+        self.bytes_complete == self.length
+    }
+}
+
+/// Result of checking a download directory against a torrent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    pub pieces: Bitfield,
+    /// Every file except BEP 47 padding files, in torrent order
+    pub files: Vec<FileStatus>,
+}
+
+impl Verification {
+    pub fn is_complete(&self) -> bool {
+        self.pieces.is_full()
+    }
+
+    pub fn bytes_complete(&self) -> u64 {
+        self.files.iter().map(|f| f.bytes_complete).sum()
+    }
+}
+
+// A file of the v1 piece stream, located on disk unless it is padding
+struct StreamFile {
+    path: Vec<String>,
+    disk_path: Option<PathBuf>,
+    offset: u64,
+    length: u64,
+}
+
+fn stream_files(info: &Info, dir: &FsPath) -> Result<Vec<StreamFile>, VerifyError> {
+    let mut offset = 0;
+    let mut files = Vec::new();
+    match &info.layout {
+        Some(FileLayout::Single { length }) => files.push(StreamFile {
+            path: vec![info.name_lossy().into_owned()],
+            disk_path: Some(dir.join(name_path(&info.name))),
+            offset,
+            length: *length,
+        }),
+        Some(FileLayout::Multiple { files: entries }) => {
+            for entry in entries {
+                let disk_path = if entry.is_padding() {
+                    None
+                } else {
+                    Some(
+                        entry
+                            .path
+                            .iter()
+                            .fold(dir.join(name_path(&info.name)), |p, c| p.join(c)),
+                    )
+                };
+                files.push(StreamFile {
+                    path: entry.path.clone(),
+                    disk_path,
+                    offset,
+                    length: entry.length,
+                });
+                offset += entry.length;
+            }
+        }
+        None => return Err(VerifyError::NoPieceHashes),
+    }
+    Ok(files)
+}
+
+// The torrent name as a path component; on Unix its bytes are used as they are
+#[cfg(unix)]
+fn name_path(name: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(OsStr::from_bytes(name))
+}
+
+#[cfg(not(unix))]
+fn name_path(name: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(name).into_owned())
+}
+
+// Fill `buf` from a file at `offset`; returns false if the file is missing or too short
+fn read_file_range(path: &FsPath, offset: u64, buf: &mut [u8]) -> io::Result<bool> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    file.seek(SeekFrom::Start(offset))?;
+    match file.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Check the data under `dir` against the torrent's v1 piece hashes
+///
+/// Files are looked up where a client would save them: `dir/name` for a
+/// single-file torrent and `dir/name/path...` otherwise. Missing or short
+/// files only fail the pieces they overlap; padding files are taken as zeros
+/// without touching the disk.
+pub fn verify(info: &Info, dir: &FsPath) -> Result<Verification, VerifyError> {
+    if info.pieces.is_none() {
+        return Err(VerifyError::NoPieceHashes);
+    }
+    let files = stream_files(info, dir)?;
+    let piece_length = info.piece_length;
+    let mut pieces = Bitfield::new(info.piece_count());
+    let mut buf = Vec::new();
+
+    for index in 0..info.piece_count() {
+        let start = index as u64 * piece_length;
+        let size = info.piece_size(index).unwrap_or(0);
+        buf.clear();
+        buf.resize(size as usize, 0);
+
+        let mut readable = true;
+        for file in overlapping(&files, start, size) {
+            let from = start.max(file.offset);
+            let to = (start + size).min(file.offset + file.length);
+            let target = &mut buf[(from - start) as usize..(to - start) as usize];
+            if let Some(path) = &file.disk_path {
+                if !read_file_range(path, from - file.offset, target)? {
+                    readable = false;
+                    break;
+                }
+            }
+        }
+
+        let valid = readable && info.piece_hash(index) == Some(&sha1(&buf)[..]);
+        pieces.set(index, valid);
+    }
+
+    let statuses = files
+        .iter()
+        .filter(|file| file.disk_path.is_some())
+        .map(|file| file_status(file, &pieces, piece_length))
+        .collect();
+
+    Ok(Verification {
+        pieces,
+        files: statuses,
+    })
+}
+
+fn overlapping(files: &[StreamFile], start: u64, size: u64) -> impl Iterator<Item = &StreamFile> {
+    files
+        .iter()
+        .skip_while(move |file| file.offset + file.length <= start)
+        .take_while(move |file| file.offset < start + size)
+        .filter(|file| file.length > 0)
+}
+
+fn file_status(file: &StreamFile, pieces: &Bitfield, piece_length: u64) -> FileStatus {
+    let present = file.disk_path.as_deref().is_some_and(FsPath::is_file);
+    let mut status = FileStatus {
+        path: file.path.clone(),
+        length: file.length,
+        present,
+        pieces: 0,
+        pieces_complete: 0,
+        bytes_complete: 0,
+    };
+    if file.length == 0 {
+        return status;
+    }
+
+    let first = file.offset / piece_length;
+    let last = (file.offset + file.length - 1) / piece_length;
+    for index in first..=last {
+        status.pieces += 1;
+        if pieces.get(index as usize) {
+            let piece_start = index * piece_length;
+            let from = piece_start.max(file.offset);
+            let to = (piece_start + piece_length).min(file.offset + file.length);
+            status.pieces_complete += 1;
+            status.bytes_complete += to - from;
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create::{TorrentBuilder, TorrentVersion};
+    use std::fs;
+
+    const PIECE: usize = 16 * 1024;
+
+    fn data(length: usize, seed: u8) -> Vec<u8> {
+        (0..length)
+            .map(|i| (i as u8).wrapping_mul(13).wrapping_add(seed))
+            .collect()
+    }
+
+    fn setup(version: TorrentVersion) -> (tempfile::TempDir, Info) {
+        let dir = tempfile::tempdir().unwrap();
+        let content = dir.path().join("content");
+        fs::create_dir(&content).unwrap();
+        fs::write(content.join("a"), data(PIECE + 10, 1)).unwrap();
+        fs::write(content.join("b"), data(PIECE * 2, 2)).unwrap();
+        fs::write(content.join("c"), data(100, 3)).unwrap();
+
+        let metainfo = TorrentBuilder::new(&content)
+            .version(version)
+            .build()
+            .unwrap();
+        (dir, metainfo.info)
+    }
+
+    #[test]
+    fn test_bitfield() {
+        let mut bitfield = Bitfield::new(10);
+        bitfield.set(0, true);
+        bitfield.set(9, true);
+        assert_eq!(bitfield.as_bytes(), &[0x80, 0x40]);
+        assert_eq!(bitfield.count_ones(), 2);
+        assert!(bitfield.get(9) && !bitfield.get(8) && !bitfield.get(10));
+        bitfield.set(0, false);
+        assert_eq!(bitfield.count_ones(), 1);
+
+        let parsed = Bitfield::from_bytes(&[0xff, 0xff], 10).unwrap();
+        assert_eq!(parsed.as_bytes(), &[0xff, 0xc0]);
+        assert!(parsed.is_full());
+        assert!(Bitfield::from_bytes(&[0xff], 10).is_none());
+    }
+
+    #[test]
+    fn test_complete_data() {
+        let (dir, info) = setup(TorrentVersion::V1);
+        let result = verify(&info, dir.path()).unwrap();
+        assert!(result.is_complete());
+        assert_eq!(result.pieces.len(), 4);
+        assert_eq!(result.bytes_complete(), info.total_length());
+        assert!(result.files.iter().all(|f| f.present && f.is_complete()));
+    }
+
+    #[test]
+    fn test_pieces_across_file_boundaries() {
+        let (dir, info) = setup(TorrentVersion::V1);
+        let content = dir.path().join("content");
+
+        // Corrupting the end of `a` breaks the piece it shares with `b`
+        let mut a = data(PIECE + 10, 1);
+        a[PIECE + 5] ^= 1;
+        fs::write(content.join("a"), a).unwrap();
+        fs::remove_file(content.join("c")).unwrap();
+
+        let result = verify(&info, dir.path()).unwrap();
+        let bits: Vec<bool> = (0..4).map(|i| result.pieces.get(i)).collect();
+        assert_eq!(bits, vec![true, false, true, false]);
+
+        let a = &result.files[0];
+        assert_eq!(
+            (a.pieces, a.pieces_complete, a.bytes_complete),
+            (2, 1, PIECE as u64)
+        );
+        let b = &result.files[1];
+        assert_eq!((b.pieces, b.pieces_complete), (3, 1));
+        assert_eq!(b.bytes_complete, PIECE as u64);
+        let c = &result.files[2];
+        assert!(!c.present);
+        assert_eq!((c.pieces, c.pieces_complete, c.bytes_complete), (1, 0, 0));
+    }
+
+    #[test]
+    fn test_padding_files() {
+        let (dir, info) = setup(TorrentVersion::Hybrid);
+        let result = verify(&info, dir.path()).unwrap();
+        assert!(result.is_complete());
+        assert_eq!(result.files.len(), 3);
+        assert_eq!(result.pieces.len(), 5);
+
+        // A short file fails its own pieces but not the next file's, thanks to padding
+        fs::write(dir.path().join("content/a"), data(PIECE, 1)).unwrap();
+        let result = verify(&info, dir.path()).unwrap();
+        let bits: Vec<bool> = (0..5).map(|i| result.pieces.get(i)).collect();
+        assert_eq!(bits, vec![true, false, true, true, true]);
+    }
+
+    #[test]
+    fn test_single_file_and_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("one"), data(PIECE * 2, 4)).unwrap();
+        let info = TorrentBuilder::new(dir.path().join("one"))
+            .build()
+            .unwrap()
+            .info;
+        assert!(verify(&info, dir.path()).unwrap().is_complete());
+
+        let empty = tempfile::tempdir().unwrap();
+        let result = verify(&info, empty.path()).unwrap();
+        assert_eq!(result.pieces.count_ones(), 0);
+        assert!(!result.files[0].present);
+
+        let v2 = TorrentBuilder::new(dir.path().join("one"))
+            .version(TorrentVersion::V2)
+            .build()
+            .unwrap()
+            .info;
+        assert!(matches!(
+            verify(&v2, dir.path()),
+            Err(VerifyError::NoPieceHashes)
+        ));
+    }
+}
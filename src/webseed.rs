@@ -0,0 +1,392 @@
+use std::fmt;
+use std::io;
+use std::ops::Range;
+
+use crate::common::BencodeValue;
+use crate::encoder::{encode_to_bytes, EncodingError};
+use crate::encoding::percent_encode;
+use crate::fields::{as_list, as_string, parse_document, FieldError};
+#[cfg(feature = "hash")]
+use crate::infohash::sha1;
+use crate::path::Path;
+use crate::torrent::{FileLayout, Info, Metainfo};
+
+/// The web seeds of a torrent: BEP 19 `url-list` and BEP 17 `httpseeds`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WebSeeds {
+    /// Plain HTTP or FTP servers holding the files themselves
+    pub url_list: Vec<String>,
+    /// Servers speaking the BEP 17 piece protocol
+    pub http_seeds: Vec<String>,
+}
+
+impl WebSeeds {
+    /// Read both keys from the unmodelled entries of a metainfo file
+    ///
+    /// `url-list` may be a single URL or a list, and empty URLs, which some
+    /// torrent creators write when no seed is configured, are skipped.
+    pub fn from_metainfo(metainfo: &Metainfo) -> Result<WebSeeds, FieldError> {
+        let read = |key: &[u8]| -> Result<Vec<String>, FieldError> {
+            let raw = match metainfo.extra.get(key) {
+                Some(raw) => raw,
+                None => return Ok(Vec::new()),
+            };
+            let path = Path::root().key(key);
+            let value = parse_document(raw)?;
+            let urls = match &value {
+                BencodeValue::ByteString(_) => vec![as_string(&value, &path)?],
+                _ => as_list(&value, &path)?
+                    .iter()
+                    .enumerate()
+                    .map(|(i, url)| as_string(url, &path.index(i)))
+                    .collect::<Result<_, _>>()?,
+            };
+            Ok(urls.into_iter().filter(|url| !url.is_empty()).collect())
+        };
+        Ok(WebSeeds {
+            url_list: read(b"url-list")?,
+            http_seeds: read(b"httpseeds")?,
+        })
+    }
+
+    /// Store both keys in `metainfo.extra` as lists, removing them when empty
+    pub fn apply_to(&self, metainfo: &mut Metainfo) -> Result<(), EncodingError> {
+        for (key, urls) in [
+            (&b"url-list"[..], &self.url_list),
+            (b"httpseeds", &self.http_seeds),
+        ] {
+            if urls.is_empty() {
+                metainfo.extra.remove(key);
+                continue;
+            }
+            let list = urls
+                .iter()
+                .map(|url| BencodeValue::ByteString(url.as_bytes()))
+                .collect();
+            metainfo
+                .extra
+                .insert(key.to_vec(), encode_to_bytes(&BencodeValue::List(list))?);
+        }
+        Ok(())
+    }
+}
+
+/// One HTTP request for part of a file, as needed to download a piece
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeRequest {
+    pub url: String,
+    /// Index into `files` of a multi-file torrent, 0 for a single file
+    pub file_index: usize,
+    /// Byte range within the file
+    pub range: Range<u64>,
+    /// Where the response goes within the piece
+    pub piece_offset: u64,
+}
+
+impl RangeRequest {
+    /// Value of the `Range` header, whose end is inclusive
+    pub fn header(&self) -> String {
+        format!("bytes={}-{}", self.range.start, self.range.end - 1)
+    }
+}
+
+/// URL of a file on a BEP 19 web seed
+///
+/// For a single-file torrent a base URL ending in `/` is a directory holding
+/// the file under the torrent name; otherwise it is the file itself. For a
+/// multi-file torrent the torrent name and the file path are appended.
+pub fn file_url(base: &str, info: &Info, path: &[String]) -> String {
+    let multi_file = info.is_multi_file();
+    if !multi_file && !base.ends_with('/') {
+        return base.to_string();
+    }
+    let mut url = base.to_string();
+    if !url.ends_with('/') {
+        url.push('/');
+    }
+    url.push_str(&percent_encode(&info.name));
+    for component in path.iter().filter(|_| multi_file) {
+        url.push('/');
+        url.push_str(&percent_encode(component.as_bytes()));
+    }
+    url
+}
+
+/// The range requests that make up `piece` on the web seed at `base`
+///
+/// Padding files and empty files need no request, so the requests may not
+/// cover the whole piece; the gaps are zeros. Returns `None` when the piece
+/// index is out of range or the torrent has no v1 file layout.
+pub fn piece_requests(info: &Info, base: &str, piece: usize) -> Option<Vec<RangeRequest>> {
+    let size = info.piece_size(piece)?;
+    let start = piece as u64 * info.piece_length;
+    let end = start + size;
+
+    let files: Vec<(u64, Vec<String>, bool)> = match info.layout.as_ref()? {
+        FileLayout::Single { length } => vec![(*length, Vec::new(), false)],
+        FileLayout::Multiple { files } => files
+            .iter()
+            .map(|file| (file.length, file.path.clone(), file.is_padding()))
+            .collect(),
+    };
+
+    let mut requests = Vec::new();
+    let mut offset = 0;
+    for (file_index, (length, path, padding)) in files.into_iter().enumerate() {
+        let file_start = offset;
+        offset += length;
+        if padding || offset <= start || file_start >= end {
+            continue;
+        }
+        let from = start.max(file_start);
+        let to = end.min(offset);
+        requests.push(RangeRequest {
+            url: file_url(base, info, &path),
+            file_index,
+            range: from - file_start..to - file_start,
+            piece_offset: from - start,
+        });
+    }
+    Some(requests)
+}
+
+/// Error type for downloading pieces from a web seed
+#[derive(Debug)]
+pub enum WebSeedError {
+    /// The piece index is out of range or the torrent has no v1 layout
+    NoSuchPiece(usize),
+    Io(io::Error),
+    /// A response was not the length of the requested range
+    WrongLength {
+        url: String,
+        expected: u64,
+        actual: usize,
+    },
+    HashMismatch(usize),
+}
+
+impl From<io::Error> for WebSeedError {
+    fn from(error: io::Error) -> Self {
+        WebSeedError::Io(error)
+    }
+}
+
+impl fmt::Display for WebSeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebSeedError::NoSuchPiece(piece) => write!(f, "No piece {}", piece),
+            WebSeedError::Io(e) => write!(f, "I/O error: {}", e),
+            WebSeedError::WrongLength {
+                url,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} returned {} bytes, expected {}",
+                url, actual, expected
+            ),
+            WebSeedError::HashMismatch(piece) => write!(f, "Piece {} failed its hash check", piece),
+        }
+    }
+}
+
+impl std::error::Error for WebSeedError {}
+
+/// Download `piece` from a BEP 19 web seed
+///
+/// `fetch` performs a single range request, so any HTTP client, or an
+/// in-memory stand-in in tests, can be plugged in. With the `hash` feature
+/// the assembled piece is checked against its SHA-1 hash.
+pub fn fetch_piece<F>(
+    info: &Info,
+    base: &str,
+    piece: usize,
+    mut fetch: F,
+) -> Result<Vec<u8>, WebSeedError>
+where
+    F: FnMut(&RangeRequest) -> io::Result<Vec<u8>>,
+{
+    let requests = piece_requests(info, base, piece).ok_or(WebSeedError::NoSuchPiece(piece))?;
+    let size = info
+        .piece_size(piece)
+        .ok_or(WebSeedError::NoSuchPiece(piece))?;
+    let mut buf = vec![0; size as usize];
+    for request in &requests {
+        let data = fetch(request)?;
+        let expected = request.range.end - request.range.start;
+        if data.len() as u64 != expected {
+            return Err(WebSeedError::WrongLength {
+                url: request.url.clone(),
+                expected,
+                actual: data.len(),
+            });
+        }
+        let at = request.piece_offset as usize;
+        buf[at..at + data.len()].copy_from_slice(&data);
+    }
+
+    #[cfg(feature = "hash")]
+    if info.piece_hash(piece) != Some(&sha1(&buf)[..]) {
+        return Err(WebSeedError::HashMismatch(piece));
+    }
+    Ok(buf)
+}
+
+/// URL of a BEP 17 request for a whole piece
+pub fn http_seed_url(base: &str, info_hash: &[u8; 20], piece: usize) -> String {
+    let separator = if base.contains('?') { '&' } else { '?' };
+    format!(
+        "{}{}info_hash={}&piece={}",
+        base,
+        separator,
+        percent_encode(info_hash),
+        piece
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::FileEntry;
+    use std::collections::BTreeMap;
+
+    fn info(layout: FileLayout, piece_length: u64, content: &[u8]) -> Info {
+        #[cfg(feature = "hash")]
+        let pieces = content
+            .chunks(piece_length as usize)
+            .flat_map(|chunk| sha1(chunk).to_vec())
+            .collect();
+        #[cfg(not(feature = "hash"))]
+        let pieces = vec![0; content.len().div_ceil(piece_length as usize) * 20];
+        Info {
+            name: b"my dir".to_vec(),
+            piece_length,
+            pieces: Some(pieces),
+            layout: Some(layout),
+            meta_version: None,
+            file_tree: None,
+            private: None,
+            source: None,
+            extra: BTreeMap::new(),
+        }
+    }
+
+    fn entry(length: u64, path: &str, attr: Option<&str>) -> FileEntry {
+        FileEntry {
+            length,
+            path: path.split('/').map(String::from).collect(),
+            attr: attr.map(String::from),
+            extra: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_url_list_forms() {
+        let mut metainfo = Metainfo::from_bytes(
+            b"d4:infod6:lengthi1e4:name1:x12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaae8:url-list9:http://w/e",
+        )
+        .unwrap();
+        let seeds = WebSeeds::from_metainfo(&metainfo).unwrap();
+        assert_eq!(seeds.url_list, ["http://w/"]);
+        assert!(seeds.http_seeds.is_empty());
+
+        let seeds = WebSeeds {
+            url_list: vec![],
+            http_seeds: vec!["http://h/seed".to_string()],
+        };
+        seeds.apply_to(&mut metainfo).unwrap();
+        assert!(!metainfo.extra.contains_key(b"url-list".as_slice()));
+        assert_eq!(
+            metainfo.extra[b"httpseeds".as_slice()],
+            b"l13:http://h/seede".to_vec()
+        );
+        assert_eq!(WebSeeds::from_metainfo(&metainfo).unwrap(), seeds);
+
+        metainfo.extra.insert(b"url-list".to_vec(), b"0:".to_vec());
+        assert!(WebSeeds::from_metainfo(&metainfo)
+            .unwrap()
+            .url_list
+            .is_empty());
+        metainfo.extra.insert(b"url-list".to_vec(), b"i1e".to_vec());
+        assert_eq!(
+            WebSeeds::from_metainfo(&metainfo).unwrap_err().to_string(),
+            "url-list: expected list"
+        );
+    }
+
+    #[test]
+    fn test_piece_requests_single_file() {
+        let info = info(FileLayout::Single { length: 10 }, 4, &[0; 10]);
+        let requests = piece_requests(&info, "http://w/file.iso", 2).unwrap();
+        assert_eq!(
+            requests,
+            [RangeRequest {
+                url: "http://w/file.iso".to_string(),
+                file_index: 0,
+                range: 8..10,
+                piece_offset: 0,
+            }]
+        );
+        assert_eq!(requests[0].header(), "bytes=8-9");
+        assert_eq!(
+            piece_requests(&info, "http://w/", 0).unwrap()[0].url,
+            "http://w/my%20dir"
+        );
+        assert!(piece_requests(&info, "http://w/", 3).is_none());
+    }
+
+    #[test]
+    fn test_fetch_multi_file_piece() {
+        let files = vec![
+            entry(3, "a", None),
+            entry(1, ".pad/1", Some("p")),
+            entry(0, "empty", None),
+            entry(6, "sub/b c", None),
+        ];
+        let content = b"AAA\0BBBBBB";
+        let info = info(FileLayout::Multiple { files }, 4, content);
+
+        let requests = piece_requests(&info, "http://w", 0).unwrap();
+        let urls: Vec<(&str, Range<u64>, u64)> = requests
+            .iter()
+            .map(|r| (r.url.as_str(), r.range.clone(), r.piece_offset))
+            .collect();
+        assert_eq!(urls, [("http://w/my%20dir/a", 0..3, 0)]);
+
+        // Stand-in for an HTTP server holding the files
+        let server = |request: &RangeRequest| -> io::Result<Vec<u8>> {
+            let file: &[u8] = match request.url.as_str() {
+                "http://w/my%20dir/a" => b"AAA",
+                "http://w/my%20dir/sub/b%20c" => b"BBBBBB",
+                _ => return Err(io::Error::new(io::ErrorKind::NotFound, "404")),
+            };
+            Ok(file[request.range.start as usize..request.range.end as usize].to_vec())
+        };
+        let mut pieces = Vec::new();
+        for piece in 0..3 {
+            pieces.extend(fetch_piece(&info, "http://w", piece, server).unwrap());
+        }
+        assert_eq!(pieces, content.to_vec());
+
+        let error = fetch_piece(&info, "http://w", 1, |_| Ok(vec![b'B'; 3])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "http://w/my%20dir/sub/b%20c returned 3 bytes, expected 4"
+        );
+        #[cfg(feature = "hash")]
+        assert!(matches!(
+            fetch_piece(&info, "http://w", 2, |_| Ok(vec![b'X'; 2])),
+            Err(WebSeedError::HashMismatch(2))
+        ));
+    }
+
+    #[test]
+    fn test_http_seed_url() {
+        let mut hash = [b'a'; 20];
+        hash[0] = 0xff;
+        assert_eq!(
+            http_seed_url("http://h/seed?id=1", &hash, 7),
+            "http://h/seed?id=1&info_hash=%FFaaaaaaaaaaaaaaaaaaa&piece=7"
+        );
+    }
+}